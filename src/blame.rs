@@ -0,0 +1,71 @@
+//! blame
+//!
+//! Annotates each line of a swinstalled file, as of a datetime, with the install
+//! which introduced it. The annotation is built by walking the installed stack
+//! entries in order and diffing each backup against the one before it.
+use chrono::naive::NaiveDateTime;
+use diff::{diff_lines, DiffOp};
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile, SwinstallLayout};
+use stack_history_parser::read_stack_history;
+use std::fs;
+use std::path::Path;
+
+/// A line of the resolved file along with the install which introduced it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BlameLine {
+    pub version: FileVersion,
+    pub line: String,
+}
+
+/// Blame each line of the swinstalled `file` as of `datetime`.
+pub fn blame<P: AsRef<Path>>(file: P, datetime: &NaiveDateTime) -> Result<Vec<BlameLine>, BBError> {
+    blame_with_layout(file, datetime, &SwinstallLayout::default())
+}
+
+/// Blame each line of the swinstalled `file` as of `datetime`, locating its
+/// history using `layout`.
+pub fn blame_with_layout<P: AsRef<Path>>(
+    file: P,
+    datetime: &NaiveDateTime,
+    layout: &dyn BakLayout,
+) -> Result<Vec<BlameLine>, BBError> {
+    let file = file.as_ref();
+    let target = SwinstalledFile::new(file, layout)?;
+    let history = read_stack_history(&target.stack)?;
+    let resolved = history.entry_on(datetime)
+                        .ok_or(BBError::NoVersionFound(format!("{} had no installed version on or before {}", file.display(), datetime)))?;
+
+    let mut blamed: Vec<BlameLine> = Vec::new();
+    for entry in history.installed() {
+        let contents = read_backup_lines(&target, &entry.version)?;
+        let mut next = Vec::with_capacity(contents.len());
+        {
+            let previous = blamed.iter().map(|b| b.line.as_str()).collect::<Vec<_>>();
+            let current = contents.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+            for op in diff_lines(&previous, &current) {
+                match op {
+                    DiffOp::Equal(old, _) => next.push(blamed[old].clone()),
+                    DiffOp::Insert(new) => next.push(BlameLine {
+                        version: entry.version.clone(),
+                        line: contents[new].clone(),
+                    }),
+                    DiffOp::Delete(_) => {}
+                }
+            }
+        }
+        blamed = next;
+        if entry == resolved {
+            break;
+        }
+    }
+    Ok(blamed)
+}
+
+fn read_backup_lines(target: &SwinstalledFile, version: &FileVersion) -> Result<Vec<String>, BBError> {
+    let backup = target.backup_path(version)?;
+    let bytes = fs::read(&backup)
+                .map_err(|e| BBError::IoError(format!("unable to read {}: {}", backup.display(), e)))?;
+    Ok(String::from_utf8_lossy(&bytes).lines().map(|l| l.to_string()).collect())
+}
@@ -0,0 +1,101 @@
+//! changes
+//!
+//! A chronological feed of the installs and rollbacks beneath a root within a
+//! window of time.
+//!
+//! Installs are dated by their stack entries. Stacks do not record when a
+//! rollback happened, so a rollback, detected as entries after the current
+//! one, is dated by the stack file's modification time: a rollback is the last
+//! change made to a stack which still has rolled back entries. Stacks last
+//! modified before the window cannot hold changes within it, so are not read.
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile};
+use index::find_stacks;
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+use timezone::Zone;
+
+/// What happened to a file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ChangeKind {
+    /// The version was installed
+    Install,
+    /// The current marker moved back to the version, from `from`, the newest
+    /// install rolled back
+    Rollback { from: FileVersion },
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChangeKind::Install => write!(f, "install"),
+            ChangeKind::Rollback { .. } => write!(f, "rollback"),
+        }
+    }
+}
+
+/// A change to a swinstalled file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Change {
+    /// When the change happened, in facility local time. For a rollback this
+    /// is the stack's modification time.
+    pub datetime: NaiveDateTime,
+    pub kind: ChangeKind,
+    pub target: SwinstalledFile,
+    /// The version installed, or rolled back to
+    pub version: FileVersion,
+}
+
+/// Every install and rollback beneath `roots` between `since` and `until`
+/// inclusive, facility local times, oldest first. Stack modification times
+/// are converted to facility local time using `facility`.
+pub fn changes<P: AsRef<Path>>(
+    roots: &[P],
+    since: &NaiveDateTime,
+    until: Option<&NaiveDateTime>,
+    facility: Zone,
+    layout: &dyn BakLayout,
+) -> Result<Vec<Change>, BBError> {
+    let in_window = |datetime: &NaiveDateTime| datetime >= since && until.map_or(true, |until| datetime <= until);
+    let mut changes = Vec::new();
+    for root in roots {
+        for found in find_stacks(root.as_ref(), layout)? {
+            let modified = facility_local(found.stamp.mtime, facility);
+            if modified < *since {
+                continue;
+            }
+            let target = found.target.clone();
+            let history = found.into_history()?;
+            for entry in history.entries.iter().filter(|e| in_window(&e.version.date_time)) {
+                changes.push(Change {
+                    datetime: entry.version.date_time,
+                    kind: ChangeKind::Install,
+                    target: target.clone(),
+                    version: entry.version.clone(),
+                });
+            }
+            if let (Some(current), Some(newest)) = (history.current(), history.entries.last()) {
+                if current.version != newest.version && in_window(&modified) {
+                    changes.push(Change {
+                        datetime: modified,
+                        kind: ChangeKind::Rollback { from: newest.version.clone() },
+                        target: target.clone(),
+                        version: current.version.clone(),
+                    });
+                }
+            }
+        }
+    }
+    changes.sort_by(|a, b| (a.datetime, &a.target.live).cmp(&(b.datetime, &b.target.live)));
+    Ok(changes)
+}
+
+// A stack's modification time in facility local time, to the second, as stack
+// timestamps are
+fn facility_local(modified: SystemTime, facility: Zone) -> NaiveDateTime {
+    let local = facility.naive_local(&DateTime::<Utc>::from(modified));
+    local.with_nanosecond(0).unwrap_or(local)
+}
@@ -0,0 +1,244 @@
+//! changeset
+//!
+//! Groups installs across files into changesets. Facility installs usually
+//! touch several files at once, either sharing a revision or landing within a
+//! few seconds of each other, and are best reviewed, diffed and restored as a
+//! unit.
+use chrono::{Duration, NaiveDateTime};
+use constants::BAKTIMEFMT;
+use diff::unified_diff;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile};
+use restore::{EntryState, RestoreEntry};
+use index::find_stacks;
+use stack_history_parser::read_stack_history;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The default number of seconds between installs grouped into one changeset
+pub static DEFAULT_CHANGESET_WINDOW_SECS: i64 = 5;
+
+/// How installs are grouped into changesets
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Grouping {
+    /// Installs sharing a revision form a changeset, however far apart. Installs
+    /// without a revision are grouped by time.
+    #[default]
+    Revision,
+    /// Installs within the window of one another form a changeset, whatever
+    /// their revisions
+    Time,
+}
+
+impl FromStr for Grouping {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "revision" => Ok(Grouping::Revision),
+            "time" => Ok(Grouping::Time),
+            _ => Err(BBError::ParseError(format!("Unable to parse changeset grouping: '{}' (expected revision or time)", s))),
+        }
+    }
+}
+
+/// An install within a changeset
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Member {
+    pub target: SwinstalledFile,
+    pub version: FileVersion,
+    /// The install before it in the stack, which it replaced
+    pub previous: Option<FileVersion>,
+}
+
+/// Installs made together
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Changeset {
+    /// `r<revision>` for a changeset grouped by revision, otherwise the
+    /// timestamp of its first install
+    pub id: String,
+    /// The revision shared by every install, if any
+    pub revision: Option<String>,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// Oldest first
+    pub members: Vec<Member>,
+}
+
+impl Changeset {
+    /// The last install of each file in the changeset, by live path
+    pub fn latest_members(&self) -> Vec<&Member> {
+        let mut latest = BTreeMap::new();
+        for member in &self.members {
+            latest.insert(&member.target.live, member);
+        }
+        latest.into_values().collect()
+    }
+
+    /// The first install of each file in the changeset, by live path
+    pub fn earliest_members(&self) -> Vec<&Member> {
+        let mut earliest = BTreeMap::new();
+        for member in &self.members {
+            earliest.entry(&member.target.live).or_insert(member);
+        }
+        earliest.into_values().collect()
+    }
+
+    // `members` is not empty, and is sorted oldest first
+    fn new(id: String, members: Vec<Member>) -> Changeset {
+        let revision = members[0].version.revision.clone()
+                                 .filter(|rev| members.iter().all(|m| m.version.revision.as_ref() == Some(rev)));
+        Changeset {
+            id,
+            revision,
+            start: members[0].version.date_time,
+            end: members[members.len() - 1].version.date_time,
+            members,
+        }
+    }
+}
+
+/// Every changeset beneath `roots`, oldest first. Installs are grouped by
+/// `grouping`; those grouped by time form a changeset while each is within
+/// `window` of the one before.
+pub fn find_changesets<P: AsRef<Path>>(
+    roots: &[P],
+    grouping: Grouping,
+    window: Duration,
+    layout: &dyn BakLayout,
+) -> Result<Vec<Changeset>, BBError> {
+    let mut members = Vec::new();
+    for root in roots {
+        for found in find_stacks(root.as_ref(), layout)? {
+            let target = found.target.clone();
+            let history = found.into_history()?;
+            let mut previous = None;
+            for entry in &history.entries {
+                members.push(Member { target: target.clone(), version: entry.version.clone(), previous: previous.clone() });
+                previous = Some(entry.version.clone());
+            }
+        }
+    }
+    members.sort_by(|a, b| (a.version.date_time, &a.target.live).cmp(&(b.version.date_time, &b.target.live)));
+
+    let mut changesets = Vec::new();
+    let mut by_revision: BTreeMap<String, Vec<Member>> = BTreeMap::new();
+    let mut by_time: Vec<Vec<Member>> = Vec::new();
+    for member in members {
+        match member.version.revision.clone() {
+            Some(revision) if grouping == Grouping::Revision => by_revision.entry(revision).or_default().push(member),
+            _ => {
+                let joins = by_time.last()
+                                   .and_then(|group| group.last())
+                                   .is_some_and(|last| member.version.date_time - last.version.date_time <= window);
+                if joins {
+                    by_time.last_mut().unwrap().push(member);
+                } else {
+                    by_time.push(vec![member]);
+                }
+            },
+        }
+    }
+    for (revision, members) in by_revision {
+        changesets.push(Changeset::new(format!("r{}", revision), members));
+    }
+    for members in by_time {
+        let id = members[0].version.date_time.format(BAKTIMEFMT).to_string();
+        changesets.push(Changeset::new(id, members));
+    }
+    changesets.sort_by(|a, b| (a.start, &a.id).cmp(&(b.start, &b.id)));
+    Ok(changesets)
+}
+
+/// The changeset beneath `roots` with `id`
+pub fn find_changeset<P: AsRef<Path>>(
+    roots: &[P],
+    id: &str,
+    grouping: Grouping,
+    window: Duration,
+    layout: &dyn BakLayout,
+) -> Result<Changeset, BBError> {
+    find_changesets(roots, grouping, window, layout)?
+        .into_iter()
+        .find(|changeset| changeset.id == id)
+        .ok_or_else(|| BBError::NoVersionFound(format!("no changeset {}", id)))
+}
+
+/// Plan restoring every file in `changeset` to its install in the changeset or,
+/// if `before`, to the install the changeset replaced, for `apply_restore`.
+/// Files first installed by the changeset cannot be restored to before it, and
+/// are left alone.
+pub fn plan_changeset_restore(changeset: &Changeset, before: bool) -> Result<Vec<RestoreEntry>, BBError> {
+    let targets = if before {
+        changeset.earliest_members().into_iter().map(|m| (m, m.previous.clone())).collect::<Vec<_>>()
+    } else {
+        changeset.latest_members().into_iter().map(|m| (m, Some(m.version.clone()))).collect()
+    };
+    let mut entries = Vec::new();
+    for (member, to) in targets {
+        let to = match to {
+            Some(to) => to,
+            None => {
+                warn!("{} was first installed by changeset {}, leaving it alone", member.target.live.display(), changeset.id);
+                continue;
+            },
+        };
+        let from = read_stack_history(&member.target.stack)?.current().map(|e| e.version.clone());
+        if from.as_ref() != Some(&to) {
+            entries.push(RestoreEntry { target: member.target.clone(), from, to, state: EntryState::Pending });
+        }
+    }
+    Ok(entries)
+}
+
+/// A file changed by a changeset, from the backup of the install before the
+/// changeset to the backup of its last install in the changeset
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ChangedFile {
+    pub live: PathBuf,
+    /// None if the changeset first installed the file
+    pub old: Option<PathBuf>,
+    pub new: PathBuf,
+}
+
+/// Every file in `changeset`, with the backups to diff
+pub fn changed_files(changeset: &Changeset) -> Result<Vec<ChangedFile>, BBError> {
+    changeset.earliest_members().into_iter().zip(changeset.latest_members())
+        .map(|(earliest, latest)| Ok(ChangedFile {
+            live: latest.target.live.clone(),
+            old: match earliest.previous {
+                Some(ref previous) => Some(earliest.target.backup_path(previous)?),
+                None => None,
+            },
+            new: latest.target.backup_path(&latest.version)?,
+        }))
+        .collect()
+}
+
+/// A unified diff of every file in `changeset`, from the install before the
+/// changeset to its last install in the changeset
+pub fn changeset_diff(changeset: &Changeset) -> Result<String, BBError> {
+    let mut out = String::new();
+    for file in changed_files(changeset)? {
+        out.push_str(&changed_file_diff(&file)?);
+    }
+    Ok(out)
+}
+
+/// A unified diff of one file changed by a changeset
+pub fn changed_file_diff(file: &ChangedFile) -> Result<String, BBError> {
+    let (old_name, old) = match file.old {
+        Some(ref backup) => (backup.to_string_lossy().into_owned(), read_backup(backup)?),
+        None => ("/dev/null".to_string(), String::new()),
+    };
+    Ok(unified_diff(&old_name, &file.new.to_string_lossy(), &old, &read_backup(&file.new)?, 3))
+}
+
+fn read_backup(backup: &Path) -> Result<String, BBError> {
+    fs::read(backup)
+        .map(|contents| String::from_utf8_lossy(&contents).into_owned())
+        .map_err(|e| BBError::IoError(format!("unable to read {}: {}", backup.display(), e)))
+}
@@ -0,0 +1,334 @@
+//! config
+//!
+//! Layered configuration. Each value is taken from the last of these layers to
+//! set it:
+//!
+//! 1. built in defaults
+//! 2. the system file, `/etc/bakbuster.toml`
+//! 3. the user file, `$XDG_CONFIG_HOME/bakbuster.toml` or `~/.config/bakbuster.toml`
+//! 4. `BAKBUSTER_*` environment variables, eg `BAKBUSTER_LAYOUT_KIND`
+//! 5. command line overrides, eg `-c layout.kind=central`
+//!
+//! Keys are dotted paths into the toml file:
+//!
+//! ```toml
+//! format = "json"
+//! difftool = "meld"
+//! search_roots = ["/dd/facility/etc"]
+//! timezone = "America/Los_Angeles"
+//!
+//! [layout]
+//! kind = "central"
+//! live_root = "/dd/shows"
+//! bak_root = "/dd/bak"
+//!
+//! [retention]
+//! keep = 100
+//! days = 90
+//!
+//! [lock]
+//! method = "auto"
+//! timeout = 10
+//!
+//! [changesets]
+//! grouping = "revision"
+//! window = 5
+//! ```
+use changeset::{Grouping, DEFAULT_CHANGESET_WINDOW_SECS};
+use chrono;
+use errors::BBError;
+use layout::{BakLayout, CentralRootLayout, SwinstallLayout, DEFAULT_BAK_DIR, DEFAULT_STACK_SUFFIX};
+use lock::LockOptions;
+use output::OutputFormat;
+use paths::normalize_path;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use timezone::Zone;
+use toml;
+
+/// The system wide configuration file
+pub static SYSTEM_CONFIG: &str = "/etc/bakbuster.toml";
+/// Prefix of environment variables which override configuration values
+pub static ENV_PREFIX: &str = "BAKBUSTER_";
+
+// Every supported key along with its default value. An empty default means unset.
+static KEYS: &[(&str, &str)] = &[
+    ("format", "text"),
+    ("difftool", ""),
+    ("search_roots", ""),
+    ("timezone", ""),
+    ("layout.kind", "swinstall"),
+    ("layout.bak_dir", DEFAULT_BAK_DIR),
+    ("layout.stack_suffix", DEFAULT_STACK_SUFFIX),
+    ("layout.live_root", ""),
+    ("layout.bak_root", ""),
+    ("retention.keep", ""),
+    ("retention.days", ""),
+    ("lock.method", "auto"),
+    ("lock.timeout", "10"),
+    ("changesets.grouping", "revision"),
+    ("changesets.window", "5"),
+];
+
+/// Where a configuration value came from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Env(String),
+    CommandLine,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(ref path) => write!(f, "{}", path.display()),
+            ConfigSource::Env(ref var) => write!(f, "${}", var),
+            ConfigSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// The effective configuration, recording the source of every value
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Config {
+    values: BTreeMap<&'static str, (String, ConfigSource)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let values = KEYS.iter()
+                         .map(|&(key, default)| (key, (default.to_string(), ConfigSource::Default)))
+                         .collect();
+        Config { values }
+    }
+}
+
+impl Config {
+    /// Load every layer. `overrides` are `key=value` pairs from the command line.
+    pub fn load(overrides: &[String]) -> Result<Config, BBError> {
+        let mut config = Config::default();
+        config.merge_file(Path::new(SYSTEM_CONFIG))?;
+        if let Some(user) = user_config_path() {
+            config.merge_file(&user)?;
+        }
+        config.merge_env()?;
+        for pair in overrides {
+            let idx = pair.find('=')
+                          .ok_or(BBError::ParseError(format!("config override '{}' is not of the form key=value", pair)))?;
+            config.set(&pair[..idx], &pair[idx + 1..], ConfigSource::CommandLine)?;
+        }
+        Ok(config)
+    }
+
+    /// Set a value, rejecting unknown keys
+    pub fn set(&mut self, key: &str, value: &str, source: ConfigSource) -> Result<(), BBError> {
+        let known = KEYS.iter()
+                        .map(|&(k, _)| k)
+                        .find(|k| *k == key)
+                        .ok_or(BBError::ParseError(format!("unknown config key '{}'", key)))?;
+        self.values.insert(known, (value.to_string(), source));
+        Ok(())
+    }
+
+    /// Merge the values in a toml file, if it exists
+    pub fn merge_file(&mut self, path: &Path) -> Result<(), BBError> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let contents = fs::read_to_string(path)
+                       .map_err(|e| BBError::IoError(format!("unable to read {}: {}", path.display(), e)))?;
+        self.merge_toml(&contents, ConfigSource::File(path.to_path_buf()))
+    }
+
+    /// Merge the values in a toml document
+    pub fn merge_toml(&mut self, contents: &str, source: ConfigSource) -> Result<(), BBError> {
+        let value = contents.parse::<toml::Value>()
+                            .map_err(|e| BBError::ParseError(format!("invalid config ({}): {}", source, e)))?;
+        let mut flattened = Vec::new();
+        flatten("", &value, &mut flattened)?;
+        for (key, value) in flattened {
+            self.set(&key, &value, source.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Merge `BAKBUSTER_*` environment variables. `layout.kind` is read from
+    /// `BAKBUSTER_LAYOUT_KIND`, and so on.
+    pub fn merge_env(&mut self) -> Result<(), BBError> {
+        for &(key, _) in KEYS {
+            let var = format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase());
+            match env::var(&var) {
+                Ok(value) => self.set(key, &value, ConfigSource::Env(var))?,
+                Err(env::VarError::NotPresent) => {},
+                Err(env::VarError::NotUnicode(_)) => return Err(BBError::EnvVarError(var)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The raw value of a key and where it came from
+    pub fn get(&self, key: &str) -> Option<(&str, &ConfigSource)> {
+        self.values.get(key).map(|(value, source)| (value.as_str(), source))
+    }
+
+    /// Every key, its raw value and where it came from, sorted by key
+    pub fn entries(&self) -> Vec<(&str, &str, &ConfigSource)> {
+        self.values.iter().map(|(key, (value, source))| (*key, value.as_str(), source)).collect()
+    }
+
+    fn value(&self, key: &str) -> Option<&str> {
+        self.get(key).map(|(value, _)| value).filter(|v| !v.is_empty())
+    }
+
+    fn parsed<T: FromStr>(&self, key: &str) -> Result<Option<T>, BBError> {
+        match self.value(key) {
+            Some(value) => value.parse::<T>()
+                                .map(Some)
+                                .map_err(|_| BBError::ParseError(format!("invalid value for {}: '{}'", key, value))),
+            None => Ok(None),
+        }
+    }
+
+    /// The default output format
+    pub fn format(&self) -> Result<OutputFormat, BBError> {
+        OutputFormat::from_str(self.value("format").unwrap_or("text"))
+    }
+
+    /// The external tool used to display differences between versions
+    pub fn difftool(&self) -> Option<&str> {
+        self.value("difftool")
+    }
+
+    /// Directories searched when a command is not given a root, with
+    /// environment variables and `~` expanded
+    pub fn search_roots(&self) -> Result<Vec<PathBuf>, BBError> {
+        self.value("search_roots")
+            .map(|roots| env::split_paths(roots).map(normalize_path).collect())
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+
+    /// The timezone facility timestamps are recorded in, defaulting to the
+    /// timezone of this host
+    pub fn timezone(&self) -> Result<Zone, BBError> {
+        self.parsed("timezone").map(|zone| zone.unwrap_or_default())
+    }
+
+    /// The number of logged operations kept for undo, if configured
+    pub fn retention_keep(&self) -> Result<Option<u32>, BBError> {
+        self.parsed("retention.keep")
+    }
+
+    /// The number of days logged operations are kept for undo, if configured
+    pub fn retention_days(&self) -> Result<Option<u32>, BBError> {
+        self.parsed("retention.days")
+    }
+
+    /// How stacks are locked by mutating operations
+    pub fn lock_options(&self) -> Result<LockOptions, BBError> {
+        let mut options = LockOptions::default();
+        if let Some(method) = self.parsed("lock.method")? {
+            options.method = method;
+        }
+        if let Some(timeout) = self.parsed("lock.timeout")? {
+            options.timeout = Duration::from_secs(timeout);
+        }
+        Ok(options)
+    }
+
+    /// How installs are grouped into changesets
+    pub fn changeset_grouping(&self) -> Result<Grouping, BBError> {
+        self.parsed("changesets.grouping").map(|grouping| grouping.unwrap_or_default())
+    }
+
+    /// The most time between installs grouped into one changeset by time
+    pub fn changeset_window(&self) -> Result<chrono::Duration, BBError> {
+        self.parsed("changesets.window")
+            .map(|secs| chrono::Duration::seconds(secs.unwrap_or(DEFAULT_CHANGESET_WINDOW_SECS)))
+    }
+
+    /// Build the configured bak layout
+    pub fn layout(&self) -> Result<Box<dyn BakLayout>, BBError> {
+        let stack_suffix = self.value("layout.stack_suffix").unwrap_or(DEFAULT_STACK_SUFFIX).to_string();
+        match self.value("layout.kind").unwrap_or("swinstall") {
+            "swinstall" => Ok(Box::new(SwinstallLayout {
+                bak_dir: self.value("layout.bak_dir").unwrap_or(DEFAULT_BAK_DIR).to_string(),
+                stack_suffix,
+            })),
+            "central" => {
+                let live_root = self.value("layout.live_root")
+                                    .ok_or(BBError::ParseError("layout.live_root is required by the central layout".to_string()))?;
+                let bak_root = self.value("layout.bak_root")
+                                   .ok_or(BBError::ParseError("layout.bak_root is required by the central layout".to_string()))?;
+                let mut layout = CentralRootLayout::new(normalize_path(live_root)?, normalize_path(bak_root)?);
+                layout.stack_suffix = stack_suffix;
+                Ok(Box::new(layout))
+            },
+            kind => Err(BBError::ParseError(format!("unknown layout.kind '{}' (expected swinstall or central)", kind))),
+        }
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    if let Ok(xdg) = env::var("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(xdg).join("bakbuster.toml"));
+    }
+    env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config").join("bakbuster.toml"))
+}
+
+// Flatten nested tables into dotted keys. Arrays of strings are joined using the
+// platform path separator, which is how search_roots is stored.
+fn flatten(prefix: &str, value: &toml::Value, out: &mut Vec<(String, String)>) -> Result<(), BBError> {
+    match *value {
+        toml::Value::Table(ref table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, out)?;
+            }
+        },
+        toml::Value::Array(ref items) => {
+            let items = items.iter()
+                             .map(|item| item.as_str().map(PathBuf::from)
+                                             .ok_or(BBError::ParseError(format!("{} must be an array of strings", prefix))))
+                             .collect::<Result<Vec<_>, _>>()?;
+            let joined = env::join_paths(items)
+                         .map_err(|e| BBError::ParseError(format!("invalid value for {}: {}", prefix, e)))?;
+            out.push((prefix.to_string(), joined.to_string_lossy().into_owned()));
+        },
+        toml::Value::String(ref s) => out.push((prefix.to_string(), s.clone())),
+        ref other => out.push((prefix.to_string(), other.to_string())),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_layers_win() {
+        let mut config = Config::default();
+        let toml = r#"
+format = "json"
+search_roots = ["/dd/facility/etc", "/dd/shows/foo/etc"]
+
+[retention]
+keep = 10
+"#;
+        config.merge_toml(toml, ConfigSource::File(PathBuf::from("/etc/bakbuster.toml"))).unwrap();
+        config.set("format", "tsv", ConfigSource::CommandLine).unwrap();
+
+        assert_eq!(config.format(), Ok(OutputFormat::Tsv));
+        assert_eq!(config.get("format").unwrap().1, &ConfigSource::CommandLine);
+        assert_eq!(config.search_roots().unwrap(), vec![PathBuf::from("/dd/facility/etc"), PathBuf::from("/dd/shows/foo/etc")]);
+        assert_eq!(config.retention_keep(), Ok(Some(10)));
+        assert_eq!(config.get("difftool").unwrap().1, &ConfigSource::Default);
+        assert!(config.set("no_such_key", "x", ConfigSource::CommandLine).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! diff
+//!
+//! A minimal line based diff, built on the longest common subsequence of the two
+//! inputs. Swinstalled files are small config files, so the quadratic table is
+//! not a concern once the common prefix and suffix have been trimmed. Where an
+//! external difftool is configured, it is run instead.
+use errors::BBError;
+use std::path::Path;
+use std::process::Command;
+
+/// A single step in transforming the old lines into the new lines. Indices refer
+/// to positions within the old and new slices respectively.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DiffOp {
+    /// The line is present in both, at (old, new)
+    Equal(usize, usize),
+    /// The old line at the index was removed
+    Delete(usize),
+    /// The new line at the index was added
+    Insert(usize),
+}
+
+/// Compute the operations which transform `old` into `new`, in order.
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffOp> {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+                    .zip(new[prefix..].iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal(i, i)).collect();
+
+    // lcs[i][j] holds the length of the longest common subsequence of
+    // old_mid[i..] and new_mid[j..]
+    let (n, m) = (old_mid.len(), new_mid.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            ops.push(DiffOp::Equal(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ops.push(DiffOp::Insert(prefix + j));
+            j += 1;
+        } else {
+            ops.push(DiffOp::Delete(prefix + i));
+            i += 1;
+        }
+    }
+
+    let (old_tail, new_tail) = (old.len() - suffix, new.len() - suffix);
+    ops.extend((0..suffix).map(|k| DiffOp::Equal(old_tail + k, new_tail + k)));
+    ops
+}
+
+/// Render the differences between `old` and `new` as a unified diff, with
+/// `context` unchanged lines around each change. Empty if they are the same.
+pub fn unified_diff(old_name: &str, new_name: &str, old: &str, new: &str, context: usize) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let ops = diff_lines(&old_lines, &new_lines);
+    let changes = ops.iter().enumerate()
+                     .filter(|(_, op)| !matches!(op, DiffOp::Equal(..)))
+                     .map(|(idx, _)| idx)
+                     .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return String::new();
+    }
+    let in_old = |op: &DiffOp| !matches!(op, DiffOp::Insert(_));
+    let in_new = |op: &DiffOp| !matches!(op, DiffOp::Delete(_));
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut next = 0;
+    while next < changes.len() {
+        // changes separated by no more than twice the context share a hunk
+        let first = changes[next];
+        let mut last = first;
+        next += 1;
+        while next < changes.len() && changes[next] - last - 1 <= 2 * context {
+            last = changes[next];
+            next += 1;
+        }
+        let start = first.saturating_sub(context);
+        let hunk = &ops[start..(last + context + 1).min(ops.len())];
+
+        let (old_before, new_before) = (ops[..start].iter().filter(|op| in_old(op)).count(),
+                                        ops[..start].iter().filter(|op| in_new(op)).count());
+        let (old_count, new_count) = (hunk.iter().filter(|op| in_old(op)).count(),
+                                      hunk.iter().filter(|op| in_new(op)).count());
+        // an empty range is numbered by the line before it
+        let old_start = if old_count == 0 { old_before } else { old_before + 1 };
+        let new_start = if new_count == 0 { new_before } else { new_before + 1 };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_count, new_start, new_count));
+        // within a run of changes, deletions are shown before insertions
+        let mut inserted = String::new();
+        for op in hunk {
+            match *op {
+                DiffOp::Equal(o, _) => {
+                    out.push_str(&inserted);
+                    inserted.clear();
+                    out.push_str(&format!(" {}\n", old_lines[o]));
+                },
+                DiffOp::Delete(o) => out.push_str(&format!("-{}\n", old_lines[o])),
+                DiffOp::Insert(n) => inserted.push_str(&format!("+{}\n", new_lines[n])),
+            }
+        }
+        out.push_str(&inserted);
+    }
+    out
+}
+
+/// Run `tool`, a command optionally followed by arguments, on the files `old`
+/// and `new`. Difftools exit non-zero when the files differ, so only a failure
+/// to run the tool is an error.
+pub fn run_difftool(tool: &str, old: &Path, new: &Path) -> Result<(), BBError> {
+    let mut words = tool.split_whitespace();
+    let program = words.next()
+                       .ok_or_else(|| BBError::ParseError("difftool is empty".to_string()))?;
+    Command::new(program)
+        .args(words)
+        .arg(old)
+        .arg(new)
+        .status()
+        .map(|_| ())
+        .map_err(|e| BBError::IoError(format!("unable to run difftool {}: {}", program, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_insert_and_delete() {
+        let old = vec!["a", "b", "c", "d"];
+        let new = vec!["a", "c", "x", "d"];
+        let ops = diff_lines(&old, &new);
+        assert_eq!(ops, vec![
+            DiffOp::Equal(0, 0),
+            DiffOp::Delete(1),
+            DiffOp::Equal(2, 1),
+            DiffOp::Insert(2),
+            DiffOp::Equal(3, 3),
+        ]);
+    }
+
+    #[test]
+    fn unified_diff_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\nten\n";
+        assert_eq!(unified_diff("a", "b", old, new, 1), "--- a\n+++ b\n\
+            @@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+            @@ -9,1 +9,2 @@\n 9\n+ten\n");
+        assert_eq!(unified_diff("a", "b", "", "x\n", 3), "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+x\n");
+        assert_eq!(unified_diff("a", "b", old, old, 3), "");
+    }
+}
@@ -1,4 +1,8 @@
+// failure_derive generates its Display impl inside an anonymous const
+#![allow(non_local_definitions)]
+
 #[derive(Fail, Debug,PartialEq,Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BBError {
     /// Parsing error
     #[fail(display = "ParseError: failed to parse: {}", _0)]
@@ -15,6 +19,13 @@ pub enum BBError {
     NonExtantPath(String),
     #[fail(display = "ConversionError: {}", _0)]
     ConversionError(String),
+    /// The file had no installed version matching the request, eg on a date
+    /// before its first install
+    #[fail(display = "NoVersionFound: {}", _0)]
+    NoVersionFound(String),
+    /// Failure reading or writing a file
+    #[fail(display = "IoError: {}", _0)]
+    IoError(String),
 
 
 }
\ No newline at end of file
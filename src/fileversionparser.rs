@@ -1,10 +1,11 @@
 use pest::Parser;
 use errors::BBError;
+use constants::BAKTIMEFMT;
 use chrono::prelude::*;
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct FileVersion {
     pub date_time: NaiveDateTime,
     pub revision: Option<String>
@@ -40,11 +41,55 @@ fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             "".to_string()
         };
 
-        write!(f, "{}{}{}-{}{}{}{}",
-            self.date_time.year(),self.date_time.month(), self.date_time.day(),
-            self.date_time.hour(), self.date_time.minute(), self.date_time.second(),
-            revision
-        )
+        write!(f, "{}{}", self.date_time.format(BAKTIMEFMT), revision)
+    }
+}
+
+// FileVersion serializes to its canonical YYYYMMDD-HHMMSS[_rN] string.
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for FileVersion {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for FileVersion {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as ::serde::Deserialize>::deserialize(deserializer)?;
+        FileVersion::from_str(&s).map_err(::serde::de::Error::custom)
+    }
+}
+
+/// A structured alternative to the canonical string form of FileVersion, for
+/// use with `#[serde(with = "bakbuster::fileversionparser::structured")]`.
+/// Serializes as `{"timestamp": "YYYY-MM-DD HH:MM:SS", "revision": "N"}`, with
+/// a null revision when there is none.
+#[cfg(feature = "serde")]
+pub mod structured {
+    use super::FileVersion;
+    use chrono::NaiveDateTime;
+    use constants::STDTIMEFMT;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Structured {
+        timestamp: String,
+        revision: Option<String>,
+    }
+
+    pub fn serialize<S: Serializer>(version: &FileVersion, serializer: S) -> Result<S::Ok, S::Error> {
+        Structured {
+            timestamp: version.date_time.format(STDTIMEFMT).to_string(),
+            revision: version.revision.clone(),
+        }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FileVersion, D::Error> {
+        let structured = Structured::deserialize(deserializer)?;
+        let date_time = NaiveDateTime::parse_from_str(&structured.timestamp, STDTIMEFMT)
+                                      .map_err(de::Error::custom)?;
+        Ok(FileVersion::new(date_time, structured.revision))
     }
 }
 
@@ -79,13 +124,11 @@ impl FileVersionParser {
 
             // A idx_piece can be converted to an iterator of the tokens which make it up:
             for inner_idx_piece in idx_piece.into_inner() {
-                let inner_span = inner_idx_piece.clone().into_span();
-
                 match inner_idx_piece.as_rule() {
 
                     Rule::revision => {
                         for revision_piece in inner_idx_piece.into_inner() {
-                            let inner_span = revision_piece.clone().into_span();
+                            let inner_span = revision_piece.as_span();
                             match revision_piece.as_rule() {
                                 Rule::revision_id  => {
                                     revision = Some(inner_span.as_str().to_string());
@@ -96,7 +139,7 @@ impl FileVersionParser {
                     },
                     Rule::datetime => {
                         for date_piece in inner_idx_piece.into_inner() {
-                            let inner_span = date_piece.clone().into_span();
+                            let inner_span = date_piece.as_span();
                             match date_piece.as_rule() {
                                 Rule::year  => {
                                     year = Some(inner_span.as_str().parse::<i32>().unwrap());
@@ -140,7 +183,7 @@ mod tests {
     #[test]
     fn parse_file_version() {
         let fvstr = "20181105-103813";
-        let fv = FileVersion::from_str(&fvstr);
+        let fv = FileVersion::from_str(fvstr);
         let expect = FileVersion {
             date_time: NaiveDate::from_ymd(2018, 11, 5).and_hms(10,38,13),
             revision: None,
@@ -151,11 +194,37 @@ mod tests {
     #[test]
     fn parse_file_version_revision() {
         let fvstr = "20181105-103813_r12431345";
-        let fv = FileVersion::from_str(&fvstr);
+        let fv = FileVersion::from_str(fvstr);
         let expect = FileVersion {
             date_time: NaiveDate::from_ymd(2018, 11, 5).and_hms(10,38,13),
             revision: Some("12431345".to_string()),
         };
         assert_eq!(fv, Ok(expect));
     }
+
+    #[test]
+    fn display_round_trips() {
+        let fvstr = "20180102-030405_r575055";
+        let fv = FileVersion::from_str(fvstr).unwrap();
+        assert_eq!(fv.to_string(), fvstr);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips() {
+        extern crate serde_json;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Wrapper {
+            canonical: FileVersion,
+            #[serde(with = "structured")]
+            structured: FileVersion,
+        }
+
+        let fv = FileVersion::from_str("20181105-103813_r575055").unwrap();
+        let wrapper = Wrapper { canonical: fv.clone(), structured: fv };
+        let json = serde_json::to_string(&wrapper).unwrap();
+        assert_eq!(json, r#"{"canonical":"20181105-103813_r575055","structured":{"timestamp":"2018-11-05 10:38:13","revision":"575055"}}"#);
+        assert_eq!(serde_json::from_str::<Wrapper>(&json).unwrap(), wrapper);
+    }
 }
\ No newline at end of file
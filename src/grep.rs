@@ -0,0 +1,110 @@
+//! grep
+//!
+//! Searches the content of every backup referenced by a swinstall stack, to
+//! answer questions like "when did this hostname first appear in our configs?".
+use chrono::naive::NaiveDateTime;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use regex::Regex;
+use scan::SwinstalledFile;
+use stack_history_parser::read_stack_history;
+use std::fs;
+use std::path::PathBuf;
+
+/// Restricts the installs which are searched
+#[derive(Debug, Default, Clone)]
+pub struct GrepOptions {
+    /// Only search installs at or after this datetime
+    pub since: Option<NaiveDateTime>,
+    /// Only search installs at or before this datetime
+    pub until: Option<NaiveDateTime>,
+}
+
+impl GrepOptions {
+    fn in_range(&self, version: &FileVersion) -> bool {
+        self.since.map(|since| version.date_time >= since).unwrap_or(true)
+            && self.until.map(|until| version.date_time <= until).unwrap_or(true)
+    }
+}
+
+/// A matching line within a backup
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GrepMatch {
+    /// The live file the backup belongs to
+    pub file: PathBuf,
+    pub version: FileVersion,
+    /// One based line number
+    pub line_number: usize,
+    pub line: String,
+}
+
+/// An install at which the number of matching lines changed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PickaxeHit {
+    /// The live file the backup belongs to
+    pub file: PathBuf,
+    pub version: FileVersion,
+    /// Number of matching lines in the previous install
+    pub before: usize,
+    /// Number of matching lines in this install
+    pub after: usize,
+}
+
+impl PickaxeHit {
+    /// True if the install added matches, false if it removed them
+    pub fn added(&self) -> bool {
+        self.after > self.before
+    }
+}
+
+/// Search each backup of `target` for lines matching `pattern`.
+pub fn grep(target: &SwinstalledFile, pattern: &Regex, options: &GrepOptions) -> Result<Vec<GrepMatch>, BBError> {
+    let mut matches = Vec::new();
+    for (version, contents) in backups(target)? {
+        if !options.in_range(&version) {
+            continue;
+        }
+        for (idx, line) in contents.lines().enumerate() {
+            if pattern.is_match(line) {
+                matches.push(GrepMatch {
+                    file: target.live.clone(),
+                    version: version.clone(),
+                    line_number: idx + 1,
+                    line: line.to_string(),
+                });
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Report only the installs of `target` where the number of lines matching
+/// `pattern` changed from the install before it. The first install is compared
+/// against an empty file.
+pub fn pickaxe(target: &SwinstalledFile, pattern: &Regex, options: &GrepOptions) -> Result<Vec<PickaxeHit>, BBError> {
+    let mut hits = Vec::new();
+    let mut before = 0;
+    for (version, contents) in backups(target)? {
+        let after = contents.lines().filter(|line| pattern.is_match(line)).count();
+        if after != before && options.in_range(&version) {
+            hits.push(PickaxeHit { file: target.live.clone(), version, before, after });
+        }
+        before = after;
+    }
+    Ok(hits)
+}
+
+// Read every backup referenced by the stack, in stack order. Backups which are
+// missing from the bak directory are skipped with a warning.
+fn backups(target: &SwinstalledFile) -> Result<Vec<(FileVersion, String)>, BBError> {
+    let history = read_stack_history(&target.stack)?;
+    let mut contents = Vec::with_capacity(history.entries.len());
+    for entry in history.entries {
+        let backup = target.backup_path(&entry.version)?;
+        match fs::read(&backup) {
+            Ok(bytes) => contents.push((entry.version, String::from_utf8_lossy(&bytes).into_owned())),
+            Err(e) => warn!("skipping {}: {}", backup.display(), e),
+        }
+    }
+    Ok(contents)
+}
@@ -0,0 +1,169 @@
+//! history
+//!
+//! Builds a log of every install recorded in a swinstall stack, newest first,
+//! annotated with the information people otherwise dig out of the raw
+//! `_swinstall_stack` xml by hand.
+use chrono::naive::NaiveDateTime;
+use chrono::Duration;
+use constants::STDTIMEFMT;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile, SwinstallLayout};
+use stack_history_parser::read_stack_history;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The template used by `LogEntry::render` when none is supplied
+pub static DEFAULT_LOG_FORMAT: &str = "{version}  {timestamp}  {revision}  {size}  {status}  newest for {newest_for}";
+
+/// A single install of a swinstalled file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LogEntry {
+    pub version: FileVersion,
+    /// The backup of this install within the bak directory
+    pub backup: PathBuf,
+    /// Size of the backup in bytes, or None if the backup is missing
+    pub size: Option<u64>,
+    pub is_current: bool,
+    /// True if the entry lies after the current entry, ie it has been rolled back
+    pub rolled_back: bool,
+    /// How long this was the newest install, ie until the next install or now
+    pub newest_for: Duration,
+}
+
+impl LogEntry {
+    /// A short description of the entry's state: current, rolled back or blank
+    pub fn status(&self) -> &'static str {
+        if self.is_current {
+            "current"
+        } else if self.rolled_back {
+            "rolled-back"
+        } else {
+            "-"
+        }
+    }
+
+    /// Render the entry using a template. Supported placeholders are
+    /// `{version}`, `{timestamp}`, `{revision}`, `{size}`, `{backup}`,
+    /// `{current}`, `{rolled_back}`, `{status}` and `{newest_for}`.
+    /// Substituted values are not themselves searched for placeholders, and
+    /// anything else in braces is left as is.
+    pub fn render(&self, template: &str) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let value = rest.find('}').and_then(|close| self.placeholder(&rest[1..close]).map(|value| (close, value)));
+            match value {
+                Some((close, value)) => {
+                    rendered.push_str(&value);
+                    rest = &rest[close + 1..];
+                },
+                None => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    // The value of the placeholder `name`, or None if there is no such placeholder
+    fn placeholder(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "version" => self.version.to_string(),
+            "timestamp" => self.version.date_time.format(STDTIMEFMT).to_string(),
+            "revision" => self.version.revision.as_deref().unwrap_or("-").to_string(),
+            "size" => self.size.map(|s| s.to_string()).unwrap_or_else(|| "missing".to_string()),
+            "backup" => self.backup.display().to_string(),
+            "current" => self.is_current.to_string(),
+            "rolled_back" => self.rolled_back.to_string(),
+            "status" => self.status().to_string(),
+            "newest_for" => format_duration(&self.newest_for),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Build the log of every install of the swinstalled `file`, newest first.
+/// `now` bounds how long the last install has been the newest.
+pub fn file_log<P: AsRef<Path>>(file: P, now: &NaiveDateTime) -> Result<Vec<LogEntry>, BBError> {
+    file_log_with_layout(file, now, &SwinstallLayout::default())
+}
+
+/// Build the log of every install of the swinstalled `file`, newest first,
+/// locating its history using `layout`.
+pub fn file_log_with_layout<P: AsRef<Path>>(
+    file: P,
+    now: &NaiveDateTime,
+    layout: &dyn BakLayout,
+) -> Result<Vec<LogEntry>, BBError> {
+    let target = SwinstalledFile::new(file.as_ref(), layout)?;
+    let history = read_stack_history(&target.stack)?;
+    let current_index = history.current_index();
+
+    let mut log = Vec::with_capacity(history.entries.len());
+    for (idx, entry) in history.entries.iter().enumerate() {
+        let backup = target.backup_path(&entry.version)?;
+        let size = fs::metadata(&backup).ok().map(|m| m.len());
+        let until = history.entries.get(idx + 1)
+                                   .map(|next| next.version.date_time)
+                                   .unwrap_or(*now);
+        log.push(LogEntry {
+            version: entry.version.clone(),
+            backup,
+            size,
+            is_current: entry.is_current,
+            rolled_back: current_index.map(|c| idx > c).unwrap_or(false),
+            newest_for: until.signed_duration_since(entry.version.date_time),
+        });
+    }
+    log.reverse();
+    Ok(log)
+}
+
+/// Format a duration as days, hours and minutes, eg `3d 4h 12m`.
+pub fn format_duration(duration: &Duration) -> String {
+    let minutes = duration.num_minutes();
+    if minutes < 0 {
+        return "-".to_string();
+    }
+    let (days, hours, minutes) = (minutes / (24 * 60), (minutes / 60) % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use constants::BAKTIMEFMT;
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(format_duration(&Duration::minutes(5)), "5m");
+        assert_eq!(format_duration(&Duration::minutes(125)), "2h 5m");
+        assert_eq!(format_duration(&Duration::minutes(3 * 24 * 60 + 61)), "3d 1h 1m");
+    }
+
+    #[test]
+    fn render_substitutes_in_one_pass() {
+        let date_time = NaiveDateTime::parse_from_str("20181105-103813", BAKTIMEFMT).unwrap();
+        let entry = LogEntry {
+            version: FileVersion::new(date_time, Some("{size}".to_string())),
+            backup: PathBuf::from("packages.xml_20181105-103813_r{size}"),
+            size: Some(12),
+            is_current: true,
+            rolled_back: false,
+            newest_for: Duration::minutes(5),
+        };
+        assert_eq!(entry.render("{revision} {size} {unknown} {{status}"), "{size} 12 {unknown} {current");
+    }
+}
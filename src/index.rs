@@ -0,0 +1,415 @@
+//! index
+//!
+//! An on-disk index of the stacks beneath a tree, so that queries over large
+//! trees on slow filesystems need not read and parse every stack.
+//!
+//! The index lives in `.bakbuster-index/index` at the root of the tree searched
+//! for stacks, and holds the parsed entries of every stack along with its
+//! modification time and size. It also records the modification time of every
+//! directory walked, as adding or renaming a file changes its directory's.
+//! A directory which is unchanged is not listed again, and a stack which is
+//! unchanged is not read again; anything else is scanned live, as it would be
+//! without an index. The index is kept in a directory of its own, which is not
+//! walked, so that writing it does not change the root's modification time.
+//!
+//! Modification times are not fine grained on every filesystem, so anything
+//! modified within a second before the index was written is not trusted.
+//!
+//! The index records stacks as the configured layout found them; rebuild it
+//! after changing `layout.stack_suffix`.
+use errors::BBError;
+use fileversionparser::FileVersion;
+use install::write_atomically;
+use layout::{BakLayout, SwinstalledFile};
+use paths::{decode_path, encode_path};
+use stack_history_parser::{read_stack_history, StackEntry, StackHistory};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The name of the directory holding the index at the root of an indexed tree
+pub static INDEX_DIR: &str = ".bakbuster-index";
+/// The name of the index file within `INDEX_DIR`
+pub static INDEX_FILE: &str = "index";
+/// First line of every index file
+pub static INDEX_HEADER: &str = "bakbuster-index 1";
+
+/// A file's modification time and size, which change whenever it is written
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Stamp {
+    pub mtime: SystemTime,
+    pub size: u64,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> Result<Stamp, BBError> {
+        let metadata = fs::symlink_metadata(path)
+            .map_err(|e| BBError::IoError(format!("unable to stat {}: {}", path.display(), e)))?;
+        let mtime = metadata.modified()
+            .map_err(|e| BBError::IoError(format!("unable to read modification time of {}: {}", path.display(), e)))?;
+        Ok(Stamp { mtime, size: metadata.len() })
+    }
+}
+
+/// A stack found beneath a root
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FoundStack {
+    pub target: SwinstalledFile,
+    /// The stack's modification time and size when it was found
+    pub stamp: Stamp,
+    /// The stack's history, if the index holds it and it is still current
+    pub indexed: Option<StackHistory>,
+}
+
+impl FoundStack {
+    /// The stack's history, from the index if it is current there, otherwise
+    /// read from the stack
+    pub fn into_history(self) -> Result<StackHistory, BBError> {
+        match self.indexed {
+            Some(history) => Ok(history),
+            None => read_stack_history(&self.target.stack),
+        }
+    }
+}
+
+/// What building or updating an index did
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndexRefresh {
+    pub path: PathBuf,
+    /// Stacks in the index
+    pub stacks: usize,
+    /// Stacks read from disk
+    pub read: usize,
+    /// Stacks taken from the previous index
+    pub reused: usize,
+}
+
+/// The contents of an index, and how much of it is stale
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndexStats {
+    pub path: PathBuf,
+    /// When the index was written
+    pub built: SystemTime,
+    /// The size of the index file in bytes
+    pub bytes: u64,
+    pub stacks: usize,
+    pub entries: usize,
+    pub dirs: usize,
+    /// Stacks changed or removed since the index was written
+    pub stale_stacks: usize,
+    /// Directories changed or removed since the index was written, beneath
+    /// which stacks may have been added
+    pub stale_dirs: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct IndexedStack {
+    stamp: Stamp,
+    history: StackHistory,
+}
+
+/// A parsed index file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Index {
+    path: PathBuf,
+    built: SystemTime,
+    dirs: BTreeMap<PathBuf, Stamp>,
+    stacks: BTreeMap<PathBuf, IndexedStack>,
+}
+
+impl Index {
+    fn new(path: PathBuf) -> Index {
+        Index { path, built: UNIX_EPOCH, dirs: BTreeMap::new(), stacks: BTreeMap::new() }
+    }
+
+    /// Load the index at `path`
+    pub fn load(path: &Path) -> Result<Index, BBError> {
+        let contents = fs::read_to_string(path)
+                       .map_err(|e| BBError::IoError(format!("unable to read {}: {}", path.display(), e)))?;
+        let invalid = |line: &str| BBError::ParseError(format!("invalid line in {}: '{}'", path.display(), line));
+        let mut lines = contents.lines();
+        if lines.next() != Some(INDEX_HEADER) {
+            return Err(BBError::ParseError(format!("{} is not a bakbuster index", path.display())));
+        }
+        let mut index = Index::new(path.to_path_buf());
+        let mut stack: Option<(PathBuf, IndexedStack)> = None;
+        for line in lines {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            match fields.as_slice() {
+                ["built", built] => index.built = parse_time(built).ok_or_else(|| invalid(line))?,
+                ["dir", dir, mtime, size] => {
+                    index.dirs.insert(decode_path(dir)?, parse_stamp(mtime, size).ok_or_else(|| invalid(line))?);
+                },
+                ["stack", path, mtime, size, history_path] => {
+                    if let Some((path, indexed)) = stack.take() {
+                        index.stacks.insert(path, indexed);
+                    }
+                    let history_path = match *history_path {
+                        "-" => None,
+                        encoded => Some(decode_path(encoded)?.to_string_lossy().into_owned()),
+                    };
+                    stack = Some((decode_path(path)?, IndexedStack {
+                        stamp: parse_stamp(mtime, size).ok_or_else(|| invalid(line))?,
+                        history: StackHistory { path: history_path, entries: Vec::new() },
+                    }));
+                },
+                ["entry", version, current] => {
+                    let (_, indexed) = stack.as_mut().ok_or_else(|| invalid(line))?;
+                    indexed.history.entries.push(StackEntry {
+                        version: FileVersion::from_str(version)?,
+                        is_current: *current == "current",
+                    });
+                },
+                _ => return Err(invalid(line)),
+            }
+        }
+        if let Some((path, indexed)) = stack {
+            index.stacks.insert(path, indexed);
+        }
+        Ok(index)
+    }
+
+    fn save(&mut self) -> Result<(), BBError> {
+        self.built = SystemTime::now();
+        let mut contents = format!("{}\nbuilt\t{}\n", INDEX_HEADER, format_time(self.built));
+        for (dir, stamp) in &self.dirs {
+            contents.push_str(&format!("dir\t{}\t{}\t{}\n", encode_path(dir), format_time(stamp.mtime), stamp.size));
+        }
+        for (path, indexed) in &self.stacks {
+            let history_path = indexed.history.path.as_ref()
+                                      .map(|p| encode_path(Path::new(p)))
+                                      .unwrap_or_else(|| "-".to_string());
+            contents.push_str(&format!("stack\t{}\t{}\t{}\t{}\n",
+                encode_path(path), format_time(indexed.stamp.mtime), indexed.stamp.size, history_path));
+            for entry in &indexed.history.entries {
+                contents.push_str(&format!("entry\t{}\t{}\n", entry.version, if entry.is_current { "current" } else { "-" }));
+            }
+        }
+        write_atomically(&self.path, |output| {
+            output.write_all(contents.as_bytes())
+                  .map_err(|e| BBError::IoError(format!("unable to write index: {}", e)))
+        })
+    }
+
+    // a recorded stamp may be relied on if the file still has it, and the file
+    // was not modified so close to the index being written that a later change
+    // could leave its modification time unchanged
+    fn trusts(&self, recorded: &Stamp, current: &Stamp) -> bool {
+        recorded == current && recorded.mtime + Duration::from_secs(1) < self.built
+    }
+
+    // the directories and stacks recorded directly within `dir`
+    fn children(&self, dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let within = |path: &&PathBuf| path.parent() == Some(dir);
+        let dirs = self.dirs.range(dir.to_path_buf()..)
+                            .map(|(path, _)| path)
+                            .take_while(|path| path.starts_with(dir))
+                            .filter(within)
+                            .cloned()
+                            .collect();
+        let stacks = self.stacks.range(dir.to_path_buf()..)
+                                .map(|(path, _)| path)
+                                .take_while(|path| path.starts_with(dir))
+                                .filter(within)
+                                .cloned()
+                                .collect();
+        (dirs, stacks)
+    }
+}
+
+/// The index file for the tree beneath `root`, as laid out by `layout`
+pub fn index_path(root: &Path, layout: &dyn BakLayout) -> Result<PathBuf, BBError> {
+    Ok(layout.search_root(root)?.join(INDEX_DIR).join(INDEX_FILE))
+}
+
+/// Index every stack beneath `root` afresh, reading each one
+pub fn build_index(root: &Path, layout: &dyn BakLayout) -> Result<IndexRefresh, BBError> {
+    let path = index_path(root, layout)?;
+    refresh(&path, &layout.search_root(root)?, layout, None)
+}
+
+/// Bring the index beneath `root` up to date, only reading the stacks which
+/// have changed. Builds the index if there is none.
+pub fn update_index(root: &Path, layout: &dyn BakLayout) -> Result<IndexRefresh, BBError> {
+    let path = index_path(root, layout)?;
+    let previous = if path.exists() { Some(Index::load(&path)?) } else { None };
+    refresh(&path, &layout.search_root(root)?, layout, previous.as_ref())
+}
+
+/// Describe the index beneath `root`, checking every stack and directory it
+/// records against the disk
+pub fn index_stats(root: &Path, layout: &dyn BakLayout) -> Result<IndexStats, BBError> {
+    let path = index_path(root, layout)?;
+    if !path.exists() {
+        return Err(BBError::NonExtantPath(format!("{} has no index; build one with `bakbuster index build`", root.display())));
+    }
+    let index = Index::load(&path)?;
+    let stale = |recorded: &Stamp, path: &Path| Stamp::of(path).map(|current| !index.trusts(recorded, &current)).unwrap_or(true);
+    Ok(IndexStats {
+        bytes: Stamp::of(&path)?.size,
+        built: index.built,
+        stacks: index.stacks.len(),
+        entries: index.stacks.values().map(|indexed| indexed.history.entries.len()).sum(),
+        dirs: index.dirs.len(),
+        stale_stacks: index.stacks.iter().filter(|(path, indexed)| stale(&indexed.stamp, path)).count(),
+        stale_dirs: index.dirs.iter().filter(|(path, stamp)| stale(stamp, path)).count(),
+        path,
+    })
+}
+
+/// Every stack beneath `root`, sorted by live path, as
+/// `scan::find_swinstalled_files_with_layout` finds them. Where `root` lies
+/// within an indexed tree, unchanged directories are not listed and the
+/// histories of unchanged stacks are taken from the index.
+pub fn find_stacks(root: &Path, layout: &dyn BakLayout) -> Result<Vec<FoundStack>, BBError> {
+    let search_root = layout.search_root(root)?;
+    let index = match find_index(&search_root) {
+        Some(path) => match Index::load(&path) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                warn!("ignoring index: {}", e);
+                None
+            },
+        },
+        None => None,
+    };
+    let mut scan = Scan::new(layout, index.as_ref(), search_root.join(INDEX_DIR).join(INDEX_FILE));
+    if search_root.is_dir() {
+        scan.visit(&search_root)?;
+    }
+    if index.is_some() {
+        debug!("index held {} of {} stacks beneath {}", scan.reused, scan.found.len(), search_root.display());
+    }
+    let mut found = scan.found;
+    found.sort_by(|a, b| a.target.cmp(&b.target));
+    Ok(found)
+}
+
+// the nearest index at or above `dir`
+fn find_index(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|dir| dir.join(INDEX_DIR).join(INDEX_FILE)).find(|path| path.is_file())
+}
+
+fn refresh(path: &Path, search_root: &Path, layout: &dyn BakLayout, previous: Option<&Index>) -> Result<IndexRefresh, BBError> {
+    let mut scan = Scan::new(layout, previous, path.to_path_buf());
+    if search_root.is_dir() {
+        // created before the root is listed, so that the root's recorded
+        // modification time already includes it
+        let dir = search_root.join(INDEX_DIR);
+        match fs::create_dir(&dir) {
+            Err(ref e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                return Err(BBError::IoError(format!("unable to create {}: {}", dir.display(), e)));
+            },
+            _ => (),
+        }
+        scan.visit(search_root)?;
+    }
+    let reused = scan.reused;
+    let (mut index, read) = scan.into_index()?;
+    index.save()?;
+    Ok(IndexRefresh { path: path.to_path_buf(), stacks: index.stacks.len(), read, reused })
+}
+
+// A walk of a tree, taking what it can from an index and recording what it
+// finds in a new one
+struct Scan<'a> {
+    layout: &'a dyn BakLayout,
+    previous: Option<&'a Index>,
+    index: Index,
+    found: Vec<FoundStack>,
+    read: usize,
+    reused: usize,
+}
+
+impl<'a> Scan<'a> {
+    fn new(layout: &'a dyn BakLayout, previous: Option<&'a Index>, path: PathBuf) -> Scan<'a> {
+        Scan { layout, previous, index: Index::new(path), found: Vec::new(), read: 0, reused: 0 }
+    }
+
+    fn visit(&mut self, dir: &Path) -> Result<(), BBError> {
+        let stamp = Stamp::of(dir)?;
+        self.index.dirs.insert(dir.to_path_buf(), stamp);
+        if let Some(previous) = self.previous {
+            if previous.dirs.get(dir).is_some_and(|recorded| previous.trusts(recorded, &stamp)) {
+                let (dirs, stacks) = previous.children(dir);
+                for dir in dirs {
+                    self.visit(&dir)?;
+                }
+                for stack in stacks {
+                    self.stack(stack)?;
+                }
+                return Ok(());
+            }
+        }
+        let entries = fs::read_dir(dir)
+                      .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+                      .map_err(|e| BBError::IoError(format!("unable to read {}: {}", dir.display(), e)))?;
+        for entry in entries {
+            let file_type = entry.file_type()
+                                 .map_err(|e| BBError::IoError(format!("unable to stat {}: {}", entry.path().display(), e)))?;
+            if file_type.is_dir() {
+                if entry.file_name() != INDEX_DIR {
+                    self.visit(&entry.path())?;
+                }
+            } else if file_type.is_file() {
+                self.stack(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stack(&mut self, stack: PathBuf) -> Result<(), BBError> {
+        if !self.layout.is_stack_path(&stack) {
+            return Ok(());
+        }
+        let live = match self.layout.live_path(&stack) {
+            Ok(live) => live,
+            Err(e) => {
+                warn!("skipping {}: {}", stack.display(), e);
+                return Ok(());
+            },
+        };
+        let stamp = Stamp::of(&stack)?;
+        let indexed = self.previous
+                          .and_then(|previous| previous.stacks.get(&stack).filter(|i| previous.trusts(&i.stamp, &stamp)))
+                          .map(|indexed| indexed.history.clone());
+        if indexed.is_some() {
+            self.reused += 1;
+        }
+        self.found.push(FoundStack { target: SwinstalledFile { live, stack }, stamp, indexed });
+        Ok(())
+    }
+
+    // the index of everything found, reading the stacks the previous index did
+    // not hold, and the number read
+    fn into_index(mut self) -> Result<(Index, usize), BBError> {
+        for found in self.found {
+            let history = match found.indexed {
+                Some(history) => history,
+                None => {
+                    self.read += 1;
+                    read_stack_history(&found.target.stack)?
+                },
+            };
+            self.index.stacks.insert(found.target.stack, IndexedStack { stamp: found.stamp, history });
+        }
+        Ok((self.index, self.read))
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+fn parse_time(s: &str) -> Option<SystemTime> {
+    let (secs, nanos) = s.split_once('.')?;
+    Some(UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+fn parse_stamp(mtime: &str, size: &str) -> Option<Stamp> {
+    Some(Stamp { mtime: parse_time(mtime)?, size: size.parse().ok()? })
+}
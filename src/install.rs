@@ -0,0 +1,364 @@
+//! install
+//!
+//! Installs files the way swinstall does, so that bakbuster and swinstall can
+//! manage the same files. The source is copied to a new backup, the stack gains
+//! an entry marked current, and finally the live file is replaced.
+//!
+//! Each file is written beside its destination and renamed into place, so a
+//! reader never sees a partially written backup, stack or live file. A replaced
+//! file keeps the mode and ownership of the file it replaces. Where only root
+//! could keep them, as for a group writable file owned by another user, the
+//! file is rewritten in place instead; readers of stacks retry a stack read
+//! part way through such a rewrite.
+use chrono::{NaiveDateTime, Timelike};
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile, SwinstallLayout};
+use lock::{holder_id, lock_stack, LockOptions};
+use metadata::{
+    apply_metadata, check_can_replace, replacement_metadata, replaces_in_place, report_mismatches, FileMetadata, Ownership
+};
+use oplog::Operation;
+use paths::os_concat;
+use script::Script;
+use stack_history_parser::{read_stack_history, write_stack_history, StackHistory};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// distinguishes the temporary files of this process
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The result of a successful install
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Installed {
+    /// The installed file and its stack
+    pub target: SwinstalledFile,
+    /// The version recorded in the stack
+    pub version: FileVersion,
+    /// The backup holding the installed contents
+    pub backup: PathBuf,
+}
+
+/// Install `source` as the swinstalled file `dest`, recording the install at
+/// `datetime`, a facility local time, with an optional revision.
+pub fn install<S: AsRef<Path>, D: AsRef<Path>>(
+    source: S,
+    dest: D,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+) -> Result<Installed, BBError> {
+    install_with_layout(source, dest, datetime, revision, &SwinstallLayout::default())
+}
+
+/// As `install`, locating the stack and backups using `layout`
+pub fn install_with_layout<S: AsRef<Path>, D: AsRef<Path>>(
+    source: S,
+    dest: D,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+    layout: &dyn BakLayout,
+) -> Result<Installed, BBError> {
+    install_with_options(source, dest, datetime, revision, layout, &LockOptions::default())
+}
+
+/// As `install_with_layout`, taking the stack's lock as `lock` describes. The
+/// lock is held from reading the stack until the live file is replaced.
+pub fn install_with_options<S: AsRef<Path>, D: AsRef<Path>>(
+    source: S,
+    dest: D,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+    layout: &dyn BakLayout,
+    lock: &LockOptions,
+) -> Result<Installed, BBError> {
+    let options = InstallOptions { lock: lock.clone(), ..InstallOptions::default() };
+    install_logged(source.as_ref(), dest.as_ref(), datetime, revision, layout, &options, None)
+}
+
+/// As `install_with_options`, recording the install in `operation` so that it
+/// may be undone, and giving the live file any mode and ownership set in
+/// `ownership`. The caller commits the operation once the install succeeds.
+pub fn install_recorded<S: AsRef<Path>, D: AsRef<Path>>(
+    source: S,
+    dest: D,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+    layout: &dyn BakLayout,
+    options: &InstallOptions,
+    operation: &mut Operation,
+) -> Result<Installed, BBError> {
+    install_logged(source.as_ref(), dest.as_ref(), datetime, revision, layout, options, Some(operation))
+}
+
+/// How an install is performed
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct InstallOptions {
+    pub lock: LockOptions,
+    /// Mode and ownership for the live file, overriding those preserved
+    pub ownership: Ownership,
+}
+
+fn install_logged(
+    source: &Path,
+    dest: &Path,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+    layout: &dyn BakLayout,
+    options: &InstallOptions,
+    operation: Option<&mut Operation>,
+) -> Result<Installed, BBError> {
+    let (target, version) = install_target(source, dest, datetime, revision, layout)?;
+
+    // check permissions before touching anything
+    let live_metadata = replacement_metadata(source, &target.live, &options.ownership)?;
+    check_can_replace(&target.live, &live_metadata)?;
+    if target.stack.exists() {
+        check_can_replace(&target.stack, &FileMetadata::of(&target.stack)?)?;
+    }
+
+    let bak_dir = target.stack.parent()
+                        .ok_or(BBError::ConversionError(format!("Unable to get parent of '{:?}'", target.stack)))?;
+    fs::create_dir_all(bak_dir)
+        .map_err(|e| BBError::IoError(format!("unable to create {}: {}", bak_dir.display(), e)))?;
+    let _lock = lock_stack(&target.stack, &options.lock)?;
+    let (mut history, backup) = history_for_install(&target, &version)?;
+
+    if let Some(operation) = operation {
+        let stack_before = Some(target.stack.as_path()).filter(|p| p.exists());
+        let live_before = Some(target.live.as_path()).filter(|p| p.exists());
+        operation.record(&target, history.current().map(|e| e.version.clone()), version.clone(),
+                         stack_before, live_before, vec![backup.clone()])?;
+    }
+    copy_atomically(source, &backup)?;
+    let previous = if target.stack.exists() { Some(history.clone()) } else { None };
+    history.push_current(version.clone());
+    if let Err(e) = write_atomically(&target.stack, |output| write_stack_history(&history, output)) {
+        remove_backup(&backup);
+        return Err(e);
+    }
+    let replaced = report_mismatches(&backup, &target.live)
+        .and_then(|_| copy_atomically_as(source, &target.live, &live_metadata));
+    if let Err(e) = replaced {
+        roll_back_install(&target, previous.as_ref(), &backup);
+        return Err(e);
+    }
+    debug!("installed {} ({})", target.live.display(), version);
+
+    Ok(Installed { target, version, backup })
+}
+
+// Undo an install whose stack was written but whose live file could not be
+// replaced, so that the stack does not claim an install which never happened:
+// put back the stack as it was, or remove it if there was none, and remove
+// the new backup
+fn roll_back_install(target: &SwinstalledFile, previous: Option<&StackHistory>, backup: &Path) {
+    let restored = match previous {
+        Some(previous) => write_atomically(&target.stack, |output| write_stack_history(previous, output)),
+        None => fs::remove_file(&target.stack)
+                    .map_err(|e| BBError::IoError(format!("unable to remove {}: {}", target.stack.display(), e))),
+    };
+    if let Err(e) = restored {
+        warn!("unable to put back {} after a failed install: {}", target.stack.display(), e);
+    }
+    remove_backup(backup);
+}
+
+fn remove_backup(backup: &Path) {
+    if let Err(e) = fs::remove_file(backup) {
+        warn!("unable to remove {} after a failed install: {}", backup.display(), e);
+    }
+}
+
+/// A script performing the install `install_recorded` would, for review. The
+/// script refuses to run if the source, stack, backup or live file has changed
+/// since it was generated.
+pub fn install_script<S: AsRef<Path>, D: AsRef<Path>>(
+    source: S,
+    dest: D,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+    layout: &dyn BakLayout,
+    ownership: &Ownership,
+) -> Result<Script, BBError> {
+    let source = source.as_ref();
+    let (target, version) = install_target(source, dest.as_ref(), datetime, revision, layout)?;
+    let (mut history, backup) = history_for_install(&target, &version)?;
+    let live_metadata = replacement_metadata(source, &target.live, ownership)?;
+    let stack_metadata = if target.stack.exists() { Some(FileMetadata::of(&target.stack)?) } else { None };
+    report_mismatches(source, &target.live)?;
+
+    let mut script = Script::new();
+    script.describe(format!("install {} as {} ({})", source.display(), target.live.display(), version));
+    for path in &[source, &target.stack, &backup, &target.live] {
+        script.guard(path)?;
+    }
+    if let Some(bak_dir) = target.stack.parent() {
+        script.mkdir(bak_dir);
+    }
+    script.copy(source, &backup, &FileMetadata::of(source)?, false);
+    history.push_current(version);
+    script.write(&target.stack, &stack_history_text(&history)?, stack_metadata.as_ref());
+    let chown = target.live.exists() || ownership.uid.is_some() || ownership.gid.is_some();
+    script.copy(source, &target.live, &live_metadata, chown);
+    Ok(script)
+}
+
+/// A stack history as written to a stack file
+pub fn stack_history_text(history: &StackHistory) -> Result<String, BBError> {
+    let mut contents = Vec::new();
+    write_stack_history(history, &mut contents)?;
+    String::from_utf8(contents).map_err(|e| BBError::ConversionError(format!("stack history is not UTF-8: {}", e)))
+}
+
+// The file an install of `source` to `dest` targets, and the version it records
+fn install_target(
+    source: &Path,
+    dest: &Path,
+    datetime: &NaiveDateTime,
+    revision: Option<&str>,
+    layout: &dyn BakLayout,
+) -> Result<(SwinstalledFile, FileVersion), BBError> {
+    if !source.is_file() {
+        return Err(BBError::NonExtantPath(source.display().to_string()));
+    }
+    let target = SwinstalledFile::new(dest, layout)?;
+    // stack timestamps have a resolution of one second
+    let date_time = datetime.with_nanosecond(0).unwrap_or(*datetime);
+    Ok((target, FileVersion::new(date_time, revision.map(|r| r.to_string()))))
+}
+
+// The target's history, or a new one, and the backup an install of `version`
+// writes, refusing an install which collides with an earlier one
+fn history_for_install(target: &SwinstalledFile, version: &FileVersion) -> Result<(StackHistory, PathBuf), BBError> {
+    let history = if target.stack.exists() {
+        read_stack_history(&target.stack)?
+    } else {
+        StackHistory { path: Some(target.stack.to_string_lossy().into_owned()), entries: Vec::new() }
+    };
+    if history.entries.iter().any(|e| e.version == *version) {
+        return Err(BBError::IoError(format!(
+            "{} already has an install at {}; installs must be at least a second apart", target.live.display(), version)));
+    }
+    let backup = target.backup_path(version)?;
+    if backup.exists() {
+        return Err(BBError::IoError(format!("{} already exists", backup.display())));
+    }
+    Ok((history, backup))
+}
+
+/// Copy `source` to `dest`, by way of a temporary file beside `dest` which is
+/// renamed over it, or in place where only that keeps `dest`'s ownership. The
+/// copy keeps the mode and ownership of any file it replaces, and the
+/// modification time of `source`.
+pub fn copy_atomically(source: &Path, dest: &Path) -> Result<(), BBError> {
+    copy_atomically_as(source, dest, &replacement_metadata(source, dest, &Ownership::default())?)
+}
+
+/// As `copy_atomically`, giving the copy `metadata`
+pub fn copy_atomically_as(source: &Path, dest: &Path, metadata: &FileMetadata) -> Result<(), BBError> {
+    if replaces_in_place(dest, metadata)? {
+        return copy_in_place(source, dest, metadata);
+    }
+    let (temp, mut output) = create_temp_beside(dest)?;
+    let result = File::open(source)
+        .and_then(|mut input| io::copy(&mut input, &mut output))
+        .map_err(|e| BBError::IoError(format!("unable to copy {} to {}: {}", source.display(), temp.display(), e)))
+        .and_then(|_| {
+            drop(output);
+            apply_metadata(&temp, metadata)
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    rename(&temp, dest)
+}
+
+/// Write `dest` using `write`, by way of a temporary file beside `dest` which
+/// is renamed over it once written and synced, or in place where only that
+/// keeps `dest`'s ownership. The new file keeps the mode and ownership of any
+/// file it replaces.
+pub fn write_atomically<F>(dest: &Path, write: F) -> Result<(), BBError>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), BBError>,
+{
+    let preserved = if dest.exists() { Some(FileMetadata { mtime: None, ..FileMetadata::of(dest)? }) } else { None };
+    if let Some(ref preserved) = preserved {
+        if replaces_in_place(dest, preserved)? {
+            let mut contents = Vec::new();
+            write(&mut contents)?;
+            return rewrite_in_place(dest, |output| io::copy(&mut contents.as_slice(), output).map(|_| ()))
+                .map(|_| ());
+        }
+    }
+    let (temp, file) = create_temp_beside(dest)?;
+    let mut output = BufWriter::new(file);
+    let result = write(&mut output)
+        .and_then(|_| output.into_inner().map_err(|e| BBError::IoError(format!("unable to write {}: {}", temp.display(), e))))
+        .and_then(|file| file.sync_all().map_err(|e| BBError::IoError(format!("unable to sync {}: {}", temp.display(), e))))
+        .and_then(|_| match preserved {
+            Some(ref metadata) => apply_metadata(&temp, metadata),
+            None => Ok(()),
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    rename(&temp, dest)
+}
+
+// Copy `source` over `dest` in place, keeping `dest`'s mode and ownership. The
+// modification time can only be set by the file's owner, so is otherwise left
+// as the time of the copy.
+fn copy_in_place(source: &Path, dest: &Path, metadata: &FileMetadata) -> Result<(), BBError> {
+    let mut input = File::open(source)
+                    .map_err(|e| BBError::IoError(format!("unable to read {}: {}", source.display(), e)))?;
+    let file = rewrite_in_place(dest, |output| io::copy(&mut input, output).map(|_| ()))?;
+    if let Some(mtime) = metadata.mtime {
+        if let Err(e) = file.set_modified(mtime) {
+            debug!("unable to set modification time of {}: {}", dest.display(), e);
+        }
+    }
+    Ok(())
+}
+
+// Truncate `dest` and write it using `write`, syncing it before returning it
+fn rewrite_in_place<F>(dest: &Path, write: F) -> Result<File, BBError>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    debug!("rewriting {} in place to keep its ownership", dest.display());
+    let mut file = OpenOptions::new().write(true).truncate(true).open(dest)
+                   .map_err(|e| BBError::IoError(format!("unable to open {}: {}", dest.display(), e)))?;
+    write(&mut file)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| BBError::IoError(format!("unable to rewrite {}: {}", dest.display(), e)))?;
+    Ok(file)
+}
+
+fn rename(temp: &Path, dest: &Path) -> Result<(), BBError> {
+    fs::rename(temp, dest).map_err(|e| {
+        let _ = fs::remove_file(temp);
+        BBError::IoError(format!("unable to rename {} to {}: {}", temp.display(), dest.display(), e))
+    })
+}
+
+// Create a new hidden file in the same directory, so the rename cannot cross
+// filesystems. The name holds the host, pid and a count, as hosts sharing the
+// directory over NFS may share pids, and the file is created exclusively so a
+// file left by a crashed process is never reused.
+fn create_temp_beside(path: &Path) -> Result<(PathBuf, File), BBError> {
+    let name = path.file_name()
+                   .ok_or(BBError::ConversionError(format!("Unable to get filename from '{:?}'", path)))?;
+    loop {
+        let count = TEMP_COUNT.fetch_add(1, Ordering::SeqCst);
+        let temp = path.with_file_name(os_concat(os_concat(".", name), format!(".bakbuster-{}-{}", holder_id(), count)));
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(BBError::IoError(format!("unable to create {}: {}", temp.display(), e))),
+        }
+    }
+}
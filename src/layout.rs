@@ -0,0 +1,262 @@
+//! layout
+//!
+//! Describes where the history of a swinstalled file lives. The swinstall layout
+//! keeps a `bak` directory alongside the live file; the central root layout
+//! mirrors the live tree beneath another directory.
+//!
+//! Whatever the layout, backups live in the same directory as the stack file and
+//! are named `<live file name>.<FileVersion>`.
+use errors::BBError;
+use fileversionparser::FileVersion;
+use paths::{os_concat, os_strip_prefix, os_strip_suffix};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The default name of the directory holding backups in the swinstall layout
+pub const DEFAULT_BAK_DIR: &str = "bak";
+/// The default suffix appended to the live file name to name its stack file
+pub const DEFAULT_STACK_SUFFIX: &str = "_swinstall_stack";
+
+/// Maps a live file to its stack file and backups, and back again.
+pub trait BakLayout: fmt::Debug {
+    /// The stack file recording the history of the live file
+    fn stack_path(&self, live: &Path) -> Result<PathBuf, BBError>;
+
+    /// The live file which a stack file or backup belongs to
+    fn live_path(&self, bak_path: &Path) -> Result<PathBuf, BBError>;
+
+    /// The suffix appended to the live file name to name its stack file
+    fn stack_suffix(&self) -> &str;
+
+    /// The directory to search for the stack files of live files beneath `root`
+    fn search_root(&self, root: &Path) -> Result<PathBuf, BBError>;
+
+    /// The backup of the live file at `version`
+    fn backup_path(&self, live: &Path, version: &FileVersion) -> Result<PathBuf, BBError> {
+        let stack = self.stack_path(live)?;
+        Ok(backup_beside(&stack, &file_name(live)?, version))
+    }
+
+    /// True if the path names a stack file, judging by its name and directory
+    fn is_stack_path(&self, path: &Path) -> bool {
+        name_matches_stack(path, self.stack_suffix())
+    }
+}
+
+/// The layout used by swinstall:
+///
+/// ```text,ignore
+/// ./foo.yaml
+/// ./bak/foo.yaml/foo.yaml_swinstall_stack
+/// ./bak/foo.yaml/foo.yaml.20181105-103813
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SwinstallLayout {
+    /// Name of the directory holding backups, alongside the live file
+    pub bak_dir: String,
+    /// Suffix appended to the live file name to name its stack file
+    pub stack_suffix: String,
+}
+
+impl Default for SwinstallLayout {
+    fn default() -> SwinstallLayout {
+        SwinstallLayout {
+            bak_dir: DEFAULT_BAK_DIR.to_string(),
+            stack_suffix: DEFAULT_STACK_SUFFIX.to_string(),
+        }
+    }
+}
+
+impl BakLayout for SwinstallLayout {
+    fn stack_path(&self, live: &Path) -> Result<PathBuf, BBError> {
+        let filename = file_name(live)?;
+        let mut pb = live.to_path_buf();
+        // remove filename
+        pb.pop();
+        pb.push(&self.bak_dir);
+        pb.push(&filename);
+        pb.push(os_concat(&filename, &self.stack_suffix));
+        Ok(pb)
+    }
+
+    fn live_path(&self, bak_path: &Path) -> Result<PathBuf, BBError> {
+        // bak_path is <parent>/<bak_dir>/<name>/<name><suffix or .version>
+        let name_dir = parent(bak_path)?;
+        let bak_dir = parent(name_dir)?;
+        if bak_dir.file_name() != Some(OsStr::new(&self.bak_dir)) {
+            return Err(BBError::ConversionError(
+                format!("{} is not within a '{}' directory", bak_path.display(), self.bak_dir)));
+        }
+        let name = file_name(name_dir)?;
+        check_belongs_to(bak_path, &name, &self.stack_suffix)?;
+        let mut live = bak_dir.to_path_buf();
+        live.pop();
+        Ok(live.join(name))
+    }
+
+    fn stack_suffix(&self) -> &str {
+        &self.stack_suffix
+    }
+
+    fn search_root(&self, root: &Path) -> Result<PathBuf, BBError> {
+        Ok(root.to_path_buf())
+    }
+
+    fn is_stack_path(&self, path: &Path) -> bool {
+        let in_bak_dir = path.parent()
+                             .and_then(|p| p.parent())
+                             .and_then(|p| p.file_name()) == Some(OsStr::new(&self.bak_dir));
+        in_bak_dir && name_matches_stack(path, &self.stack_suffix)
+    }
+}
+
+/// A layout which mirrors the live tree beneath a central bak root:
+///
+/// ```text,ignore
+/// <live_root>/etc/foo.yaml
+/// <bak_root>/etc/foo.yaml/foo.yaml_swinstall_stack
+/// <bak_root>/etc/foo.yaml/foo.yaml.20181105-103813
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CentralRootLayout {
+    /// Root of the live tree
+    pub live_root: PathBuf,
+    /// Root of the mirrored tree holding stacks and backups
+    pub bak_root: PathBuf,
+    /// Suffix appended to the live file name to name its stack file
+    pub stack_suffix: String,
+}
+
+impl CentralRootLayout {
+    pub fn new<L: Into<PathBuf>, B: Into<PathBuf>>(live_root: L, bak_root: B) -> CentralRootLayout {
+        CentralRootLayout {
+            live_root: live_root.into(),
+            bak_root: bak_root.into(),
+            stack_suffix: DEFAULT_STACK_SUFFIX.to_string(),
+        }
+    }
+}
+
+impl BakLayout for CentralRootLayout {
+    fn stack_path(&self, live: &Path) -> Result<PathBuf, BBError> {
+        let relative = live.strip_prefix(&self.live_root).map_err(|_| {
+            BBError::ConversionError(format!("{} is not beneath {}", live.display(), self.live_root.display()))
+        })?;
+        let filename = file_name(live)?;
+        Ok(self.bak_root.join(relative).join(os_concat(&filename, &self.stack_suffix)))
+    }
+
+    fn live_path(&self, bak_path: &Path) -> Result<PathBuf, BBError> {
+        // bak_path is <bak_root>/<relative dir>/<name>/<name><suffix or .version>
+        let name_dir = parent(bak_path)?;
+        let relative = name_dir.strip_prefix(&self.bak_root).map_err(|_| {
+            BBError::ConversionError(format!("{} is not beneath {}", bak_path.display(), self.bak_root.display()))
+        })?;
+        check_belongs_to(bak_path, &file_name(name_dir)?, &self.stack_suffix)?;
+        Ok(self.live_root.join(relative))
+    }
+
+    fn stack_suffix(&self) -> &str {
+        &self.stack_suffix
+    }
+
+    fn search_root(&self, root: &Path) -> Result<PathBuf, BBError> {
+        let relative = root.strip_prefix(&self.live_root).map_err(|_| {
+            BBError::ConversionError(format!("{} is not beneath {}", root.display(), self.live_root.display()))
+        })?;
+        Ok(self.bak_root.join(relative))
+    }
+}
+
+/// A swinstalled file along with its stack file
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct SwinstalledFile {
+    /// The live file. It may no longer exist.
+    pub live: PathBuf,
+    /// The stack file recording the history of the live file
+    pub stack: PathBuf,
+}
+
+impl SwinstalledFile {
+    /// Locate the stack file of the live file using the supplied layout
+    pub fn new<P: Into<PathBuf>>(live: P, layout: &dyn BakLayout) -> Result<SwinstalledFile, BBError> {
+        let live = live.into();
+        let stack = layout.stack_path(&live)?;
+        Ok(SwinstalledFile { live, stack })
+    }
+
+    /// The backup of the live file at `version`, which lives beside the stack
+    pub fn backup_path(&self, version: &FileVersion) -> Result<PathBuf, BBError> {
+        Ok(backup_beside(&self.stack, &file_name(&self.live)?, version))
+    }
+}
+
+fn backup_beside(stack: &Path, name: &OsStr, version: &FileVersion) -> PathBuf {
+    stack.with_file_name(os_concat(name, format!(".{}", version)))
+}
+
+// File names are kept as OsStrings, as legacy files may not be named in UTF-8
+fn file_name(path: &Path) -> Result<OsString, BBError> {
+    path.file_name()
+        .map(|name| name.to_os_string())
+        .ok_or(BBError::ConversionError(format!("Unable to get filename from '{:?}'", path)))
+}
+
+fn parent(path: &Path) -> Result<&Path, BBError> {
+    path.parent().ok_or(BBError::ConversionError(format!("Unable to get parent of '{:?}'", path)))
+}
+
+// A stack file is named <name><suffix>, within a directory named <name>
+fn name_matches_stack(path: &Path, suffix: &str) -> bool {
+    let name = match path.file_name().and_then(|n| os_strip_suffix(n, suffix)) {
+        Some(name) => name,
+        None => return false,
+    };
+    path.parent().and_then(|p| p.file_name()) == Some(name.as_os_str())
+}
+
+// A stack or backup must be named <name><suffix> or <name>.<version>
+fn check_belongs_to(bak_path: &Path, name: &OsStr, suffix: &str) -> Result<(), BBError> {
+    let bak_name = file_name(bak_path)?;
+    if bak_name == os_concat(name, suffix) || os_strip_prefix(&bak_name, os_concat(name, ".")).is_some() {
+        Ok(())
+    } else {
+        Err(BBError::ConversionError(
+            format!("{} does not belong to {}", bak_path.display(), Path::new(name).display())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn swinstall_layout_round_trips() {
+        let layout = SwinstallLayout::default();
+        let live = Path::new("/dd/facility/etc/packages.xml");
+        let stack = layout.stack_path(live).unwrap();
+        assert_eq!(stack, PathBuf::from("/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack"));
+        assert!(layout.is_stack_path(&stack));
+        assert_eq!(layout.live_path(&stack).unwrap(), live);
+
+        let version = FileVersion::from_str("20181105-103813").unwrap();
+        let backup = layout.backup_path(live, &version).unwrap();
+        assert_eq!(backup, PathBuf::from("/dd/facility/etc/bak/packages.xml/packages.xml.20181105-103813"));
+        assert_eq!(layout.live_path(&backup).unwrap(), live);
+    }
+
+    #[test]
+    fn central_root_layout_round_trips() {
+        let mut layout = CentralRootLayout::new("/shows/foo", "/bakroot/foo");
+        layout.stack_suffix = "_stack".to_string();
+        let live = Path::new("/shows/foo/etc/foo_preference.yaml");
+        let stack = layout.stack_path(live).unwrap();
+        assert_eq!(stack, PathBuf::from("/bakroot/foo/etc/foo_preference.yaml/foo_preference.yaml_stack"));
+        assert!(layout.is_stack_path(&stack));
+        assert_eq!(layout.live_path(&stack).unwrap(), live);
+        assert_eq!(layout.search_root(Path::new("/shows/foo/etc")).unwrap(), PathBuf::from("/bakroot/foo/etc"));
+        assert!(layout.stack_path(Path::new("/shows/bar/etc/x.yaml")).is_err());
+    }
+}
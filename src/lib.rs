@@ -5,16 +5,55 @@ extern crate log;
 extern crate pest;
 extern crate chrono;
 extern crate xml;
+extern crate regex;
+extern crate toml;
+extern crate chrono_tz;
+extern crate libc;
+extern crate sha2;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use] extern crate serde_derive;
 
 pub mod fileversionparser;
 pub mod errors;
 pub mod stack_history_parser;
 pub mod utils;
 pub mod constants;
+pub mod packages;
+pub mod diff;
+pub mod blame;
+pub mod scan;
+pub mod grep;
+pub mod history;
+pub mod versioned_file;
+pub mod output;
+pub mod layout;
+pub mod whois;
+pub mod config;
+pub mod paths;
+pub mod timezone;
+pub mod install;
+pub mod lock;
+pub mod metadata;
+pub mod oplog;
+pub mod restore;
+pub mod script;
+pub mod listing;
+pub mod changes;
+pub mod changeset;
+pub mod index;
 
 pub use fileversionparser::FileVersion;
 pub use errors::BBError;
-pub use stack_history_parser::{get_file_version_on, stack_history_from_path};
+pub use stack_history_parser::{
+    get_file_version_on, stack_history_from_path, canonical_stack_history_from_path,
+    resolve_stack_history_from_path, backup_path_for, live_path_for,
+    parse_stack_history, read_stack_history, write_stack_history, StackHistory, StackEntry, ResolvePolicy
+};
+pub use versioned_file::{open_as_of, open_as_of_with_policy, open_as_of_with_layout, VersionedFile};
+pub use paths::{expand_path, normalize_path, resolve_path, ParentResolution, ResolvedPath, FollowedLink};
+pub use layout::{BakLayout, SwinstallLayout, CentralRootLayout, SwinstalledFile};
 pub use constants::{CTIMEFMT, STDTIMEFMT, BAKTIMEFMT};
 
 pub mod prelude {
@@ -0,0 +1,72 @@
+//! listing
+//!
+//! Lists the swinstalled files of a directory as of a datetime. The listing is
+//! built from the stacks beneath the directory rather than the live tree, so it
+//! includes files which have since been removed but whose stacks remain.
+use chrono::NaiveDateTime;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile};
+use index::find_stacks;
+use stack_history_parser::ResolvePolicy;
+use std::fmt;
+use std::path::Path;
+
+/// Whether a file existed at the listing's datetime, and whether it exists now
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ListedStatus {
+    /// Installed by then, and the live file exists now
+    Present,
+    /// Not yet installed by then
+    AbsentYet,
+    /// Installed by then, but the live file has since been removed
+    SinceDeleted,
+}
+
+impl fmt::Display for ListedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ListedStatus::Present => write!(f, "present"),
+            ListedStatus::AbsentYet => write!(f, "absent-yet"),
+            ListedStatus::SinceDeleted => write!(f, "since-deleted"),
+        }
+    }
+}
+
+/// A file in a listing
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ListedFile {
+    pub target: SwinstalledFile,
+    pub status: ListedStatus,
+    /// The install in place at the listing's datetime, if any
+    pub version: Option<FileVersion>,
+}
+
+/// List the swinstalled files in `dir`, or beneath it if `recursive`, as of
+/// `datetime`, sorted by live path. Files not yet installed by then are
+/// included, as `AbsentYet`.
+pub fn list_at<P: AsRef<Path>>(
+    dir: P,
+    datetime: &NaiveDateTime,
+    policy: ResolvePolicy,
+    recursive: bool,
+    layout: &dyn BakLayout,
+) -> Result<Vec<ListedFile>, BBError> {
+    let dir = dir.as_ref();
+    let mut listing = Vec::new();
+    for found in find_stacks(dir, layout)? {
+        if !recursive && found.target.live.parent() != Some(dir) {
+            continue;
+        }
+        let target = found.target.clone();
+        let history = found.into_history()?;
+        let version = history.resolve(datetime, policy).map(|e| e.version.clone());
+        let status = match version {
+            None => ListedStatus::AbsentYet,
+            Some(_) if target.live.exists() => ListedStatus::Present,
+            Some(_) => ListedStatus::SinceDeleted,
+        };
+        listing.push(ListedFile { target, status, version });
+    }
+    Ok(listing)
+}
@@ -0,0 +1,315 @@
+//! lock
+//!
+//! Advisory locking of swinstall stacks, so that concurrent installs cannot
+//! interleave their updates. Writers hold the lock while reading, modifying and
+//! replacing a stack; readers do not lock, as stacks are replaced atomically.
+//!
+//! The lock is an `flock` on `<stack>.lock` together with a lockfile,
+//! `<stack>.lck`, created exclusively. Where `flock` is unsupported, as on some
+//! NFS mounts, the lockfile alone is taken. As every client takes the lockfile,
+//! clients which can `flock` and clients which cannot exclude one another.
+//!
+//! The lockfile records its holder as `pid@hostname`, so that a lock left by a
+//! process which has died on this host can be detected and broken. Breaking a
+//! lock is itself done under a lock, `<stack>.lck.break`, within which the
+//! holder is read again, so that two waiters cannot both break the same lock
+//! and remove one another's.
+use errors::BBError;
+use paths::os_concat;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default time to wait for a lock
+pub static DEFAULT_LOCK_TIMEOUT_SECS: u64 = 10;
+
+// how long to wait between attempts to take a lock
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// breaking a lock takes moments, so a break lock older than this was left by a
+// process which died while breaking
+const ABANDONED_BREAK: Duration = Duration::from_secs(10);
+
+/// How a stack is locked
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum LockMethod {
+    /// `flock`, falling back to a lockfile where the filesystem does not support it
+    #[default]
+    Auto,
+    /// `flock` only
+    Flock,
+    /// An exclusively created lockfile only
+    LockFile,
+}
+
+impl FromStr for LockMethod {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(LockMethod::Auto),
+            "flock" => Ok(LockMethod::Flock),
+            "lockfile" => Ok(LockMethod::LockFile),
+            _ => Err(BBError::ParseError(format!("Unable to parse lock method: '{}' (expected auto, flock or lockfile)", s))),
+        }
+    }
+}
+
+/// How to take a lock
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LockOptions {
+    pub method: LockMethod,
+    /// How long to wait for another holder to release the lock
+    pub timeout: Duration,
+}
+
+impl Default for LockOptions {
+    fn default() -> LockOptions {
+        LockOptions { method: LockMethod::Auto, timeout: Duration::from_secs(DEFAULT_LOCK_TIMEOUT_SECS) }
+    }
+}
+
+/// An exclusive lock on a stack, released when dropped
+#[derive(Debug)]
+pub struct StackLock {
+    lockfile: PathBuf,
+    // the flocked file, if flock is in use, held open to hold the flock
+    _flocked: Option<File>,
+}
+
+impl StackLock {
+    /// The lockfile which records the holder
+    pub fn path(&self) -> &Path {
+        &self.lockfile
+    }
+}
+
+impl Drop for StackLock {
+    fn drop(&mut self) {
+        // the lockfile is removed while the flock, released when the file is
+        // closed after this, is still held
+        if let Err(e) = fs::remove_file(&self.lockfile) {
+            warn!("unable to remove lockfile {}: {}", self.lockfile.display(), e);
+        }
+    }
+}
+
+/// Take an exclusive lock on `stack`, waiting up to the configured timeout.
+/// The stack's directory must exist; the stack itself need not.
+pub fn lock_stack(stack: &Path, options: &LockOptions) -> Result<StackLock, BBError> {
+    let deadline = Instant::now() + options.timeout;
+    let flock_path = stack.with_file_name(os_concat(file_name(stack)?, ".lock"));
+    let lockfile_path = stack.with_file_name(os_concat(file_name(stack)?, ".lck"));
+    loop {
+        let attempt = match options.method {
+            LockMethod::LockFile => try_lockfile(&lockfile_path),
+            LockMethod::Flock => try_flock(&flock_path, &lockfile_path),
+            LockMethod::Auto => match try_flock(&flock_path, &lockfile_path) {
+                Err(LockError::Unsupported) => {
+                    debug!("flock unsupported for {}, using a lockfile", flock_path.display());
+                    try_lockfile(&lockfile_path)
+                },
+                other => other,
+            },
+        };
+        match attempt {
+            Ok(lock) => return Ok(lock),
+            Err(LockError::Held(holder)) => {
+                if Instant::now() >= deadline {
+                    return Err(BBError::IoError(format!(
+                        "timed out after {}s waiting for the lock on {}, held by {}",
+                        options.timeout.as_secs(), stack.display(), holder.unwrap_or_else(|| "an unknown process".to_string()))));
+                }
+                thread::sleep(POLL_INTERVAL);
+            },
+            Err(LockError::Unsupported) => {
+                return Err(BBError::IoError(format!("flock is not supported for {}; use the lockfile lock method", stack.display())));
+            },
+            Err(LockError::Io(e)) => return Err(e),
+        }
+    }
+}
+
+/// The holder of a lock, as `pid@hostname`
+pub fn holder_id() -> String {
+    format!("{}@{}", process::id(), hostname())
+}
+
+enum LockError {
+    // the lock is held, by the holder if known
+    Held(Option<String>),
+    Unsupported,
+    Io(BBError),
+}
+
+fn try_flock(path: &Path, lockfile_path: &Path) -> Result<StackLock, LockError> {
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+                   .map_err(|e| LockError::Io(io_error("unable to open", path, &e)))?;
+    flock(&file).map_err(|e| match e.raw_os_error() {
+        Some(code) if is_unsupported(code) => LockError::Unsupported,
+        _ if e.kind() == ErrorKind::WouldBlock => LockError::Held(read_holder(path)),
+        _ => LockError::Io(io_error("unable to lock", path, &e)),
+    })?;
+    // take the lockfile too, so that clients without flock are excluded; if it
+    // is held the flock is released as the file is dropped
+    create_lockfile(lockfile_path)?;
+    // record the holder for diagnostics
+    let _ = file.set_len(0).and_then(|_| file.write_all(holder_id().as_bytes()));
+    Ok(StackLock { lockfile: lockfile_path.to_path_buf(), _flocked: Some(file) })
+}
+
+fn try_lockfile(path: &Path) -> Result<StackLock, LockError> {
+    create_lockfile(path)?;
+    Ok(StackLock { lockfile: path.to_path_buf(), _flocked: None })
+}
+
+fn create_lockfile(path: &Path) -> Result<(), LockError> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => file.write_all(holder_id().as_bytes()).map_err(|e| {
+            let _ = fs::remove_file(path);
+            LockError::Io(io_error("unable to write", path, &e))
+        }),
+        // if the lockfile was stale it has been removed, and the next attempt
+        // may succeed
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => Err(LockError::Held(live_lockfile_holder(path))),
+        Err(e) => Err(LockError::Io(io_error("unable to create", path, &e))),
+    }
+}
+
+// The holder of the lockfile at `path`, or None if there is no lockfile or it
+// was left by a process on this host which no longer exists, in which case it
+// is removed.
+fn live_lockfile_holder(path: &Path) -> Option<String> {
+    let holder = read_holder(path)?;
+    if is_stale(&holder) && break_stale_lock(path, &holder) {
+        return None;
+    }
+    Some(holder)
+}
+
+// Remove the lockfile at `path` if it is still held by `stale`. This is done
+// under the break lock, as otherwise a waiter which found the lock stale could
+// remove the lockfile another waiter has just broken and taken. Returns false
+// if another waiter is breaking the lock.
+fn break_stale_lock(path: &Path, stale: &str) -> bool {
+    let break_path = path.with_file_name(os_concat(path.file_name().unwrap_or_default(), ".break"));
+    let created = OpenOptions::new().write(true).create_new(true).open(&break_path);
+    if created.is_err() {
+        let abandoned = fs::metadata(&break_path)
+            .and_then(|m| m.modified())
+            .map(|modified| modified.elapsed().unwrap_or_default() > ABANDONED_BREAK)
+            .unwrap_or(false);
+        if abandoned {
+            warn!("removing abandoned {}", break_path.display());
+            let _ = fs::remove_file(&break_path);
+        }
+        return false;
+    }
+    let broken = read_holder(path).as_deref() == Some(stale);
+    if broken {
+        warn!("breaking stale lock {} held by {}", path.display(), stale);
+        let _ = fs::remove_file(path);
+    }
+    let _ = fs::remove_file(&break_path);
+    broken
+}
+
+fn read_holder(path: &Path) -> Option<String> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut contents)).ok()?;
+    Some(contents.trim().to_string()).filter(|c| !c.is_empty())
+}
+
+// A holder is stale if it names a process on this host which is not running.
+// Holders on other hosts cannot be checked, so are assumed to be live.
+fn is_stale(holder: &str) -> bool {
+    let idx = match holder.find('@') {
+        Some(idx) => idx,
+        None => return false,
+    };
+    match holder[..idx].parse::<u32>() {
+        Ok(pid) => holder[idx + 1..] == hostname() && !process_exists(pid),
+        Err(_) => false,
+    }
+}
+
+fn file_name(path: &Path) -> Result<&::std::ffi::OsStr, BBError> {
+    path.file_name().ok_or(BBError::ConversionError(format!("Unable to get filename from '{:?}'", path)))
+}
+
+fn io_error(action: &str, path: &Path, e: &io::Error) -> BBError {
+    BBError::IoError(format!("{} {}: {}", action, path.display(), e))
+}
+
+#[cfg(unix)]
+fn flock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { ::libc::flock(file.as_raw_fd(), ::libc::LOCK_EX | ::libc::LOCK_NB) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn flock(_file: &File) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(UNSUPPORTED))
+}
+
+#[cfg(unix)]
+fn is_unsupported(code: i32) -> bool {
+    code == ::libc::ENOLCK || code == ::libc::EOPNOTSUPP || code == ::libc::ENOSYS
+}
+
+#[cfg(not(unix))]
+const UNSUPPORTED: i32 = -1;
+
+#[cfg(not(unix))]
+fn is_unsupported(code: i32) -> bool {
+    code == UNSUPPORTED
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    // signal 0 checks for existence; EPERM means it exists but is not ours
+    let signalled = unsafe { ::libc::kill(pid as ::libc::pid_t, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(::libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { ::libc::gethostname(buf.as_mut_ptr() as *mut ::libc::c_char, buf.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    ::std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_stale_holders() {
+        assert!(!is_stale(&holder_id()));
+        assert!(!is_stale("1@some-other-host"));
+        assert!(!is_stale("garbage"));
+        // pids are far below this on every platform we run on
+        assert!(is_stale(&format!("{}@{}", i32::MAX - 1, hostname())));
+    }
+}
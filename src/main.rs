@@ -2,11 +2,392 @@ extern crate bakbuster;
 extern crate chrono;
 #[macro_use] extern crate log;
 extern crate env_logger;
-use chrono::Local;
-//use bakbuster::stack_history_parser::*;
+extern crate structopt;
+extern crate regex;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use env_logger::Env;
-//use bakbuster::utils::*;
 use bakbuster::prelude::*;
+use bakbuster::blame::blame_with_layout;
+use bakbuster::config::{Config, ConfigSource};
+use bakbuster::grep::{grep, pickaxe, GrepOptions};
+use bakbuster::scan::swinstalled_files_for_with_layout;
+use bakbuster::history::{file_log_with_layout, DEFAULT_LOG_FORMAT};
+use bakbuster::output::{render, OutputFormat, Record, FILE_VERSION_COLUMNS};
+use bakbuster::timezone::{parse_query_time, query_time_at, AmbiguityPolicy, QueryTime, LocalTime, Zone};
+use bakbuster::index::{build_index, index_stats, update_index, IndexStats};
+use bakbuster::install::{install_recorded, install_script, InstallOptions};
+use bakbuster::changes::{changes, ChangeKind};
+use bakbuster::diff::run_difftool;
+use bakbuster::changeset::{
+    changed_file_diff, changed_files, changeset_diff, find_changeset, find_changesets, plan_changeset_restore, Changeset, Grouping
+};
+use bakbuster::listing::{list_at, ListedStatus};
+use bakbuster::lock::LockOptions;
+use bakbuster::metadata::{parse_group, parse_mode, parse_owner, Ownership};
+use bakbuster::oplog::{undo, undo_script, OpKind, OpLog, Operation};
+use bakbuster::restore::{
+    abort_restore, apply_restore, default_journal_dir, planned_restore_script, restore, restore_script, resume_restore,
+    RestoreEntry
+};
+use bakbuster::script::Script;
+use bakbuster::whois::whois;
+use bakbuster::packages::{package_history_with_layout, package_version_on_with_layout, PackageCache};
+use regex::Regex;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+// exit code used when the file had no installed version at the requested time
+const EXIT_NO_VERSION: i32 = 2;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "bakbuster", about = "Query the history of swinstalled files")]
+struct Opt {
+    /// Override a configuration value, eg -c layout.kind=central. May be repeated.
+    #[structopt(short = "c", long = "config", number_of_values = 1)]
+    config: Vec<String>,
+    /// Find bak directories beside the logical parent of each file, as written,
+    /// instead of resolving symlinked directories
+    #[structopt(short = "L", long = "logical")]
+    logical: bool,
+    /// Timezone of datetimes given without an offset: local, a tz database name
+    /// or an offset like -08:00. Defaults to the facility timezone.
+    #[structopt(long = "tz")]
+    tz: Option<Zone>,
+    /// How to resolve datetimes which occur twice when clocks go back: reject,
+    /// earliest or latest
+    #[structopt(long = "ambiguous", default_value = "reject")]
+    ambiguous: AmbiguityPolicy,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Report the version of a package listed in packages.xml
+    #[structopt(name = "package")]
+    Package {
+        /// The swinstalled packages.xml to query
+        #[structopt(short = "f", long = "file", default_value = "packages.xml", parse(from_os_str))]
+        file: PathBuf,
+        /// Report the version as of this datetime instead of now
+        #[structopt(long = "at")]
+        at: Option<String>,
+        /// Report every install at which the package version changed
+        #[structopt(long = "history")]
+        history: bool,
+        /// The name of the package
+        name: String,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Annotate each line of a file with the install which introduced it
+    #[structopt(name = "blame")]
+    Blame {
+        /// The swinstalled file to annotate
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Annotate the file as of this datetime instead of now
+        #[structopt(long = "at")]
+        at: Option<String>,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Search every backup of a file, or of every swinstalled file under a root
+    #[structopt(name = "grep")]
+    Grep {
+        /// The regular expression to search for
+        pattern: String,
+        /// A swinstalled file, or a directory to search beneath. Defaults to the
+        /// configured search roots.
+        #[structopt(parse(from_os_str))]
+        file_or_root: Option<PathBuf>,
+        /// Only search installs at or after this datetime
+        #[structopt(long = "since")]
+        since: Option<String>,
+        /// Only search installs at or before this datetime
+        #[structopt(long = "until")]
+        until: Option<String>,
+        /// Only report installs where the number of matches changed
+        #[structopt(short = "S", long = "pickaxe")]
+        pickaxe: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// List every install of a file, newest first
+    #[structopt(name = "log")]
+    Log {
+        /// The swinstalled file
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Only list installs at or after this datetime
+        #[structopt(long = "since")]
+        since: Option<String>,
+        /// Only list installs at or before this datetime
+        #[structopt(long = "until")]
+        until: Option<String>,
+        /// Output format: text, json, tsv, or a template for each entry. Placeholders:
+        /// {version} {timestamp} {revision} {size} {backup} {current} {rolled_back}
+        /// {status} {newest_for}
+        #[structopt(long = "format")]
+        format: Option<String>,
+    },
+    /// Print the contents of a file as of a datetime
+    #[structopt(name = "cat")]
+    Cat {
+        /// The swinstalled file
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Print the file as of this datetime instead of now
+        #[structopt(long = "at")]
+        at: Option<String>,
+        /// Print the install with this revision instead
+        #[structopt(long = "rev", conflicts_with = "at")]
+        rev: Option<String>,
+        /// Which installs to consider: current (ignore rolled back installs) or latest
+        #[structopt(long = "policy", default_value = "current")]
+        policy: ResolvePolicy,
+        /// Explain how the datetime was resolved to an install, on stderr
+        #[structopt(long = "explain")]
+        explain: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Identify the swinstalled file a backup or stack file belongs to
+    #[structopt(name = "whois")]
+    Whois {
+        /// A backup or swinstall stack file within a bak directory
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Install a file, writing a backup and updating its stack as swinstall does
+    #[structopt(name = "install")]
+    Install {
+        /// The file to install
+        #[structopt(parse(from_os_str))]
+        source: PathBuf,
+        /// Where to install it
+        #[structopt(parse(from_os_str))]
+        dest: PathBuf,
+        /// Record this revision with the install
+        #[structopt(long = "rev")]
+        rev: Option<String>,
+        /// Give the live file this mode, in octal, instead of preserving it
+        #[structopt(long = "mode", parse(try_from_str = "parse_mode"))]
+        mode: Option<u32>,
+        /// Give the live file this owner, a user name or uid, instead of preserving it
+        #[structopt(long = "owner", parse(try_from_str = "parse_owner"))]
+        owner: Option<u32>,
+        /// Give the live file this group, a group name or gid, instead of preserving it
+        #[structopt(long = "group", parse(try_from_str = "parse_group"))]
+        group: Option<u32>,
+        /// Print a POSIX shell script making the changes, for review, instead of making them
+        #[structopt(long = "emit-script")]
+        emit_script: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Restore every swinstalled file beneath a root as of a datetime, as one
+    /// transaction which is rolled back if any file fails
+    #[structopt(name = "restore")]
+    Restore {
+        /// The directory to restore
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+        /// Restore each file to its install as of this datetime
+        #[structopt(long = "at")]
+        at: Option<String>,
+        /// Which installs to consider: current (ignore rolled back installs) or latest
+        #[structopt(long = "policy", default_value = "current")]
+        policy: ResolvePolicy,
+        /// Continue an interrupted restore
+        #[structopt(long = "resume", raw(conflicts_with_all = r#"&["abort", "root", "at", "emit_script"]"#))]
+        resume: bool,
+        /// Put back every file changed by an interrupted restore
+        #[structopt(long = "abort", raw(conflicts_with_all = r#"&["root", "at", "emit_script"]"#))]
+        abort: bool,
+        /// Print a POSIX shell script making the changes, for review, instead of making them
+        #[structopt(long = "emit-script")]
+        emit_script: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Undo an install or restore performed by bakbuster
+    #[structopt(name = "undo")]
+    Undo {
+        /// The operation to undo. Defaults to the most recent which has not been undone.
+        op_id: Option<String>,
+        /// List the logged operations instead
+        #[structopt(long = "list", conflicts_with = "op_id")]
+        list: bool,
+        /// Print a POSIX shell script making the changes, for review, instead of making them
+        #[structopt(long = "emit-script", conflicts_with = "list")]
+        emit_script: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// List the swinstalled files in a directory as of a datetime, including
+    /// files since removed from the live tree
+    #[structopt(name = "ls")]
+    Ls {
+        /// The directory to list
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// List the directory as of this datetime instead of now
+        #[structopt(long = "at")]
+        at: Option<String>,
+        /// Which installs to consider: current (ignore rolled back installs) or latest
+        #[structopt(long = "policy", default_value = "current")]
+        policy: ResolvePolicy,
+        /// List files in subdirectories too
+        #[structopt(short = "r", long = "recursive")]
+        recursive: bool,
+        /// Include files not yet installed at the datetime
+        #[structopt(short = "a", long = "all")]
+        all: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// List every install and rollback beneath a root between two datetimes, oldest first
+    #[structopt(name = "changes")]
+    Changes {
+        /// The directory to search beneath. Defaults to the configured search roots.
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+        /// List changes at or after this datetime
+        #[structopt(long = "since")]
+        since: String,
+        /// List changes at or before this datetime
+        #[structopt(long = "until")]
+        until: Option<String>,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// List the changesets beneath a root: installs made together, across files
+    #[structopt(name = "changesets")]
+    Changesets {
+        /// The directory to search beneath. Defaults to the configured search roots.
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+        /// List changesets ending at or after this datetime
+        #[structopt(long = "since")]
+        since: Option<String>,
+        /// List changesets starting at or before this datetime
+        #[structopt(long = "until")]
+        until: Option<String>,
+        /// Group installs by revision or time. Defaults to the configured grouping.
+        #[structopt(long = "by")]
+        by: Option<Grouping>,
+        /// Seconds between installs grouped by time. Defaults to the configured window.
+        #[structopt(long = "window")]
+        window: Option<i64>,
+        /// Print a unified diff of the changeset with this id
+        #[structopt(long = "diff", conflicts_with = "restore")]
+        diff: Option<String>,
+        /// Restore every file to its version in the changeset with this id
+        #[structopt(long = "restore")]
+        restore: Option<String>,
+        /// With --restore, restore every file to its version before the changeset instead
+        #[structopt(long = "before", requires = "restore")]
+        before: bool,
+        /// With --restore, print a POSIX shell script making the changes, for review, instead of making them
+        #[structopt(long = "emit-script", requires = "restore")]
+        emit_script: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Inspect the effective configuration
+    #[structopt(name = "config")]
+    Config(ConfigCommand),
+    /// Manage the history index at the root of a tree, which speeds up ls,
+    /// changes and changesets
+    #[structopt(name = "index")]
+    Index(IndexCommand),
+    /// Manage the log of operations which undo reverses
+    #[structopt(name = "oplog")]
+    Oplog(OplogCommand),
+}
+
+#[derive(StructOpt, Debug)]
+enum IndexCommand {
+    /// Index every stack beneath a root afresh
+    #[structopt(name = "build")]
+    Build {
+        /// The root of the tree to index. Defaults to the configured search roots.
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Bring an index up to date, reading only the stacks which have changed
+    #[structopt(name = "update")]
+    Update {
+        /// The root of the indexed tree. Defaults to the configured search roots.
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+    /// Describe an index and how much of it is stale
+    #[structopt(name = "stats")]
+    Stats {
+        /// The root of the indexed tree. Defaults to the configured search roots.
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum OplogCommand {
+    /// Remove old operations from the log, after which they cannot be undone
+    #[structopt(name = "prune")]
+    Prune {
+        /// Keep this many of the newest operations. Defaults to retention.keep.
+        #[structopt(long = "keep")]
+        keep: Option<u32>,
+        /// Keep operations begun within this many days. Defaults to retention.days.
+        #[structopt(long = "days")]
+        days: Option<u32>,
+        /// Print a POSIX shell script removing the operations, for review, instead of removing them
+        #[structopt(long = "emit-script")]
+        emit_script: bool,
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum ConfigCommand {
+    /// Print every configuration value and where it came from
+    #[structopt(name = "show")]
+    Show {
+        /// Output format: text, json or tsv. Defaults to the configured format.
+        #[structopt(long = "format")]
+        format: Option<OutputFormat>,
+    },
+}
+
 fn main() {
     let env =
     Env::default()
@@ -14,27 +395,890 @@ fn main() {
 
     env_logger::init_from_env(env);
 
-     let xml =
-r#"<stack_history path="/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack">
-    <alt is_current="False" version="packages.xml.20161213-093146_r575055" />
-    <alt is_current="False" version="packages.xml.20181102-144204" />
-    <alt is_current="True" version="packages.xml.20181105-103813" />
-    <alt is_current="False" version="packages.xml.20181106-104603" />
-</stack_history>"#;
+    let opt = Opt::from_args();
+    debug!("{:?}", opt);
 
-    let result = get_file_version_on(xml.as_bytes(), Local::now().naive_local());
-    info!("get_file_version_on(...) results: {:?}", result);
+    if let Err(e) = run(opt) {
+        eprintln!("{}", e);
+        match e {
+            BBError::NoVersionFound(_) => process::exit(EXIT_NO_VERSION),
+            _ => process::exit(1),
+        }
+    }
+}
 
-    let dt = Local::now();
-    let ndt = dt.naive_local();
-    info!("{:?} {:?}", dt, ndt);
+fn run(opt: Opt) -> Result<(), BBError> {
+    let config = Config::load(&opt.config)?;
+    let facility = config.timezone()?;
+    let ctx = Context {
+        layout: config.layout()?,
+        facility,
+        query_zone: opt.tz.unwrap_or(facility),
+        ambiguity: opt.ambiguous,
+        lock: config.lock_options()?,
+        retention_keep: config.retention_keep()?,
+        retention_days: config.retention_days()?,
+    };
+    let default_format = config.format()?;
+    let mode = if opt.logical { ParentResolution::Logical } else { ParentResolution::Physical };
+    let resolve = |path: PathBuf| resolve_user_path(&path, mode);
+    match opt.command {
+        Command::Package { file, at, history, name, format } => {
+            run_package(resolve(file)?, at, history, name, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Blame { file, at, format } => run_blame(resolve(file)?, at, format.unwrap_or(default_format), &ctx),
+        Command::Grep { pattern, file_or_root, since, until, pickaxe, format } => {
+            let roots = match file_or_root {
+                Some(file_or_root) => vec![resolve(file_or_root)?],
+                None => config.search_roots()?,
+            };
+            if roots.is_empty() {
+                return Err(BBError::ParseError("no file or root given, and no search_roots configured".to_string()));
+            }
+            run_grep(pattern, roots, since, until, pickaxe, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Log { file, since, until, format } => {
+            let format = format.or_else(|| if default_format == OutputFormat::Text {
+                None
+            } else {
+                Some(default_format.to_string())
+            });
+            run_log(resolve(file)?, since, until, format, &ctx)
+        },
+        Command::Cat { file, at, rev, policy, explain, format } => {
+            run_cat(resolve(file)?, at, rev, policy, explain, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Whois { path, format } => run_whois(resolve(path)?, format.unwrap_or(default_format), &ctx),
+        Command::Install { source, dest, rev, mode, owner, group, emit_script, format } => {
+            let ownership = Ownership { mode, uid: owner, gid: group };
+            if emit_script {
+                let script = install_script(
+                    resolve(source)?, resolve(dest)?, &ctx.facility.now(), rev.as_deref(), ctx.layout.as_ref(), &ownership)?;
+                return write_script(&script);
+            }
+            run_install(resolve(source)?, resolve(dest)?, rev, ownership, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Restore { root, at, policy, resume, abort, emit_script, format } => {
+            let format = format.unwrap_or(default_format);
+            let journal_dir = default_journal_dir()?;
+            if resume {
+                let restored = logged(OpKind::Restore, &ctx, |op| resume_restore(&journal_dir, &ctx.lock, Some(op)))?;
+                return emit_restore(&restored, "restored", format, &ctx);
+            }
+            if abort {
+                return emit_restore(&abort_restore(&journal_dir, &ctx.lock)?, "put back", format, &ctx);
+            }
+            let (root, at) = match (root, at) {
+                (Some(root), Some(at)) => (root, at),
+                _ => return Err(BBError::ParseError("restore needs a root and --at, or --resume or --abort".to_string())),
+            };
+            let datetime = ctx.datetime_or_now(Some(at))?;
+            let root = resolve(root)?;
+            if emit_script {
+                return write_script(&restore_script(&root, &datetime, policy, ctx.layout.as_ref())?);
+            }
+            let restored = logged(OpKind::Restore, &ctx, |op| {
+                restore(&root, &datetime, policy, ctx.layout.as_ref(), &ctx.lock, &journal_dir, Some(op))
+            })?;
+            emit_restore(&restored, "restored", format, &ctx)
+        },
+        Command::Ls { dir, at, policy, recursive, all, format } => {
+            run_ls(resolve(dir)?, at, policy, recursive, all, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Changes { root, since, until, format } => {
+            run_changes(roots_or_configured(root, &config, mode)?, since, until, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Changesets { root, since, until, by, window, diff, restore, before, emit_script, format } => {
+            let roots = roots_or_configured(root, &config, mode)?;
+            let grouping = match by {
+                Some(by) => by,
+                None => config.changeset_grouping()?,
+            };
+            let window = match window {
+                Some(secs) => chrono::Duration::seconds(secs),
+                None => config.changeset_window()?,
+            };
+            let format = format.unwrap_or(default_format);
+            match (diff, restore) {
+                (Some(id), _) => {
+                    let changeset = find_changeset(&roots, &id, grouping, window, ctx.layout.as_ref())?;
+                    run_changeset_diff(&changeset, config.difftool(), format)
+                },
+                (_, Some(id)) => {
+                    // the journal records the single root a restore was made beneath
+                    if roots.len() > 1 {
+                        return Err(BBError::ParseError(
+                            "changesets --restore needs a single root; give the root to restore beneath".to_string()));
+                    }
+                    let changeset = find_changeset(&roots, &id, grouping, window, ctx.layout.as_ref())?;
+                    let entries = plan_changeset_restore(&changeset, before)?;
+                    let at = if before { changeset.start } else { changeset.end };
+                    if emit_script {
+                        return write_script(&planned_restore_script(&roots[0], &at, &entries)?);
+                    }
+                    let restored = logged(OpKind::Restore, &ctx, |op| {
+                        apply_restore(&roots[0], &at, entries, &ctx.lock, &default_journal_dir()?, Some(op))
+                    })?;
+                    emit_restore(&restored, "restored", format, &ctx)
+                },
+                (None, None) => {
+                    let changesets = find_changesets(&roots, grouping, window, ctx.layout.as_ref())?;
+                    run_changesets(&changesets, since, until, format, &ctx)
+                },
+            }
+        },
+        Command::Undo { op_id, list, emit_script, format } => {
+            if emit_script {
+                return write_script(&undo_script(&OpLog::default_log()?, op_id.as_deref())?);
+            }
+            run_undo(op_id, list, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Oplog(OplogCommand::Prune { keep, days, emit_script, format }) => {
+            run_oplog_prune(keep.or(ctx.retention_keep), days.or(ctx.retention_days), emit_script, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Config(ConfigCommand::Show { format }) => run_config_show(&config, format.unwrap_or(default_format)),
+        Command::Index(IndexCommand::Build { root, format }) => {
+            run_index_refresh(roots_or_configured(root, &config, mode)?, true, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Index(IndexCommand::Update { root, format }) => {
+            run_index_refresh(roots_or_configured(root, &config, mode)?, false, format.unwrap_or(default_format), &ctx)
+        },
+        Command::Index(IndexCommand::Stats { root, format }) => {
+            run_index_stats(roots_or_configured(root, &config, mode)?, format.unwrap_or(default_format), &ctx)
+        },
+    }
+}
 
-    let this_file = path_to_executable().unwrap();
-    let this_file = pathbuf_to_string(this_file).unwrap();
-    println!("executable path: {}", this_file);
+// Expand and resolve a path given on the command line, reporting any symlinks
+// followed on the way
+fn resolve_user_path(path: &Path, mode: ParentResolution) -> Result<PathBuf, BBError> {
+    let resolved = resolve_path(path, mode)?;
+    for link in &resolved.followed {
+        info!("followed symlink {} -> {}", link.link.display(), link.target.display());
+    }
+    Ok(resolved.path)
+}
+
+// The root given on the command line, or the configured search roots
+fn roots_or_configured(root: Option<PathBuf>, config: &Config, mode: ParentResolution) -> Result<Vec<PathBuf>, BBError> {
+    let roots = match root {
+        Some(root) => vec![resolve_user_path(&root, mode)?],
+        None => config.search_roots()?,
+    };
+    if roots.is_empty() {
+        return Err(BBError::ParseError("no root given, and no search_roots configured".to_string()));
+    }
+    Ok(roots)
+}
 
-    let mut first = this_file.split("bakbuster");
-    let next = first.next().unwrap();
-    println!("defined in file: {}/backbuster", next);
+// Print the records of a command in a machine readable format
+fn emit(format: OutputFormat, command: &str, columns: &[&str], records: &[Record]) {
+    print!("{}", render(format, command, columns, records));
 }
 
+// Settings shared by every command
+struct Context {
+    layout: Box<dyn BakLayout>,
+    // the timezone stack timestamps are recorded in
+    facility: Zone,
+    // the timezone of datetimes given without an offset
+    query_zone: Zone,
+    ambiguity: AmbiguityPolicy,
+    lock: LockOptions,
+    // how many logged operations are kept, and for how many days
+    retention_keep: Option<u32>,
+    retention_days: Option<u32>,
+}
+
+impl Context {
+    // Remove the logged operations beyond the newest `keep`, and those begun
+    // more than `days` ago
+    fn prune_oplog(&self, oplog: &OpLog, keep: Option<u32>, days: Option<u32>) -> Result<Vec<Operation>, BBError> {
+        oplog.prune(keep.map(|keep| keep as usize), self.retention_cutoff(days))
+    }
+
+    // The time before which operations are pruned, if kept for `days`
+    fn retention_cutoff(&self, days: Option<u32>) -> Option<NaiveDateTime> {
+        days.map(|days| self.facility.now() - chrono::Duration::days(i64::from(days)))
+    }
+
+    // Resolve an optional user supplied datetime, defaulting to now
+    fn query_time(&self, at: Option<String>) -> Result<QueryTime, BBError> {
+        match at {
+            Some(ref at) => parse_query_time(at, self.query_zone, self.facility, self.ambiguity),
+            None => Ok(query_time_at("now", Utc::now(), self.facility)),
+        }
+    }
+
+    // Resolve an optional user supplied datetime to the facility's local time,
+    // defaulting to now
+    fn datetime_or_now(&self, at: Option<String>) -> Result<NaiveDateTime, BBError> {
+        self.query_time(at).map(|query| query.facility_local)
+    }
+
+    // Resolve an optional user supplied datetime to the facility's local time
+    fn optional_datetime(&self, input: Option<String>) -> Result<Option<NaiveDateTime>, BBError> {
+        match input {
+            Some(input) => self.datetime_or_now(Some(input)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn run_package(
+    file: PathBuf,
+    at: Option<String>,
+    history: bool,
+    name: String,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let layout = ctx.layout.as_ref();
+    let target = SwinstalledFile::new(&file, layout)?;
+    let mut cache = match PackageCache::default_cache_file(&target) {
+        Ok(cache_file) => PackageCache::with_cache_file(cache_file)?,
+        Err(e) => {
+            debug!("package cache disabled: {}", e);
+            PackageCache::new()
+        }
+    };
+
+    let results = if history {
+        package_history_with_layout(&file, &name, &mut cache, layout)?
+    } else {
+        let datetime = ctx.datetime_or_now(at)?;
+        vec![package_version_on_with_layout(&file, &name, &datetime, &mut cache, layout)?]
+    };
+
+    match format {
+        OutputFormat::Text if history => {
+            for change in results {
+                println!("{} {} {}", change.file_version, name, change.version.unwrap_or_else(|| "-".to_string()));
+            }
+        },
+        OutputFormat::Text => {
+            for result in results {
+                match result.version {
+                    Some(version) => println!("{} {} ({})", name, version, result.file_version),
+                    None => println!("{} not listed ({})", name, result.file_version),
+                }
+            }
+        },
+        _ => {
+            let records = results.iter().map(|r| {
+                Record::new()
+                    .path("file", &file)
+                    .file_version_in(&r.file_version, ctx.facility)
+                    .field("package", name.as_str())
+                    .field("package_version", r.version.clone())
+            }).collect::<Vec<_>>();
+            let columns = [&["file"], FILE_VERSION_COLUMNS, &["package", "package_version"]].concat();
+            emit(format, "package", &columns, &records);
+        }
+    }
+
+    if let Err(e) = cache.save() {
+        warn!("unable to save package cache: {}", e);
+    }
+    Ok(())
+}
+
+fn run_blame(file: PathBuf, at: Option<String>, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    let datetime = ctx.datetime_or_now(at)?;
+    let blamed = blame_with_layout(&file, &datetime, ctx.layout.as_ref())?;
+    if format != OutputFormat::Text {
+        let records = blamed.iter().enumerate().map(|(lineno, b)| {
+            Record::new()
+                .file_version_in(&b.version, ctx.facility)
+                .field("line_number", lineno + 1)
+                .field("line", b.line.as_str())
+        }).collect::<Vec<_>>();
+        emit(format, "blame", &[FILE_VERSION_COLUMNS, &["line_number", "line"]].concat(), &records);
+        return Ok(());
+    }
+    for (lineno, blamed) in blamed.iter().enumerate() {
+        let revision = blamed.version.revision.as_ref()
+                             .map(|r| format!("r{}", r))
+                             .unwrap_or_else(|| "-".to_string());
+        println!("{} {:>10} {:>5}) {}",
+            blamed.version.date_time.format(STDTIMEFMT), revision, lineno + 1, blamed.line);
+    }
+    Ok(())
+}
+
+fn run_grep(
+    pattern: String,
+    roots: Vec<PathBuf>,
+    since: Option<String>,
+    until: Option<String>,
+    pickaxe_mode: bool,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let layout = ctx.layout.as_ref();
+    let regex = Regex::new(&pattern)
+                .map_err(|e| BBError::ParseError(format!("invalid pattern '{}': {}", pattern, e)))?;
+    let options = GrepOptions {
+        since: ctx.optional_datetime(since)?,
+        until: ctx.optional_datetime(until)?,
+    };
+    let mut records = Vec::new();
+    let mut targets = Vec::new();
+    for file_or_root in &roots {
+        targets.extend(swinstalled_files_for_with_layout(file_or_root, layout)?);
+    }
+    for target in targets {
+        if pickaxe_mode {
+            for hit in pickaxe(&target, &regex, &options)? {
+                let change = if hit.added() { "added" } else { "removed" };
+                if format == OutputFormat::Text {
+                    println!("{} {} {} ({} -> {})", hit.file.display(), hit.version, change, hit.before, hit.after);
+                } else {
+                    records.push(Record::new()
+                        .path("file", &hit.file)
+                        .file_version_in(&hit.version, ctx.facility)
+                        .field("change", change)
+                        .field("before", hit.before)
+                        .field("after", hit.after));
+                }
+            }
+        } else {
+            for found in grep(&target, &regex, &options)? {
+                if format == OutputFormat::Text {
+                    println!("{}:{}:{}:{}", found.file.display(), found.version, found.line_number, found.line);
+                } else {
+                    records.push(Record::new()
+                        .path("file", &found.file)
+                        .file_version_in(&found.version, ctx.facility)
+                        .field("line_number", found.line_number)
+                        .field("line", found.line));
+                }
+            }
+        }
+    }
+    if format != OutputFormat::Text {
+        let columns: &[&str] = if pickaxe_mode { &["change", "before", "after"] } else { &["line_number", "line"] };
+        emit(format, "grep", &[&["file"], FILE_VERSION_COLUMNS, columns].concat(), &records);
+    }
+    Ok(())
+}
+
+fn run_log(
+    file: PathBuf,
+    since: Option<String>,
+    until: Option<String>,
+    format: Option<String>,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let layout = ctx.layout.as_ref();
+    let since = ctx.optional_datetime(since)?;
+    let until = ctx.optional_datetime(until)?;
+    let template = format.unwrap_or_else(|| DEFAULT_LOG_FORMAT.to_string());
+    // --format accepts either a machine readable format or a template
+    let format = OutputFormat::from_str(&template).unwrap_or(OutputFormat::Text);
+    let template = if template == "text" { DEFAULT_LOG_FORMAT.to_string() } else { template };
+
+    let mut records = Vec::new();
+    for entry in file_log_with_layout(&file, &ctx.facility.now(), layout)? {
+        let date_time = entry.version.date_time;
+        if since.map(|s| date_time < s).unwrap_or(false) || until.map(|u| date_time > u).unwrap_or(false) {
+            continue;
+        }
+        if format == OutputFormat::Text {
+            println!("{}", entry.render(&template));
+        } else {
+            records.push(Record::new()
+                .path("file", &file)
+                .file_version_in(&entry.version, ctx.facility)
+                .path("backup", &entry.backup)
+                .field("size", entry.size.map(|s| s as i64))
+                .field("is_current", entry.is_current)
+                .field("rolled_back", entry.rolled_back)
+                .field("newest_for_seconds", entry.newest_for.num_seconds()));
+        }
+    }
+    if format != OutputFormat::Text {
+        let columns = [&["file"], FILE_VERSION_COLUMNS, &["backup", "size", "is_current", "rolled_back", "newest_for_seconds"]].concat();
+        emit(format, "log", &columns, &records);
+    }
+    Ok(())
+}
+
+fn run_cat(
+    file: PathBuf,
+    at: Option<String>,
+    rev: Option<String>,
+    policy: ResolvePolicy,
+    explain: bool,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let layout = ctx.layout.as_ref();
+    let target = SwinstalledFile::new(&file, layout)?;
+    if !target.stack.exists() {
+        return Err(BBError::NoVersionFound(format!("{} has no swinstall stack at {}", file.display(), target.stack.display())));
+    }
+    let mut versioned = match rev {
+        Some(rev) => {
+            let history = read_stack_history(&target.stack)?;
+            let entry = history.with_revision(&rev, policy).ok_or_else(|| {
+                BBError::NoVersionFound(format!("{} has no install with revision {}", file.display(), rev))
+            })?;
+            VersionedFile::open_swinstalled(&target, entry.version.clone())?
+        },
+        None => {
+            let query = ctx.query_time(at)?;
+            if explain {
+                explain_query(&query, ctx);
+            }
+            let datetime = query.facility_local;
+            open_as_of_with_layout(&file, datetime, policy, layout).map_err(|e| match e {
+                BBError::NoVersionFound(_) => BBError::NoVersionFound(
+                    format!("{} had no history yet on {}", file.display(), datetime.format(STDTIMEFMT))),
+                e => e,
+            })?
+        }
+    };
+    if explain {
+        let version = versioned.file_version();
+        eprintln!("selected: {} ({})", version, ctx.facility.localize(&version.date_time));
+    }
+    if format != OutputFormat::Text {
+        let mut contents = Vec::new();
+        versioned.read_to_end(&mut contents)
+                 .map_err(|e| BBError::IoError(format!("unable to read {}: {}", versioned.backup_path().display(), e)))?;
+        let record = Record::new()
+            .path("file", &file)
+            .file_version_in(versioned.file_version(), ctx.facility)
+            .path("backup", versioned.backup_path())
+            .field("content", String::from_utf8_lossy(&contents).into_owned());
+        emit(format, "cat", &[&["file"], FILE_VERSION_COLUMNS, &["backup", "content"]].concat(), &[record]);
+        return Ok(());
+    }
+    let stdout = io::stdout();
+    io::copy(&mut versioned, &mut stdout.lock())
+        .map_err(|e| BBError::IoError(format!("unable to write {}: {}", versioned.backup_path().display(), e)))?;
+    Ok(())
+}
+
+fn run_whois(path: PathBuf, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    let found = whois(&path, ctx.layout.as_ref())?;
+    if format != OutputFormat::Text {
+        let mut record = Record::new()
+            .path("path", &path)
+            .path("file", &found.target.live)
+            .path("stack", &found.target.stack);
+        record = match found.version {
+            Some(ref version) => record.file_version_in(version, ctx.facility),
+            None => record.field("version", None::<String>)
+                          .field("timestamp", None::<String>)
+                          .field("revision", None::<String>)
+                          .field("timestamp_utc", None::<String>),
+        };
+        let record = record
+            .field("position", found.position)
+            .field("entries", found.entries)
+            .field("is_current", found.is_current)
+            .field("rolled_back", found.rolled_back)
+            .field("recorded_path", found.recorded_path.clone())
+            .field("recorded_path_matches", found.recorded_path_matches);
+        let columns = [
+            &["path", "file", "stack"],
+            FILE_VERSION_COLUMNS,
+            &["position", "entries", "is_current", "rolled_back", "recorded_path", "recorded_path_matches"],
+        ].concat();
+        emit(format, "whois", &columns, &[record]);
+        return Ok(());
+    }
+
+    println!("file:     {}", found.target.live.display());
+    println!("stack:    {}", found.target.stack.display());
+    if let Some(ref version) = found.version {
+        match found.position {
+            Some(position) => {
+                let status = if found.is_current { ", current" } else if found.rolled_back { ", rolled back" } else { "" };
+                println!("version:  {} ({} of {}{})", version, position + 1, found.entries, status);
+            },
+            None => println!("version:  {} (not recorded in stack)", version),
+        }
+    }
+    match (found.recorded_path, found.recorded_path_matches) {
+        (Some(recorded), Some(true)) => println!("recorded: {}", recorded),
+        (Some(recorded), _) => println!("recorded: {} (does not match)", recorded),
+        (None, _) => println!("recorded: -"),
+    }
+    Ok(())
+}
+
+fn run_install(
+    source: PathBuf,
+    dest: PathBuf,
+    rev: Option<String>,
+    ownership: Ownership,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let options = InstallOptions { lock: ctx.lock.clone(), ownership };
+    let installed = logged(OpKind::Install, ctx, |op| {
+        install_recorded(&source, &dest, &ctx.facility.now(), rev.as_deref(), ctx.layout.as_ref(), &options, op)
+    })?;
+    if format != OutputFormat::Text {
+        let record = Record::new()
+            .path("file", &installed.target.live)
+            .file_version_in(&installed.version, ctx.facility)
+            .path("backup", &installed.backup)
+            .path("stack", &installed.target.stack);
+        emit(format, "install", &[&["file"], FILE_VERSION_COLUMNS, &["backup", "stack"]].concat(), &[record]);
+        return Ok(());
+    }
+    println!("installed {} ({})", installed.target.live.display(), installed.version);
+    Ok(())
+}
+
+fn run_ls(
+    dir: PathBuf,
+    at: Option<String>,
+    policy: ResolvePolicy,
+    recursive: bool,
+    all: bool,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let datetime = ctx.datetime_or_now(at)?;
+    let listing = list_at(&dir, &datetime, policy, recursive, ctx.layout.as_ref())?
+        .into_iter()
+        .filter(|listed| all || listed.status != ListedStatus::AbsentYet)
+        .collect::<Vec<_>>();
+    if format != OutputFormat::Text {
+        let records = listing.iter().map(|listed| {
+            let record = Record::new()
+                .path("file", &listed.target.live)
+                .field("status", listed.status.to_string());
+            match listed.version {
+                Some(ref version) => record.file_version_in(version, ctx.facility),
+                None => record.field("version", None::<String>)
+                              .field("timestamp", None::<String>)
+                              .field("revision", None::<String>)
+                              .field("timestamp_utc", None::<String>),
+            }
+        }).collect::<Vec<_>>();
+        emit(format, "ls", &[&["file", "status"], FILE_VERSION_COLUMNS].concat(), &records);
+        return Ok(());
+    }
+    for listed in &listing {
+        let version = listed.version.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        println!("{:<13}  {:<24}  {}", listed.status, version, listed.target.live.display());
+    }
+    Ok(())
+}
+
+fn run_changes(
+    roots: Vec<PathBuf>,
+    since: String,
+    until: Option<String>,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let since = ctx.datetime_or_now(Some(since))?;
+    let until = ctx.optional_datetime(until)?;
+    let changes = changes(&roots, &since, until.as_ref(), ctx.facility, ctx.layout.as_ref())?;
+    if format != OutputFormat::Text {
+        let records = changes.iter().map(|change| {
+            let rolled_back = match change.kind {
+                ChangeKind::Rollback { ref from } => Some(from.to_string()),
+                ChangeKind::Install => None,
+            };
+            Record::new()
+                .field("datetime", change.datetime.format(STDTIMEFMT).to_string())
+                .field("change", change.kind.to_string())
+                .path("file", &change.target.live)
+                .file_version_in(&change.version, ctx.facility)
+                .field("rolled_back_from", rolled_back)
+        }).collect::<Vec<_>>();
+        let columns = [&["datetime", "change", "file"], FILE_VERSION_COLUMNS, &["rolled_back_from"]].concat();
+        emit(format, "changes", &columns, &records);
+        return Ok(());
+    }
+    for change in &changes {
+        let detail = match change.kind {
+            ChangeKind::Rollback { ref from } => format!("{} (from {})", change.version, from),
+            ChangeKind::Install => change.version.to_string(),
+        };
+        println!("{}  {:<8}  {}  {}", change.datetime.format(STDTIMEFMT), change.kind, change.target.live.display(), detail);
+    }
+    Ok(())
+}
+
+fn run_changesets(
+    changesets: &[Changeset],
+    since: Option<String>,
+    until: Option<String>,
+    format: OutputFormat,
+    ctx: &Context,
+) -> Result<(), BBError> {
+    let since = ctx.optional_datetime(since)?;
+    let until = ctx.optional_datetime(until)?;
+    let changesets = changesets.iter().filter(|changeset| {
+        since.map_or(true, |since| changeset.end >= since) && until.map_or(true, |until| changeset.start <= until)
+    }).collect::<Vec<_>>();
+    if format != OutputFormat::Text {
+        let records = changesets.iter().flat_map(|changeset| changeset.members.iter().map(move |member| {
+            Record::new()
+                .field("changeset", changeset.id.as_str())
+                .field("start", changeset.start.format(STDTIMEFMT).to_string())
+                .field("end", changeset.end.format(STDTIMEFMT).to_string())
+                .path("file", &member.target.live)
+                .file_version_in(&member.version, ctx.facility)
+                .field("previous_version", member.previous.as_ref().map(|v| v.to_string()))
+        })).collect::<Vec<_>>();
+        let columns = [&["changeset", "start", "end", "file"], FILE_VERSION_COLUMNS, &["previous_version"]].concat();
+        emit(format, "changesets", &columns, &records);
+        return Ok(());
+    }
+    for changeset in &changesets {
+        let files = changeset.latest_members().len();
+        println!("{}  {} - {}  {} file{}", changeset.id, changeset.start.format(STDTIMEFMT),
+                 changeset.end.format(STDTIMEFMT), files, if files == 1 { "" } else { "s" });
+        for member in &changeset.members {
+            println!("    {} {}", member.target.live.display(), member.version);
+        }
+    }
+    Ok(())
+}
+
+// Run a mutating operation, logging it so that it may be undone. Operations
+// which fail, or change nothing, are not logged.
+fn logged<T, F>(kind: OpKind, ctx: &Context, run: F) -> Result<T, BBError>
+where
+    F: FnOnce(&mut Operation) -> Result<T, BBError>,
+{
+    let oplog = OpLog::default_log()?;
+    let mut operation = oplog.begin(kind, &ctx.facility.now())?;
+    match run(&mut operation) {
+        Ok(result) => {
+            if operation.files.is_empty() {
+                operation.discard();
+            } else {
+                operation.commit()?;
+                info!("logged {} as operation {}", kind, operation.id);
+            }
+            if ctx.retention_keep.is_some() || ctx.retention_days.is_some() {
+                match ctx.prune_oplog(&oplog, ctx.retention_keep, ctx.retention_days) {
+                    Ok(pruned) => debug!("pruned {} logged operations", pruned.len()),
+                    Err(e) => warn!("unable to prune the operation log: {}", e),
+                }
+            }
+            Ok(result)
+        },
+        Err(e) => {
+            operation.discard();
+            Err(e)
+        },
+    }
+}
+
+// Diff each file in a changeset, with the configured difftool if there is one.
+// Machine readable output carries the unified diff of each file.
+fn run_changeset_diff(changeset: &Changeset, difftool: Option<&str>, format: OutputFormat) -> Result<(), BBError> {
+    let files = changed_files(changeset)?;
+    match (format, difftool) {
+        (OutputFormat::Text, Some(tool)) => {
+            for file in &files {
+                run_difftool(tool, file.old.as_deref().unwrap_or(Path::new("/dev/null")), &file.new)?;
+            }
+        },
+        (OutputFormat::Text, None) => print!("{}", changeset_diff(changeset)?),
+        _ => {
+            let records = files.iter().map(|file| {
+                Ok(Record::new()
+                    .field("changeset", changeset.id.as_str())
+                    .path("file", &file.live)
+                    .field("old_backup", file.old.as_ref().map(|old| old.to_string_lossy().into_owned()))
+                    .path("new_backup", &file.new)
+                    .field("diff", changed_file_diff(file)?))
+            }).collect::<Result<Vec<_>, BBError>>()?;
+            emit(format, "changesets", &["changeset", "file", "old_backup", "new_backup", "diff"], &records);
+        },
+    }
+    Ok(())
+}
+
+fn run_undo(op_id: Option<String>, list: bool, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    let oplog = OpLog::default_log()?;
+    let operations = if list {
+        oplog.operations()?
+    } else {
+        vec![undo(&oplog, op_id.as_deref(), &ctx.lock)?]
+    };
+    if format != OutputFormat::Text {
+        let records = operations.iter().flat_map(|op| op.files.iter().map(move |file| {
+            Record::new()
+                .field("operation", op.id.as_str())
+                .field("kind", op.kind.to_string())
+                .field("undone", op.undone)
+                .path("file", &file.target.live)
+                .field("from_version", file.from.as_ref().map(|v| v.to_string()))
+                .file_version_in(&file.to, ctx.facility)
+        })).collect::<Vec<_>>();
+        let columns = [&["operation", "kind", "undone", "file", "from_version"], FILE_VERSION_COLUMNS].concat();
+        emit(format, "undo", &columns, &records);
+        return Ok(());
+    }
+    for op in &operations {
+        let state = if op.undone { "undone" } else { "applied" };
+        println!("{} {} {} ({})", op.id, op.kind, op.datetime.format(STDTIMEFMT), state);
+        for file in &op.files {
+            let from = file.from.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+            println!("    {} {} -> {}", file.target.live.display(), from, file.to);
+        }
+    }
+    Ok(())
+}
+
+fn run_oplog_prune(keep: Option<u32>, days: Option<u32>, emit_script: bool, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    if keep.is_none() && days.is_none() {
+        return Err(BBError::ParseError("no --keep or --days given, and no retention configured".to_string()));
+    }
+    if emit_script {
+        let script = OpLog::default_log()?.prune_script(keep.map(|keep| keep as usize), ctx.retention_cutoff(days))?;
+        return write_script(&script);
+    }
+    let pruned = ctx.prune_oplog(&OpLog::default_log()?, keep, days)?;
+    if format != OutputFormat::Text {
+        let records = pruned.iter().map(|op| {
+            Record::new()
+                .field("operation", op.id.as_str())
+                .field("kind", op.kind.to_string())
+                .field("at", op.datetime.format(STDTIMEFMT).to_string())
+                .field("undone", op.undone)
+        }).collect::<Vec<_>>();
+        emit(format, "oplog", &["operation", "kind", "at", "undone"], &records);
+        return Ok(());
+    }
+    for op in &pruned {
+        println!("pruned {} {} {}", op.id, op.kind, op.datetime.format(STDTIMEFMT));
+    }
+    Ok(())
+}
+
+// Print a script for review
+fn write_script(script: &Script) -> Result<(), BBError> {
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    output.write_all(&script.render())
+          .and_then(|_| output.flush())
+          .map_err(|e| BBError::IoError(format!("unable to write script: {}", e)))
+}
+
+// Report the files changed, or put back, by a restore
+fn emit_restore(entries: &[RestoreEntry], action: &str, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    if format != OutputFormat::Text {
+        let records = entries.iter().map(|entry| {
+            Record::new()
+                .path("file", &entry.target.live)
+                .field("from_version", entry.from.as_ref().map(|v| v.to_string()))
+                .file_version_in(&entry.to, ctx.facility)
+                .field("action", action)
+        }).collect::<Vec<_>>();
+        emit(format, "restore", &[&["file", "from_version"], FILE_VERSION_COLUMNS, &["action"]].concat(), &records);
+        return Ok(());
+    }
+    for entry in entries {
+        let from = entry.from.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        println!("{} {} {} -> {}", action, entry.target.live.display(), from, entry.to);
+    }
+    Ok(())
+}
+
+fn run_index_refresh(roots: Vec<PathBuf>, rebuild: bool, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    let mut refreshed = Vec::new();
+    for root in &roots {
+        refreshed.push(if rebuild {
+            build_index(root, ctx.layout.as_ref())?
+        } else {
+            update_index(root, ctx.layout.as_ref())?
+        });
+    }
+    if format != OutputFormat::Text {
+        let records = refreshed.iter().map(|refresh| {
+            Record::new()
+                .path("index", &refresh.path)
+                .field("stacks", refresh.stacks)
+                .field("read", refresh.read)
+                .field("reused", refresh.reused)
+        }).collect::<Vec<_>>();
+        emit(format, "index", &["index", "stacks", "read", "reused"], &records);
+        return Ok(());
+    }
+    for refresh in &refreshed {
+        println!("indexed {} stacks in {} ({} read, {} unchanged)",
+                 refresh.stacks, refresh.path.display(), refresh.read, refresh.reused);
+    }
+    Ok(())
+}
+
+fn run_index_stats(roots: Vec<PathBuf>, format: OutputFormat, ctx: &Context) -> Result<(), BBError> {
+    let stats = roots.iter().map(|root| index_stats(root, ctx.layout.as_ref())).collect::<Result<Vec<_>, _>>()?;
+    let built = |stats: &IndexStats| ctx.facility.naive_local(&DateTime::<Utc>::from(stats.built)).format(STDTIMEFMT).to_string();
+    if format != OutputFormat::Text {
+        let records = stats.iter().map(|stats| {
+            Record::new()
+                .path("index", &stats.path)
+                .field("built", built(stats))
+                .field("bytes", stats.bytes as i64)
+                .field("stacks", stats.stacks)
+                .field("entries", stats.entries)
+                .field("dirs", stats.dirs)
+                .field("stale_stacks", stats.stale_stacks)
+                .field("stale_dirs", stats.stale_dirs)
+        }).collect::<Vec<_>>();
+        let columns = ["index", "built", "bytes", "stacks", "entries", "dirs", "stale_stacks", "stale_dirs"];
+        emit(format, "index", &columns, &records);
+        return Ok(());
+    }
+    for stats in &stats {
+        println!("{}", stats.path.display());
+        println!("    built:    {}", built(stats));
+        println!("    size:     {} bytes", stats.bytes);
+        println!("    stacks:   {} ({} stale)", stats.stacks, stats.stale_stacks);
+        println!("    entries:  {}", stats.entries);
+        println!("    dirs:     {} ({} stale)", stats.dirs, stats.stale_dirs);
+    }
+    Ok(())
+}
+
+fn run_config_show(config: &Config, format: OutputFormat) -> Result<(), BBError> {
+    let entries = config.entries();
+    if format != OutputFormat::Text {
+        let records = entries.iter().map(|&(key, value, source)| {
+            let kind = match *source {
+                ConfigSource::Default => "default",
+                ConfigSource::File(_) => "file",
+                ConfigSource::Env(_) => "env",
+                ConfigSource::CommandLine => "command_line",
+            };
+            Record::new()
+                .field("key", key)
+                .field("value", if value.is_empty() { None } else { Some(value) })
+                .field("source", kind)
+                .field("origin", source.to_string())
+        }).collect::<Vec<_>>();
+        emit(format, "config", &["key", "value", "source", "origin"], &records);
+        return Ok(());
+    }
+    for (key, value, source) in entries {
+        let value = if value.is_empty() { "-" } else { value };
+        println!("{:<20} {:<30} ({})", key, value, source);
+    }
+    Ok(())
+}
+
+// Describe how a query datetime was resolved, for --explain
+fn explain_query(query: &QueryTime, ctx: &Context) {
+    eprintln!("query:    {} (datetimes without an offset are in {})", query.input, ctx.query_zone);
+    eprintln!("instant:  {}", query.instant.to_rfc3339());
+    eprintln!("facility: {} in {} ({})", query.facility_local.format(STDTIMEFMT), ctx.facility, query.facility_time);
+    if let LocalTime::Ambiguous(..) = query.facility_time {
+        eprintln!("warning:  the query falls in the facility's repeated hour; installs recorded during it cannot be ordered reliably");
+    }
+}
@@ -0,0 +1,356 @@
+//! metadata
+//!
+//! The mode, ownership and modification time of the files bakbuster replaces.
+//! A replaced file keeps the mode and ownership of the file it replaces, as
+//! these are what the facility expects, unless they are set explicitly, and
+//! takes the modification time of its contents' source. Whether a file can be
+//! replaced that way is checked before anything is touched.
+//!
+//! Files are replaced by renaming a new file over them, which needs root to
+//! keep another user's ownership. A file the current user can write but whose
+//! ownership they cannot keep is rewritten in place instead, which keeps its
+//! ownership and mode but not, unless the user owns it, its modification time.
+//!
+//! Elsewhere than unix there is no ownership to keep: files are owned by uid
+//! and gid 0, and a mode only records whether a file is read only.
+use errors::BBError;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The metadata bakbuster preserves
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FileMetadata {
+    /// The permission bits, including setuid, setgid and sticky
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The modification time, or None to leave it as written
+    pub mtime: Option<SystemTime>,
+}
+
+/// A difference between the metadata of two files
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Mismatch {
+    /// mode, owner or group
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is {} rather than {}", self.field, self.actual, self.expected)
+    }
+}
+
+/// Explicit metadata for an installed file, overriding what would be preserved
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Ownership {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FileMetadata {
+    /// The metadata of `path`, following symlinks
+    pub fn of(path: &Path) -> Result<FileMetadata, BBError> {
+        let metadata = fs::metadata(path)
+                       .map_err(|e| BBError::IoError(format!("unable to read metadata of {}: {}", path.display(), e)))?;
+        let (mode, uid, gid) = mode_and_owner(&metadata);
+        Ok(FileMetadata { mode, uid, gid, mtime: metadata.modified().ok() })
+    }
+
+    /// Where this file's mode and ownership differ from `expected`'s
+    pub fn mismatches(&self, expected: &FileMetadata) -> Vec<Mismatch> {
+        let mut mismatches = Vec::new();
+        if self.mode != expected.mode {
+            mismatches.push(Mismatch { field: "mode", expected: format!("{:04o}", expected.mode), actual: format!("{:04o}", self.mode) });
+        }
+        if self.uid != expected.uid {
+            mismatches.push(Mismatch { field: "owner", expected: expected.uid.to_string(), actual: self.uid.to_string() });
+        }
+        if self.gid != expected.gid {
+            mismatches.push(Mismatch { field: "group", expected: expected.gid.to_string(), actual: self.gid.to_string() });
+        }
+        mismatches
+    }
+}
+
+/// The metadata for a file replacing `dest` with the contents of `source`: the
+/// mode and ownership of `dest` if it exists, otherwise the mode of `source` and
+/// the ownership of the current user, overridden by `ownership`, and the
+/// modification time of `source`.
+pub fn replacement_metadata(source: &Path, dest: &Path, ownership: &Ownership) -> Result<FileMetadata, BBError> {
+    let source_metadata = FileMetadata::of(source)?;
+    let mut metadata = if dest.exists() {
+        FileMetadata { mtime: source_metadata.mtime, ..FileMetadata::of(dest)? }
+    } else {
+        FileMetadata { uid: effective_uid(), gid: effective_gid(), ..source_metadata }
+    };
+    metadata.mode = ownership.mode.unwrap_or(metadata.mode);
+    metadata.uid = ownership.uid.unwrap_or(metadata.uid);
+    metadata.gid = ownership.gid.unwrap_or(metadata.gid);
+    Ok(metadata)
+}
+
+/// Warn of any difference between the mode and ownership of `backup` and the
+/// live file it is about to replace
+pub fn report_mismatches(backup: &Path, live: &Path) -> Result<Vec<Mismatch>, BBError> {
+    if !live.exists() {
+        return Ok(Vec::new());
+    }
+    let mismatches = FileMetadata::of(backup)?.mismatches(&FileMetadata::of(live)?);
+    for mismatch in &mismatches {
+        warn!("{} differs from the live file {}: its {}", backup.display(), live.display(), mismatch);
+    }
+    Ok(mismatches)
+}
+
+/// Give `path` the mode, ownership and modification time in `metadata`.
+/// Ownership is only changed where it differs.
+pub fn apply_metadata(path: &Path, metadata: &FileMetadata) -> Result<(), BBError> {
+    set_owner_and_mode(path, metadata)?;
+    if let Some(mtime) = metadata.mtime {
+        File::options().write(true).open(path)
+            .and_then(|file| file.set_modified(mtime))
+            .map_err(|e| BBError::IoError(format!("unable to set modification time of {}: {}", path.display(), e)))?;
+    }
+    Ok(())
+}
+
+/// Check, without touching anything, that `dest` can be replaced by a file with
+/// `metadata`: its directory must be writable, and the current user must be
+/// able to give the file that ownership.
+pub fn check_can_replace(dest: &Path, metadata: &FileMetadata) -> Result<(), BBError> {
+    if replaces_in_place(dest, metadata)? {
+        return Ok(());
+    }
+    let dir = parent_dir(dest);
+    // a missing directory is created by the caller, and fails there if need be
+    if dir.exists() && !is_writable_dir(dir)? {
+        return Err(BBError::IoError(format!("permission denied: unable to replace files in {}", dir.display())));
+    }
+    let uid = effective_uid();
+    if uid == 0 {
+        return Ok(());
+    }
+    if metadata.uid != uid {
+        return Err(BBError::IoError(format!(
+            "permission denied: {} must be owned by uid {}, which only root can give", dest.display(), metadata.uid)));
+    }
+    if metadata.gid != effective_gid() && !groups().contains(&metadata.gid) {
+        return Err(BBError::IoError(format!(
+            "permission denied: {} must have gid {}, which the current user is not a member of", dest.display(), metadata.gid)));
+    }
+    Ok(())
+}
+
+/// True if `dest` must be rewritten in place, rather than replaced by renaming
+/// a new file over it, to give it `metadata`. That is so where `metadata` keeps
+/// the mode and ownership `dest` has, a new file could not be given them, or
+/// could not be renamed into `dest`'s directory, and the current user can write
+/// to `dest` itself: a group writable file owned by a colleague, say. Only root
+/// can give a new file another user's ownership, so root always renames.
+pub fn replaces_in_place(dest: &Path, metadata: &FileMetadata) -> Result<bool, BBError> {
+    let uid = effective_uid();
+    if uid == 0 || !dest.is_file() {
+        return Ok(false);
+    }
+    let current = FileMetadata::of(dest)?;
+    if (current.mode, current.uid, current.gid) != (metadata.mode, metadata.uid, metadata.gid) {
+        return Ok(false);
+    }
+    let renames = metadata.uid == uid
+        && (metadata.gid == effective_gid() || groups().contains(&metadata.gid))
+        && is_writable_dir(parent_dir(dest))?;
+    Ok(!renames && is_writable_file(dest)?)
+}
+
+/// Parse a mode given in octal, eg `0664`
+pub fn parse_mode(s: &str) -> Result<u32, BBError> {
+    u32::from_str_radix(s, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| BBError::ParseError(format!("Unable to parse mode: '{}' (expected octal, eg 0664)", s)))
+}
+
+/// The uid of a user name or number
+#[cfg(unix)]
+pub fn parse_owner(s: &str) -> Result<u32, BBError> {
+    if let Ok(uid) = s.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(s).map_err(|_| BBError::ParseError(format!("Unable to parse owner: '{}'", s)))?;
+    let passwd = unsafe { ::libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(BBError::ParseError(format!("Unable to parse owner: no user '{}'", s)));
+    }
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+/// The gid of a group name or number
+#[cfg(unix)]
+pub fn parse_group(s: &str) -> Result<u32, BBError> {
+    if let Ok(gid) = s.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(s).map_err(|_| BBError::ParseError(format!("Unable to parse group: '{}'", s)))?;
+    let group = unsafe { ::libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return Err(BBError::ParseError(format!("Unable to parse group: no group '{}'", s)));
+    }
+    Ok(unsafe { (*group).gr_gid })
+}
+
+/// The uid of a user number; user names can only be looked up on unix
+#[cfg(not(unix))]
+pub fn parse_owner(s: &str) -> Result<u32, BBError> {
+    s.parse().map_err(|_| BBError::ParseError(format!("Unable to parse owner: '{}' (expected a uid)", s)))
+}
+
+/// The gid of a group number; group names can only be looked up on unix
+#[cfg(not(unix))]
+pub fn parse_group(s: &str) -> Result<u32, BBError> {
+    s.parse().map_err(|_| BBError::ParseError(format!("Unable to parse group: '{}' (expected a gid)", s)))
+}
+
+#[cfg(unix)]
+fn mode_and_owner(metadata: &fs::Metadata) -> (u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.mode() & 0o7777, metadata.uid(), metadata.gid())
+}
+
+#[cfg(not(unix))]
+fn mode_and_owner(metadata: &fs::Metadata) -> (u32, u32, u32) {
+    (if metadata.permissions().readonly() { 0o444 } else { 0o644 }, 0, 0)
+}
+
+#[cfg(unix)]
+fn set_owner_and_mode(path: &Path, metadata: &FileMetadata) -> Result<(), BBError> {
+    use std::fs::Permissions;
+    use std::os::unix::fs::{self as unix_fs, PermissionsExt};
+    let current = FileMetadata::of(path)?;
+    if current.uid != metadata.uid || current.gid != metadata.gid {
+        unix_fs::chown(path, Some(metadata.uid), Some(metadata.gid))
+            .map_err(|e| BBError::IoError(format!(
+                "unable to give {} owner {} and group {}: {}", path.display(), metadata.uid, metadata.gid, e)))?;
+    }
+    // set after chown, which clears setuid and setgid
+    fs::set_permissions(path, Permissions::from_mode(metadata.mode))
+        .map_err(|e| BBError::IoError(format!("unable to set mode of {}: {}", path.display(), e)))
+}
+
+#[cfg(not(unix))]
+fn set_owner_and_mode(path: &Path, metadata: &FileMetadata) -> Result<(), BBError> {
+    let mut permissions = fs::metadata(path)
+        .map_err(|e| BBError::IoError(format!("unable to read metadata of {}: {}", path.display(), e)))?
+        .permissions();
+    permissions.set_readonly(metadata.mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+        .map_err(|e| BBError::IoError(format!("unable to set mode of {}: {}", path.display(), e)))
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(unix)]
+fn is_writable_dir(dir: &Path) -> Result<bool, BBError> {
+    access(dir, ::libc::W_OK | ::libc::X_OK)
+}
+
+#[cfg(unix)]
+fn is_writable_file(path: &Path) -> Result<bool, BBError> {
+    access(path, ::libc::W_OK)
+}
+
+#[cfg(unix)]
+fn access(path: &Path, mode: ::libc::c_int) -> Result<bool, BBError> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+                 .map_err(|_| BBError::ConversionError(format!("Unable to convert '{:?}'", path)))?;
+    Ok(unsafe { ::libc::access(c_path.as_ptr(), mode) } == 0)
+}
+
+#[cfg(not(unix))]
+fn is_writable_dir(dir: &Path) -> Result<bool, BBError> {
+    is_writable_file(dir)
+}
+
+#[cfg(not(unix))]
+fn is_writable_file(path: &Path) -> Result<bool, BBError> {
+    fs::metadata(path)
+        .map(|metadata| !metadata.permissions().readonly())
+        .map_err(|e| BBError::IoError(format!("unable to read metadata of {}: {}", path.display(), e)))
+}
+
+#[cfg(unix)]
+fn effective_uid() -> u32 {
+    unsafe { ::libc::geteuid() }
+}
+
+#[cfg(unix)]
+fn effective_gid() -> u32 {
+    unsafe { ::libc::getegid() }
+}
+
+// The supplementary groups of the current process
+#[cfg(unix)]
+fn groups() -> Vec<u32> {
+    let count = unsafe { ::libc::getgroups(0, ::std::ptr::null_mut()) };
+    if count <= 0 {
+        return Vec::new();
+    }
+    let mut groups = vec![0; count as usize];
+    let count = unsafe { ::libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups
+}
+
+// without ownership, every user may replace what they can write, as root can
+#[cfg(not(unix))]
+fn effective_uid() -> u32 {
+    0
+}
+
+#[cfg(not(unix))]
+fn effective_gid() -> u32 {
+    0
+}
+
+#[cfg(not(unix))]
+fn groups() -> Vec<u32> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(parse_mode("0664").unwrap(), 0o664);
+        assert_eq!(parse_mode("2775").unwrap(), 0o2775);
+        assert!(parse_mode("0999").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let live = FileMetadata { mode: 0o664, uid: 100, gid: 200, mtime: None };
+        let backup = FileMetadata { mode: 0o644, ..live };
+        assert_eq!(backup.mismatches(&live), vec![
+            Mismatch { field: "mode", expected: "0664".to_string(), actual: "0644".to_string() },
+        ]);
+        assert_eq!(format!("{}", backup.mismatches(&live)[0]), "mode is 0644 rather than 0664");
+        assert!(live.mismatches(&live).is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+//! oplog
+//!
+//! A log of the operations bakbuster performs which modify stacks, such as
+//! `install` and `restore`, so that they can be undone. Each operation is a
+//! directory in the log holding a record of the files it changed, a copy of each
+//! stack and live file as they were before, and the files it created.
+//!
+//! An operation is only recorded once it has completed; a directory without a
+//! record belongs to an operation which failed or is in progress, and is ignored.
+//!
+//! The log keeps every operation until it is pruned, by count or by age, which
+//! bakbuster does after each operation it logs where retention is configured.
+use chrono::NaiveDateTime;
+use constants::STDTIMEFMT;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use install::{copy_atomically, write_atomically};
+use layout::SwinstalledFile;
+use lock::{lock_stack, LockOptions};
+use metadata::{replacement_metadata, FileMetadata, Ownership};
+use paths::{decode_path, encode_path};
+use script::Script;
+use stack_history_parser::read_stack_history;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use utils::state_dir;
+
+/// The first line of every operation record, naming its format version
+pub static RECORD_HEADER: &str = "bakbuster-operation 1";
+
+/// The kinds of operation which are logged. Changeset restores are logged as
+/// restores; pruning the log is not logged.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OpKind {
+    Install,
+    Restore,
+}
+
+impl FromStr for OpKind {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "install" => Ok(OpKind::Install),
+            "restore" => Ok(OpKind::Restore),
+            _ => Err(BBError::ParseError(format!("Unable to parse operation kind: '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for OpKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpKind::Install => write!(f, "install"),
+            OpKind::Restore => write!(f, "restore"),
+        }
+    }
+}
+
+/// A file changed by an operation
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpFile {
+    pub target: SwinstalledFile,
+    /// The version current before the operation, if the stack existed
+    pub from: Option<FileVersion>,
+    /// The version the operation made current
+    pub to: FileVersion,
+    /// Files the operation created, such as backups, which undo removes
+    pub created: Vec<PathBuf>,
+}
+
+/// An operation in the log
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Operation {
+    pub id: String,
+    pub kind: OpKind,
+    /// When the operation began, in facility local time
+    pub datetime: NaiveDateTime,
+    pub undone: bool,
+    pub files: Vec<OpFile>,
+    dir: PathBuf,
+}
+
+impl Operation {
+    /// Record that `target` is changing from `from` to `to`, copying the stack
+    /// and live file as they were before from `stack_before` and `live_before`.
+    /// None means the file did not exist, and undo removes it.
+    pub fn record(
+        &mut self,
+        target: &SwinstalledFile,
+        from: Option<FileVersion>,
+        to: FileVersion,
+        stack_before: Option<&Path>,
+        live_before: Option<&Path>,
+        created: Vec<PathBuf>,
+    ) -> Result<(), BBError> {
+        let idx = self.files.len();
+        if let Some(stack) = stack_before {
+            copy_atomically(stack, &self.saved_stack(idx))?;
+        }
+        if let Some(live) = live_before {
+            copy_atomically(live, &self.saved_live(idx))?;
+        }
+        self.files.push(OpFile { target: target.clone(), from, to, created });
+        Ok(())
+    }
+
+    /// Write the record of a completed operation to the log
+    pub fn commit(&self) -> Result<(), BBError> {
+        let contents = self.record_text();
+        write_atomically(&self.record_path(), |output| {
+            output.write_all(contents.as_bytes())
+                  .map_err(|e| BBError::IoError(format!("unable to write operation record: {}", e)))
+        })
+    }
+
+    fn record_text(&self) -> String {
+        let mut contents = format!("{}\nkind\t{}\nat\t{}\nstate\t{}\n",
+            RECORD_HEADER, self.kind, self.datetime.format(STDTIMEFMT), if self.undone { "undone" } else { "applied" });
+        for (idx, file) in self.files.iter().enumerate() {
+            contents.push_str(&format!("file\t{}\t{}\t{}\t{}\t{}\n",
+                idx,
+                encode_path(&file.target.live),
+                encode_path(&file.target.stack),
+                file.from.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()),
+                file.to));
+            for created in &file.created {
+                contents.push_str(&format!("created\t{}\t{}\n", idx, encode_path(created)));
+            }
+        }
+        contents
+    }
+
+    fn record_path(&self) -> PathBuf {
+        self.dir.join("record")
+    }
+
+    /// Forget an operation which did not complete
+    pub fn discard(self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!("unable to remove {}: {}", self.dir.display(), e);
+        }
+    }
+
+    fn saved_stack(&self, idx: usize) -> PathBuf {
+        self.dir.join(format!("{}.stack", idx))
+    }
+
+    fn saved_live(&self, idx: usize) -> PathBuf {
+        self.dir.join(format!("{}.live", idx))
+    }
+
+    fn load(dir: &Path) -> Result<Option<Operation>, BBError> {
+        let path = dir.join("record");
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(BBError::IoError(format!("unable to read {}: {}", path.display(), e))),
+        };
+        let invalid = |line: &str| BBError::ParseError(format!("invalid line in {}: '{}'", path.display(), line));
+        let mut lines = contents.lines();
+        if lines.next() != Some(RECORD_HEADER) {
+            return Err(BBError::ParseError(format!("{} is not an operation record", path.display())));
+        }
+        let mut kind = None;
+        let mut datetime = None;
+        let mut undone = false;
+        let mut files: Vec<OpFile> = Vec::new();
+        for line in lines {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            match fields.as_slice() {
+                ["kind", k] => kind = Some(OpKind::from_str(k)?),
+                ["at", at] => datetime = Some(NaiveDateTime::parse_from_str(at, STDTIMEFMT).map_err(|_| invalid(line))?),
+                ["state", state] => undone = *state == "undone",
+                ["file", _, live, stack, from, to] => files.push(OpFile {
+                    target: SwinstalledFile { live: decode_path(live)?, stack: decode_path(stack)? },
+                    from: if *from == "-" { None } else { Some(FileVersion::from_str(from)?) },
+                    to: FileVersion::from_str(to)?,
+                    created: Vec::new(),
+                }),
+                ["created", idx, created] => {
+                    let idx = idx.parse::<usize>().map_err(|_| invalid(line))?;
+                    files.get_mut(idx).ok_or_else(|| invalid(line))?.created.push(decode_path(created)?);
+                },
+                _ => return Err(invalid(line)),
+            }
+        }
+        let id = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(Some(Operation {
+            id,
+            kind: kind.ok_or_else(|| invalid("missing kind"))?,
+            datetime: datetime.ok_or_else(|| invalid("missing at"))?,
+            undone,
+            files,
+            dir: dir.to_path_buf(),
+        }))
+    }
+}
+
+/// The log of operations, a directory holding one directory per operation
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpLog {
+    dir: PathBuf,
+}
+
+impl OpLog {
+    pub fn new<P: AsRef<Path>>(dir: P) -> OpLog {
+        OpLog { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// The log in bakbuster's state directory
+    pub fn default_log() -> Result<OpLog, BBError> {
+        Ok(OpLog::new(state_dir()?.join("operations")))
+    }
+
+    /// Begin recording an operation, started at `datetime`
+    pub fn begin(&self, kind: OpKind, datetime: &NaiveDateTime) -> Result<Operation, BBError> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| BBError::IoError(format!("unable to create {}: {}", self.dir.display(), e)))?;
+        // operations begun in the same second are numbered
+        let mut n = 1;
+        loop {
+            let id = format!("{}-{}", datetime.format("%Y%m%d-%H%M%S"), n);
+            let dir = self.dir.join(&id);
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(Operation { id, kind, datetime: *datetime, undone: false, files: Vec::new(), dir }),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(BBError::IoError(format!("unable to create {}: {}", dir.display(), e))),
+            }
+        }
+    }
+
+    /// Every recorded operation, oldest first
+    pub fn operations(&self) -> Result<Vec<Operation>, BBError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(BBError::IoError(format!("unable to read {}: {}", self.dir.display(), e))),
+        };
+        let mut operations = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| BBError::IoError(format!("unable to read {}: {}", self.dir.display(), e)))?;
+            if let Some(operation) = Operation::load(&entry.path())? {
+                operations.push(operation);
+            }
+        }
+        operations.sort_by_key(|op| (op.datetime, sequence(&op.id)));
+        Ok(operations)
+    }
+
+    /// The operation with `id`, or the most recent which has not been undone
+    pub fn find(&self, id: Option<&str>) -> Result<Operation, BBError> {
+        let operations = self.operations()?;
+        let found = match id {
+            Some(id) => operations.into_iter().find(|op| op.id == id),
+            None => operations.into_iter().rev().find(|op| !op.undone),
+        };
+        found.ok_or_else(|| BBError::NonExtantPath(match id {
+            Some(id) => format!("no operation {} in {}", id, self.dir.display()),
+            None => format!("no operation to undo in {}", self.dir.display()),
+        }))
+    }
+
+    /// Remove the operations beyond the newest `keep`, and those begun before
+    /// `before`, so that they can no longer be undone. Returns the operations
+    /// removed, oldest first.
+    pub fn prune(&self, keep: Option<usize>, before: Option<NaiveDateTime>) -> Result<Vec<Operation>, BBError> {
+        let pruned = self.to_prune(keep, before)?;
+        for op in &pruned {
+            fs::remove_dir_all(&op.dir)
+                .map_err(|e| BBError::IoError(format!("unable to remove {}: {}", op.dir.display(), e)))?;
+        }
+        Ok(pruned)
+    }
+
+    /// A script removing the operations `prune` would, for review. The script
+    /// refuses to run if any of their records has changed since it was generated.
+    pub fn prune_script(&self, keep: Option<usize>, before: Option<NaiveDateTime>) -> Result<Script, BBError> {
+        let mut script = Script::new();
+        script.describe(format!("prune the operation log in {}", self.dir.display()));
+        for op in self.to_prune(keep, before)? {
+            script.describe(format!("  {} {} {}", op.id, op.kind, op.datetime.format(STDTIMEFMT)));
+            script.guard(&op.record_path())?;
+            script.remove_dir(&op.dir);
+        }
+        Ok(script)
+    }
+
+    // The operations beyond the newest `keep`, and those begun before `before`
+    fn to_prune(&self, keep: Option<usize>, before: Option<NaiveDateTime>) -> Result<Vec<Operation>, BBError> {
+        let operations = self.operations()?;
+        let excess = operations.len().saturating_sub(keep.unwrap_or(operations.len()));
+        Ok(operations.into_iter()
+                     .enumerate()
+                     .filter(|&(idx, ref op)| idx < excess || before.is_some_and(|before| op.datetime < before))
+                     .map(|(_, op)| op)
+                     .collect())
+    }
+}
+
+/// Reverse the operation with `id`, or the most recent which has not been
+/// undone. Each stack and live file is put back as it was before the operation,
+/// and the files it created are removed. Undo is refused if a file has been
+/// changed since.
+pub fn undo(oplog: &OpLog, id: Option<&str>, lock: &LockOptions) -> Result<Operation, BBError> {
+    let mut operation = find_undoable(oplog, id)?;
+    let mut locks = Vec::new();
+    for file in &operation.files {
+        locks.push(lock_stack(&file.target.stack, lock)?);
+        check_unchanged(&operation, file)?;
+    }
+    for (idx, file) in operation.files.iter().enumerate().rev() {
+        put_back(&operation.saved_stack(idx), &file.target.stack)?;
+        put_back(&operation.saved_live(idx), &file.target.live)?;
+        for created in &file.created {
+            remove_if_exists(created)?;
+        }
+        info!("undid {} of {}", operation.kind, file.target.live.display());
+    }
+    operation.undone = true;
+    operation.commit()?;
+    Ok(operation)
+}
+
+/// A script performing the undo `undo` would, for review, including marking
+/// the operation undone. The script refuses to run if any stack, live file or
+/// saved copy has changed since it was generated.
+pub fn undo_script(oplog: &OpLog, id: Option<&str>) -> Result<Script, BBError> {
+    let mut operation = find_undoable(oplog, id)?;
+    let mut script = Script::new();
+    script.describe(format!("undo {} {} begun {}", operation.kind, operation.id, operation.datetime.format(STDTIMEFMT)));
+    for (idx, file) in operation.files.iter().enumerate().rev() {
+        check_unchanged(&operation, file)?;
+        let from = file.from.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        script.describe(format!("  {} {} -> {}", file.target.live.display(), file.to, from));
+        let (saved_stack, saved_live) = (operation.saved_stack(idx), operation.saved_live(idx));
+        for path in [&file.target.stack, &file.target.live, &saved_stack, &saved_live].iter().cloned().chain(&file.created) {
+            script.guard(path)?;
+        }
+        put_back_step(&mut script, &saved_stack, &file.target.stack)?;
+        put_back_step(&mut script, &saved_live, &file.target.live)?;
+        for created in &file.created {
+            script.remove(created);
+        }
+    }
+    let record = operation.record_path();
+    script.guard(&record)?;
+    operation.undone = true;
+    script.write(&record, &operation.record_text(), Some(&FileMetadata::of(&record)?));
+    Ok(script)
+}
+
+// The operation with `id`, or the most recent which has not been undone,
+// refusing one which has been
+fn find_undoable(oplog: &OpLog, id: Option<&str>) -> Result<Operation, BBError> {
+    let operation = oplog.find(id)?;
+    if operation.undone {
+        return Err(BBError::IoError(format!("operation {} has already been undone", operation.id)));
+    }
+    Ok(operation)
+}
+
+// Refuse to undo `operation` if `file` has been changed since
+fn check_unchanged(operation: &Operation, file: &OpFile) -> Result<(), BBError> {
+    let current = if file.target.stack.exists() {
+        read_stack_history(&file.target.stack)?.current().map(|e| e.version.clone())
+    } else {
+        None
+    };
+    if current.as_ref() != Some(&file.to) {
+        return Err(BBError::IoError(format!(
+            "{} has changed since operation {}; its current install is {} rather than {}",
+            file.target.live.display(), operation.id,
+            current.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()), file.to)));
+    }
+    Ok(())
+}
+
+// Copy `saved` over `dest`, or remove `dest` if nothing was saved, as it did not
+// exist before
+fn put_back(saved: &Path, dest: &Path) -> Result<(), BBError> {
+    if saved.exists() {
+        copy_atomically(saved, dest)
+    } else {
+        remove_if_exists(dest)
+    }
+}
+
+// As `put_back`, as a script step
+fn put_back_step(script: &mut Script, saved: &Path, dest: &Path) -> Result<(), BBError> {
+    if saved.exists() {
+        let metadata = replacement_metadata(saved, dest, &Ownership::default())?;
+        script.copy(saved, dest, &metadata, dest.exists());
+    } else {
+        script.remove(dest);
+    }
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), BBError> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(BBError::IoError(format!("unable to remove {}: {}", path.display(), e))),
+    }
+}
+
+// The number distinguishing operations begun in the same second
+fn sequence(id: &str) -> u32 {
+    id.rsplit('-').next().and_then(|n| n.parse().ok()).unwrap_or(0)
+}
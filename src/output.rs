@@ -0,0 +1,252 @@
+//! output
+//!
+//! Machine readable rendering of command results. Every command produces a list
+//! of flat records, which are rendered as a versioned JSON document or as tab
+//! separated values with a header row. See the README for the schema.
+use chrono::Utc;
+use constants::STDTIMEFMT;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use timezone::{LocalTime, Zone};
+
+/// Version of the JSON document produced by `render`. Bumped whenever a field is
+/// removed or changes meaning; adding fields does not bump it.
+pub static SCHEMA_VERSION: u32 = 1;
+
+/// The fields appended by `Record::file_version_in`, for naming the columns of
+/// records describing an install
+pub static FILE_VERSION_COLUMNS: &[&str] = &["version", "timestamp", "revision", "timestamp_utc"];
+
+/// The output formats supported by every command
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Tsv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(BBError::ParseError(format!("Unable to parse format: '{}' (expected json, tsv or text)", s))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Tsv => write!(f, "tsv"),
+        }
+    }
+}
+
+/// A single field value within a record
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Null,
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Int(i)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(i: usize) -> Value {
+        Value::Int(i as i64)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Value {
+        o.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// An ordered set of named fields describing one result
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Record {
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record::default()
+    }
+
+    /// Append a field to the record
+    pub fn field<V: Into<Value>>(mut self, name: &'static str, value: V) -> Record {
+        self.fields.push((name, value.into()));
+        self
+    }
+
+    /// Append a path field, rendered lossily for display
+    pub fn path(self, name: &'static str, path: &Path) -> Record {
+        self.field(name, path.to_string_lossy().into_owned())
+    }
+
+    /// Append the `version`, `timestamp` and `revision` fields of a FileVersion
+    pub fn file_version(self, version: &FileVersion) -> Record {
+        self.field("version", version.to_string())
+            .field("timestamp", version.date_time.format(STDTIMEFMT).to_string())
+            .field("revision", version.revision.clone())
+    }
+
+    /// Append the fields of a FileVersion as `file_version` does, along with
+    /// `timestamp_utc`, the instant its timestamp names in the facility's
+    /// timezone. It is null where daylight saving makes the instant ambiguous
+    /// or impossible.
+    pub fn file_version_in(self, version: &FileVersion, facility: Zone) -> Record {
+        let utc = match facility.localize(&version.date_time) {
+            LocalTime::Single(dt) => Some(dt.with_timezone(&Utc).to_rfc3339()),
+            _ => None,
+        };
+        self.file_version(version).field("timestamp_utc", utc)
+    }
+}
+
+/// Render the records of `command` in a machine readable format. `columns`
+/// names the fields of every record, in order, so that a TSV header is printed
+/// even when there are no records. Text output is command specific and is not
+/// handled here.
+pub fn render(format: OutputFormat, command: &str, columns: &[&str], records: &[Record]) -> String {
+    debug_assert!(records.iter().all(|r| r.fields.iter().map(|(name, _)| *name).eq(columns.iter().cloned())),
+                  "{} records do not have the fields {:?}", command, columns);
+    match format {
+        OutputFormat::Json => render_json(command, records),
+        OutputFormat::Tsv => render_tsv(columns, records),
+        OutputFormat::Text => String::new(),
+    }
+}
+
+fn render_json(command: &str, records: &[Record]) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "{{\"schema_version\":{},\"command\":{},\"results\":[", SCHEMA_VERSION, json_string(command));
+    for (idx, record) in records.iter().enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        out.push('{');
+        for (fidx, (name, value)) in record.fields.iter().enumerate() {
+            if fidx > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{}:{}", json_string(name), json_value(value));
+        }
+        out.push('}');
+    }
+    out.push_str("]}\n");
+    out
+}
+
+fn render_tsv(columns: &[&str], records: &[Record]) -> String {
+    let mut out = columns.join("\t");
+    out.push('\n');
+    for record in records {
+        let row = record.fields.iter().map(|(_, value)| tsv_value(value)).collect::<Vec<_>>();
+        out.push_str(&row.join("\t"));
+        out.push('\n');
+    }
+    out
+}
+
+fn json_value(value: &Value) -> String {
+    match *value {
+        Value::Str(ref s) => json_string(s),
+        Value::Int(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => "null".to_string(),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Tabs and newlines within values are escaped so that each record stays on one
+// line. Null is rendered as an empty field.
+fn tsv_value(value: &Value) -> String {
+    match *value {
+        Value::Str(ref s) => s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"),
+        Value::Int(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_json_escapes() {
+        let records = vec![Record::new().field("line", "a \"b\"\tc").field("revision", None::<String>)];
+        assert_eq!(
+            render(OutputFormat::Json, "grep", &["line", "revision"], &records),
+            "{\"schema_version\":1,\"command\":\"grep\",\"results\":[{\"line\":\"a \\\"b\\\"\\tc\",\"revision\":null}]}\n"
+        );
+    }
+
+    #[test]
+    fn render_tsv_with_header() {
+        let records = vec![
+            Record::new().field("version", "20181105-103813").field("is_current", true),
+            Record::new().field("version", "20181106-104603").field("is_current", false),
+        ];
+        assert_eq!(
+            render(OutputFormat::Tsv, "log", &["version", "is_current"], &records),
+            "version\tis_current\n20181105-103813\ttrue\n20181106-104603\tfalse\n"
+        );
+        assert_eq!(render(OutputFormat::Tsv, "log", &["version", "is_current"], &[]), "version\tis_current\n");
+    }
+}
@@ -0,0 +1,273 @@
+//! packages
+//!
+//! Answers "which version of package foo was in packages.xml on date D?" and
+//! "when did foo change versions?" by parsing each backup of packages.xml
+//! referenced by its swinstall stack.
+//!
+//! Backups never change once written, so the parsed contents of each backup are
+//! cached per stack file, keyed by FileVersion.
+use chrono::naive::NaiveDateTime;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use install::write_atomically;
+use layout::{BakLayout, SwinstalledFile, SwinstallLayout};
+use stack_history_parser::read_stack_history;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use utils::cache_dir;
+use xml::{
+    reader::EventReader,
+    reader::XmlEvent,
+};
+
+/// Package name to package version, as listed in a single packages.xml
+pub type Packages = HashMap<String, String>;
+
+/// The version of a package as of a particular install of packages.xml
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PackageVersion {
+    /// The install of packages.xml
+    pub file_version: FileVersion,
+    /// The version of the package, or None if the package was not listed
+    pub version: Option<String>,
+}
+
+/// Given an input which implements the Read trait, collect the name and version
+/// attributes of every `package` tag.
+///
+/// # Example
+///
+/// ```xml
+/// <packages>
+///     <package name="foo" version="2.3" />
+/// </packages>
+/// ```
+pub fn parse_packages<R: Read>(input: R) -> Result<Packages, BBError> {
+    let parser = EventReader::new(input);
+    let mut packages = Packages::new();
+
+    for xml_event in parser {
+        match xml_event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) if name.local_name.as_str() == "package" => {
+                let mut pkg_name = None;
+                let mut pkg_version = None;
+                for attr in attributes {
+                    match attr.name.local_name.as_str() {
+                        "name" => pkg_name = Some(attr.value),
+                        "version" => pkg_version = Some(attr.value),
+                        _ => {}
+                    }
+                }
+                if let (Some(pkg_name), Some(pkg_version)) = (pkg_name, pkg_version) {
+                    packages.insert(pkg_name, pkg_version);
+                }
+            },
+            Err(e) => {
+                return Err(BBError::ParseError(format!("problem: {}", e)));
+            },
+            _ => {}
+        }
+    }
+    Ok(packages)
+}
+
+/// Parsed packages for each backup of a single packages.xml, optionally
+/// persisted to a cache file between runs.
+#[derive(Debug, Default)]
+pub struct PackageCache {
+    cache_file: Option<PathBuf>,
+    entries: HashMap<FileVersion, Packages>,
+    dirty: bool,
+}
+
+impl PackageCache {
+    /// An in-memory cache which is never persisted
+    pub fn new() -> PackageCache {
+        PackageCache::default()
+    }
+
+    /// A cache backed by the supplied file. The file is loaded if it exists.
+    pub fn with_cache_file<P: Into<PathBuf>>(cache_file: P) -> Result<PackageCache, BBError> {
+        let cache_file = cache_file.into();
+        let mut cache = PackageCache::new();
+        if cache_file.exists() {
+            cache.entries = read_cache_file(&cache_file)?;
+        }
+        cache.cache_file = Some(cache_file);
+        Ok(cache)
+    }
+
+    /// The default cache file for the supplied swinstalled packages.xml, which
+    /// lives in the user's cache directory. It is named for the stack's
+    /// canonical path, so that every path to the same stack shares it.
+    pub fn default_cache_file(target: &SwinstalledFile) -> Result<PathBuf, BBError> {
+        let stack = canonical_stack(&target.stack)?;
+        // the stack path with each '/' replaced by '%', without assuming UTF-8
+        let mut key = OsString::new();
+        for (idx, component) in stack.iter().enumerate() {
+            if idx > 0 {
+                key.push("%");
+            }
+            if component != "/" {
+                key.push(component);
+            }
+        }
+        Ok(cache_dir()?.join("packages").join(key))
+    }
+
+    /// Retrieve the packages listed in the backup of `target` at `version`,
+    /// parsing the backup if it has not been cached yet.
+    pub fn packages(&mut self, target: &SwinstalledFile, version: &FileVersion) -> Result<&Packages, BBError> {
+        if !self.entries.contains_key(version) {
+            let backup = target.backup_path(version)?;
+            let filehandle = File::open(&backup)
+                             .map_err(|e| BBError::IoError(format!("unable to open {}: {}", backup.display(), e)))?;
+            let packages = parse_packages(BufReader::new(filehandle))?;
+            self.entries.insert(version.clone(), packages);
+            self.dirty = true;
+        }
+        Ok(&self.entries[version])
+    }
+
+    /// Write the cache out to its cache file, if it has one and has changed.
+    pub fn save(&mut self) -> Result<(), BBError> {
+        if let (Some(cache_file), true) = (self.cache_file.as_ref(), self.dirty) {
+            write_cache_file(cache_file, &self.entries)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// Find the version of package `name` in the swinstalled `file` as of `datetime`.
+pub fn package_version_on<P: AsRef<Path>>(
+    file: P,
+    name: &str,
+    datetime: &NaiveDateTime,
+    cache: &mut PackageCache,
+) -> Result<PackageVersion, BBError> {
+    package_version_on_with_layout(file, name, datetime, cache, &SwinstallLayout::default())
+}
+
+/// As `package_version_on`, locating the history of `file` using `layout`.
+pub fn package_version_on_with_layout<P: AsRef<Path>>(
+    file: P,
+    name: &str,
+    datetime: &NaiveDateTime,
+    cache: &mut PackageCache,
+    layout: &dyn BakLayout,
+) -> Result<PackageVersion, BBError> {
+    let file = file.as_ref();
+    let target = SwinstalledFile::new(file, layout)?;
+    let history = read_stack_history(&target.stack)?;
+    let entry = history.entry_on(datetime)
+                       .ok_or(BBError::NoVersionFound(format!("{} had no installed version on or before {}", file.display(), datetime)))?;
+    let version = cache.packages(&target, &entry.version)?.get(name).cloned();
+    Ok(PackageVersion { file_version: entry.version.clone(), version })
+}
+
+/// Return the installs of the swinstalled `file` at which the version of package
+/// `name` changed, oldest first. The first install is always included.
+pub fn package_history<P: AsRef<Path>>(
+    file: P,
+    name: &str,
+    cache: &mut PackageCache,
+) -> Result<Vec<PackageVersion>, BBError> {
+    package_history_with_layout(file, name, cache, &SwinstallLayout::default())
+}
+
+/// As `package_history`, locating the history of `file` using `layout`.
+pub fn package_history_with_layout<P: AsRef<Path>>(
+    file: P,
+    name: &str,
+    cache: &mut PackageCache,
+    layout: &dyn BakLayout,
+) -> Result<Vec<PackageVersion>, BBError> {
+    let target = SwinstalledFile::new(file.as_ref(), layout)?;
+    let history = read_stack_history(&target.stack)?;
+    let mut changes: Vec<PackageVersion> = Vec::new();
+    for entry in history.installed() {
+        let version = cache.packages(&target, &entry.version)?.get(name).cloned();
+        if changes.last().map(|c| c.version != version).unwrap_or(true) {
+            changes.push(PackageVersion { file_version: entry.version.clone(), version });
+        }
+    }
+    Ok(changes)
+}
+
+// The cache file is a simple line oriented format. Each backup starts with a
+// line of the form `@<fileversion>`, followed by one `name<TAB>version` line
+// per package.
+fn read_cache_file(cache_file: &Path) -> Result<HashMap<FileVersion, Packages>, BBError> {
+    let filehandle = File::open(cache_file)
+                     .map_err(|e| BBError::IoError(format!("unable to open {}: {}", cache_file.display(), e)))?;
+    let mut entries = HashMap::new();
+    let mut current: Option<FileVersion> = None;
+    for line in BufReader::new(filehandle).lines() {
+        let line = line.map_err(|e| BBError::IoError(format!("unable to read {}: {}", cache_file.display(), e)))?;
+        if let Some(version) = line.strip_prefix('@') {
+            let version = FileVersion::from_str(version)?;
+            entries.insert(version.clone(), Packages::new());
+            current = Some(version);
+        } else if let (Some(version), Some(idx)) = (current.as_ref(), line.find('\t')) {
+            if let Some(packages) = entries.get_mut(version) {
+                packages.insert(line[..idx].to_string(), line[idx + 1..].to_string());
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// Written atomically, as concurrent queries of the same packages.xml share the
+// cache file
+fn write_cache_file(cache_file: &Path, entries: &HashMap<FileVersion, Packages>) -> Result<(), BBError> {
+    let to_io_err = |e: ::std::io::Error| BBError::IoError(format!("unable to write {}: {}", cache_file.display(), e));
+    if let Some(parent) = cache_file.parent() {
+        fs::create_dir_all(parent).map_err(to_io_err)?;
+    }
+    write_atomically(cache_file, |writer| {
+        for (version, packages) in entries {
+            writeln!(writer, "@{}", version).map_err(to_io_err)?;
+            for (name, pkg_version) in packages {
+                writeln!(writer, "{}\t{}", name, pkg_version).map_err(to_io_err)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+// `stack` with its directory resolved, so that every path to it is the same
+fn canonical_stack(stack: &Path) -> Result<PathBuf, BBError> {
+    match (stack.parent(), stack.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent == Path::new("") { Path::new(".") } else { parent };
+            fs::canonicalize(parent)
+                .map(|parent| parent.join(name))
+                .map_err(|e| BBError::IoError(format!("unable to resolve {}: {}", parent.display(), e)))
+        },
+        _ => Ok(stack.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_package_versions() {
+        let xml =
+r#"<packages>
+    <package name="foo" version="2.3" />
+    <package name="bar" version="1.0.1" />
+</packages>"#;
+
+        let packages = parse_packages(xml.as_bytes()).unwrap();
+        assert_eq!(packages.get("foo"), Some(&"2.3".to_string()));
+        assert_eq!(packages.get("bar"), Some(&"1.0.1".to_string()));
+        assert_eq!(packages.len(), 2);
+    }
+}
@@ -0,0 +1,300 @@
+//! paths
+//!
+//! Normalizes paths supplied by users and scripts, which are frequently written
+//! in terms of environment variables, eg `$FACILITY_ROOT/etc/packages.xml` or
+//! `~/prefs/foo_preference.yaml`, and which are often reached through symlinked
+//! show and facility directories.
+use errors::BBError;
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+// the most symlinks followed while resolving a single path, as with ELOOP
+const MAX_SYMLINKS: usize = 40;
+
+/// How the directory holding a live file is determined
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ParentResolution {
+    /// Resolve symlinked directories, as `pwd -P` does. The bak directory is
+    /// found beside the file's real location.
+    #[default]
+    Physical,
+    /// Take the directories as written, as `pwd -L` does, applying `..`
+    /// lexically.
+    Logical,
+}
+
+impl FromStr for ParentResolution {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "physical" => Ok(ParentResolution::Physical),
+            "logical" => Ok(ParentResolution::Logical),
+            _ => Err(BBError::ParseError(format!("Unable to parse parent resolution: '{}' (expected physical or logical)", s))),
+        }
+    }
+}
+
+/// A symlink followed while resolving a path
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FollowedLink {
+    /// The symlink, as an absolute path
+    pub link: PathBuf,
+    /// Where it pointed, as an absolute path
+    pub target: PathBuf,
+}
+
+/// A path resolved by `resolve_path`, with the symlinks followed on the way
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ResolvedPath {
+    pub path: PathBuf,
+    pub followed: Vec<FollowedLink>,
+}
+
+/// Expand a leading `~` and any `$VAR` or `${VAR}` references in `path`. A `$`
+/// which is not followed by a variable name is left alone. Referring to an unset
+/// variable is an `EnvVarError`.
+pub fn expand_path(path: &str) -> Result<PathBuf, BBError> {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&var("HOME")?);
+        rest = &rest[1..];
+    }
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        let (name, remainder) = if rest.starts_with('{') {
+            let end = rest.find('}')
+                          .ok_or(BBError::ParseError(format!("unterminated ${{ in path '{}'", path)))?;
+            (&rest[1..end], &rest[end + 1..])
+        } else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&var(name)?);
+            rest = remainder;
+        }
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+/// Expand `path` as `expand_path` does, make it absolute and resolve symlinked
+/// directories, so that it names the location swinstall recorded. The path need
+/// not exist.
+pub fn normalize_path<P: AsRef<Path>>(path: P) -> Result<PathBuf, BBError> {
+    resolve_path(path, ParentResolution::Physical).map(|resolved| resolved.path)
+}
+
+/// Expand `path` as `expand_path` does and make it absolute. With
+/// `ParentResolution::Physical` every symlinked directory along the way is
+/// resolved and reported; a final component which links to a file is kept, as
+/// that is the name swinstall installed. Components which do not exist are
+/// appended as written.
+pub fn resolve_path<P: AsRef<Path>>(path: P, mode: ParentResolution) -> Result<ResolvedPath, BBError> {
+    let path = path.as_ref();
+    let expanded = match path.to_str() {
+        Some(s) => expand_path(s)?,
+        None => path.to_path_buf(),
+    };
+    let absolute = if expanded.is_absolute() {
+        expanded
+    } else {
+        env::current_dir()
+            .map_err(|e| BBError::IoError(format!("unable to get current directory: {}", e)))?
+            .join(expanded)
+    };
+    match mode {
+        ParentResolution::Logical => Ok(ResolvedPath { path: clean(&absolute), followed: Vec::new() }),
+        ParentResolution::Physical => resolve_physical(&absolute),
+    }
+}
+
+fn resolve_physical(absolute: &Path) -> Result<ResolvedPath, BBError> {
+    let mut pending = absolute.components()
+                              .map(|c| c.as_os_str().to_os_string())
+                              .collect::<VecDeque<OsString>>();
+    let mut resolved = PathBuf::new();
+    let mut followed = Vec::new();
+    // once a component is missing, everything after it is applied lexically
+    let mut exists = true;
+    while let Some(component) = pending.pop_front() {
+        if component == "." {
+            continue;
+        }
+        if component == ".." {
+            resolved.pop();
+            continue;
+        }
+        resolved.push(&component);
+        if !exists {
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(&resolved) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                exists = false;
+                continue;
+            }
+        };
+        if !metadata.file_type().is_symlink() {
+            continue;
+        }
+        // keep a final link to a file, resolve links to directories
+        if pending.is_empty() && !fs::metadata(&resolved).map(|m| m.is_dir()).unwrap_or(false) {
+            continue;
+        }
+        if followed.len() == MAX_SYMLINKS {
+            return Err(BBError::IoError(format!("too many levels of symlinks resolving {}", absolute.display())));
+        }
+        let target = fs::read_link(&resolved)
+                     .map_err(|e| BBError::IoError(format!("unable to read link {}: {}", resolved.display(), e)))?;
+        let link = resolved.clone();
+        resolved.pop();
+        let target = resolved.join(target);
+        followed.push(FollowedLink { link, target: clean(&target) });
+        // resolve the target from the root, as it may itself contain links
+        for component in target.components().rev() {
+            pending.push_front(component.as_os_str().to_os_string());
+        }
+        resolved = PathBuf::new();
+    }
+    Ok(ResolvedPath { path: resolved, followed })
+}
+
+/// Concatenate two OsStrs, eg a file name which may not be UTF-8 and a suffix
+pub fn os_concat<A: AsRef<OsStr>, B: AsRef<OsStr>>(a: A, b: B) -> OsString {
+    let mut joined = a.as_ref().to_os_string();
+    joined.push(b);
+    joined
+}
+
+/// The remainder of `s` after `prefix`, if `s` starts with it
+pub fn os_strip_prefix<S: AsRef<OsStr>, P: AsRef<OsStr>>(s: S, prefix: P) -> Option<OsString> {
+    let (s, prefix) = (s.as_ref().as_encoded_bytes(), prefix.as_ref().as_encoded_bytes());
+    s.strip_prefix(prefix).map(os_from_bytes)
+}
+
+/// The start of `s` before `suffix`, if `s` ends with it
+pub fn os_strip_suffix<S: AsRef<OsStr>, P: AsRef<OsStr>>(s: S, suffix: P) -> Option<OsString> {
+    let (s, suffix) = (s.as_ref().as_encoded_bytes(), suffix.as_ref().as_encoded_bytes());
+    s.strip_suffix(suffix).map(os_from_bytes)
+}
+
+/// Encode a path as a single line of printable ASCII, for journals and logs.
+/// `%`, whitespace, control characters and bytes which are not ASCII, as in
+/// non-UTF-8 names, are written as `%XX`.
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        if byte.is_ascii_graphic() && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The inverse of `encode_path`
+pub fn decode_path(encoded: &str) -> Result<PathBuf, BBError> {
+    let invalid = || BBError::ParseError(format!("invalid encoded path: '{}'", encoded));
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut chars = encoded.bytes();
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex = [chars.next().ok_or_else(invalid)?, chars.next().ok_or_else(invalid)?];
+            let hex = ::std::str::from_utf8(&hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Ok(PathBuf::from(os_from_bytes(&bytes)))
+}
+
+#[cfg(unix)]
+fn os_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_os_string()
+}
+
+// elsewhere, names which are not valid unicode are converted lossily
+#[cfg(not(unix))]
+fn os_from_bytes(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// Remove `.` components and apply `..` lexically
+fn clean(path: &Path) -> PathBuf {
+    let mut cleaned = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { cleaned.pop(); },
+            other => cleaned.push(other.as_os_str()),
+        }
+    }
+    cleaned
+}
+
+fn var(name: &str) -> Result<String, BBError> {
+    env::var(name).map_err(|_| BBError::EnvVarError(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_variables() {
+        env::set_var("BAKBUSTER_TEST_ROOT", "/dd/facility");
+        assert_eq!(expand_path("$BAKBUSTER_TEST_ROOT/etc/packages.xml").unwrap(),
+                   PathBuf::from("/dd/facility/etc/packages.xml"));
+        assert_eq!(expand_path("${BAKBUSTER_TEST_ROOT}_old/etc").unwrap(),
+                   PathBuf::from("/dd/facility_old/etc"));
+        assert_eq!(expand_path("/cost/$/x").unwrap(), PathBuf::from("/cost/$/x"));
+        assert_eq!(expand_path("$BAKBUSTER_TEST_UNSET/etc"),
+                   Err(BBError::EnvVarError("BAKBUSTER_TEST_UNSET".to_string())));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn strips_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+        // "caf\xe9.yaml" in Latin-1
+        let name = OsStr::from_bytes(b"caf\xe9.yaml");
+        let stack = os_concat(name, "_swinstall_stack");
+        assert_eq!(os_strip_suffix(&stack, "_swinstall_stack"), Some(name.to_os_string()));
+        assert_eq!(os_strip_prefix(&stack, name), Some(OsString::from("_swinstall_stack")));
+        assert_eq!(os_strip_suffix(&stack, ".yaml"), None);
+    }
+
+    #[test]
+    fn encoded_paths_round_trip() {
+        let path = Path::new("/dd/show 1/100%/foo.yaml");
+        assert_eq!(encode_path(path), "/dd/show%201/100%25/foo.yaml");
+        assert_eq!(decode_path(&encode_path(path)).unwrap(), path);
+        assert!(decode_path("/dd/%2").is_err());
+    }
+
+    #[test]
+    fn parent_resolution_from_str() {
+        assert_eq!(ParentResolution::from_str("physical"), Ok(ParentResolution::Physical));
+        assert_eq!(ParentResolution::from_str("logical"), Ok(ParentResolution::Logical));
+        assert!(ParentResolution::from_str("real").is_err());
+    }
+
+    #[test]
+    fn cleans_relative_components() {
+        assert_eq!(clean(Path::new("/dd/./facility/../shows/etc")), PathBuf::from("/dd/shows/etc"));
+    }
+}
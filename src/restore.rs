@@ -0,0 +1,438 @@
+//! restore
+//!
+//! Restores every swinstalled file beneath a root to its install as of a
+//! datetime, as a single transaction. Each file is restored the way swinstall
+//! rolls back: the current marker moves to the chosen entry and the live file is
+//! replaced with its backup. Live files keep their mode and ownership, and
+//! take the modification time of the backup.
+//!
+//! Progress is recorded in a journal, along with a copy of each stack and live
+//! file taken before it is touched. If any file fails, the files already
+//! restored are put back. If bakbuster itself is interrupted, the journal
+//! remains, and the restore may be resumed or aborted.
+use chrono::NaiveDateTime;
+use constants::STDTIMEFMT;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use install::{copy_atomically, stack_history_text, write_atomically};
+use layout::{BakLayout, SwinstalledFile};
+use lock::{lock_stack, LockOptions};
+use metadata::{check_can_replace, replacement_metadata, report_mismatches, FileMetadata, Ownership};
+use oplog::Operation;
+use paths::{decode_path, encode_path};
+use scan::find_swinstalled_files_with_layout;
+use script::Script;
+use stack_history_parser::{read_stack_history, write_stack_history, ResolvePolicy};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use utils::state_dir;
+
+/// The first line of every journal, naming its format version
+pub static JOURNAL_HEADER: &str = "bakbuster-restore-journal 1";
+
+/// The default directory holding the journal of a restore in progress
+pub fn default_journal_dir() -> Result<PathBuf, BBError> {
+    Ok(state_dir()?.join("restore"))
+}
+
+/// How far the restore of a single file has progressed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EntryState {
+    /// Nothing has been touched
+    Pending,
+    /// The stack and live file have been copied into the journal directory
+    Saved,
+    /// The stack and live file have been restored
+    Done,
+}
+
+impl FromStr for EntryState {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(EntryState::Pending),
+            "saved" => Ok(EntryState::Saved),
+            "done" => Ok(EntryState::Done),
+            _ => Err(BBError::ParseError(format!("Unable to parse journal entry state: '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for EntryState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EntryState::Pending => write!(f, "pending"),
+            EntryState::Saved => write!(f, "saved"),
+            EntryState::Done => write!(f, "done"),
+        }
+    }
+}
+
+/// A single file within a restore
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RestoreEntry {
+    pub target: SwinstalledFile,
+    /// The version current when the restore was planned
+    pub from: Option<FileVersion>,
+    /// The version being restored
+    pub to: FileVersion,
+    pub state: EntryState,
+}
+
+/// A restore in progress, as recorded on disk
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Journal {
+    pub root: PathBuf,
+    pub datetime: NaiveDateTime,
+    pub entries: Vec<RestoreEntry>,
+    dir: PathBuf,
+}
+
+impl Journal {
+    /// Load the journal in `dir`, if there is one
+    pub fn load(dir: &Path) -> Result<Option<Journal>, BBError> {
+        let path = dir.join("journal");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)
+                       .map_err(|e| BBError::IoError(format!("unable to read {}: {}", path.display(), e)))?;
+        let invalid = |line: &str| BBError::ParseError(format!("invalid line in {}: '{}'", path.display(), line));
+        let mut lines = contents.lines();
+        if lines.next() != Some(JOURNAL_HEADER) {
+            return Err(BBError::ParseError(format!("{} is not a restore journal", path.display())));
+        }
+        let mut root = None;
+        let mut datetime = None;
+        let mut entries = Vec::new();
+        for line in lines {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            match fields.as_slice() {
+                ["root", encoded] => root = Some(decode_path(encoded)?),
+                ["at", at] => datetime = Some(NaiveDateTime::parse_from_str(at, STDTIMEFMT).map_err(|_| invalid(line))?),
+                ["entry", state, live, stack, from, to] => entries.push(RestoreEntry {
+                    target: SwinstalledFile { live: decode_path(live)?, stack: decode_path(stack)? },
+                    from: if *from == "-" { None } else { Some(FileVersion::from_str(from)?) },
+                    to: FileVersion::from_str(to)?,
+                    state: EntryState::from_str(state)?,
+                }),
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(Some(Journal {
+            root: root.ok_or_else(|| invalid("missing root"))?,
+            datetime: datetime.ok_or_else(|| invalid("missing at"))?,
+            entries,
+            dir: dir.to_path_buf(),
+        }))
+    }
+
+    fn text(&self) -> String {
+        let mut contents = format!("{}\nroot\t{}\nat\t{}\n", JOURNAL_HEADER, encode_path(&self.root), self.datetime.format(STDTIMEFMT));
+        for entry in &self.entries {
+            contents.push_str(&format!("entry\t{}\t{}\t{}\t{}\t{}\n",
+                entry.state,
+                encode_path(&entry.target.live),
+                encode_path(&entry.target.stack),
+                entry.from.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()),
+                entry.to));
+        }
+        contents
+    }
+
+    // Written in full beside the journal and then linked to it, which fails if
+    // it exists, so that of two restores starting at once only one proceeds
+    fn create(&self) -> Result<(), BBError> {
+        let journal = self.dir.join("journal");
+        let pending = self.dir.join(format!("journal.{}", process::id()));
+        fs::write(&pending, self.text())
+            .map_err(|e| BBError::IoError(format!("unable to write {}: {}", pending.display(), e)))?;
+        let linked = fs::hard_link(&pending, &journal);
+        let _ = fs::remove_file(&pending);
+        match linked {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+                check_no_journal(&self.dir)?;
+                Err(BBError::IoError(format!("another restore was using {} at the same time", self.dir.display())))
+            },
+            Err(e) => Err(BBError::IoError(format!("unable to create {}: {}", journal.display(), e))),
+        }
+    }
+
+    fn save(&self) -> Result<(), BBError> {
+        let contents = self.text();
+        write_atomically(&self.dir.join("journal"), |output| {
+            output.write_all(contents.as_bytes())
+                  .map_err(|e| BBError::IoError(format!("unable to write journal: {}", e)))
+        })
+    }
+
+    fn saved_stack(&self, idx: usize) -> PathBuf {
+        self.dir.join(format!("{}.stack", idx))
+    }
+
+    fn saved_live(&self, idx: usize) -> PathBuf {
+        self.dir.join(format!("{}.live", idx))
+    }
+
+    fn set_state(&mut self, idx: usize, state: EntryState) -> Result<(), BBError> {
+        self.entries[idx].state = state;
+        self.save()
+    }
+
+    // the journal and saved copies are no longer needed
+    fn remove(self) -> Result<(), BBError> {
+        fs::remove_dir_all(&self.dir)
+            .map_err(|e| BBError::IoError(format!("unable to remove {}: {}", self.dir.display(), e)))
+    }
+}
+
+/// The files beneath `root` which a restore to `datetime` would change, and the
+/// versions they would be restored to. Files with no install at `datetime` are
+/// left alone.
+pub fn plan_restore<P: AsRef<Path>>(
+    root: P,
+    datetime: &NaiveDateTime,
+    policy: ResolvePolicy,
+    layout: &dyn BakLayout,
+) -> Result<Vec<RestoreEntry>, BBError> {
+    let mut entries = Vec::new();
+    for target in find_swinstalled_files_with_layout(root, layout)? {
+        let history = read_stack_history(&target.stack)?;
+        let to = match history.resolve(datetime, policy) {
+            Some(entry) => entry.version.clone(),
+            None => {
+                warn!("{} had no install on or before {}, leaving it alone", target.live.display(), datetime.format(STDTIMEFMT));
+                continue;
+            }
+        };
+        let from = history.current().map(|e| e.version.clone());
+        if from.as_ref() != Some(&to) {
+            entries.push(RestoreEntry { target, from, to, state: EntryState::Pending });
+        }
+    }
+    Ok(entries)
+}
+
+/// Restore every swinstalled file beneath `root` to its install as of
+/// `datetime`, journaling progress in `journal_dir`. On failure, files already
+/// restored are put back before the error is returned. Once complete, the
+/// restore is recorded in `operation`, if given, so that it may be undone.
+pub fn restore<P: AsRef<Path>>(
+    root: P,
+    datetime: &NaiveDateTime,
+    policy: ResolvePolicy,
+    layout: &dyn BakLayout,
+    lock: &LockOptions,
+    journal_dir: &Path,
+    operation: Option<&mut Operation>,
+) -> Result<Vec<RestoreEntry>, BBError> {
+    let entries = plan_restore(root.as_ref(), datetime, policy, layout)?;
+    apply_restore(root, datetime, entries, lock, journal_dir, operation)
+}
+
+/// Restore the files in `entries`, as planned, as a single transaction as
+/// `restore` does. `root` and `datetime` describe the restore in the journal.
+pub fn apply_restore<P: AsRef<Path>>(
+    root: P,
+    datetime: &NaiveDateTime,
+    entries: Vec<RestoreEntry>,
+    lock: &LockOptions,
+    journal_dir: &Path,
+    operation: Option<&mut Operation>,
+) -> Result<Vec<RestoreEntry>, BBError> {
+    check_no_journal(journal_dir)?;
+    if entries.is_empty() {
+        return Ok(entries);
+    }
+    check_permissions(&entries)?;
+    fs::create_dir_all(journal_dir)
+        .map_err(|e| BBError::IoError(format!("unable to create {}: {}", journal_dir.display(), e)))?;
+    let journal = Journal { root: root.as_ref().to_path_buf(), datetime: *datetime, entries, dir: journal_dir.to_path_buf() };
+    journal.create()?;
+    run(journal, lock, operation)
+}
+
+/// A script performing the restore `restore` would, for review. The script
+/// refuses to run if any stack, backup or live file has changed since it was
+/// generated.
+pub fn restore_script<P: AsRef<Path>>(
+    root: P,
+    datetime: &NaiveDateTime,
+    policy: ResolvePolicy,
+    layout: &dyn BakLayout,
+) -> Result<Script, BBError> {
+    let entries = plan_restore(root.as_ref(), datetime, policy, layout)?;
+    planned_restore_script(root, datetime, &entries)
+}
+
+/// A script restoring the files in `entries`, as planned, for review, as
+/// `restore_script` does. `root` and `datetime` describe the restore.
+pub fn planned_restore_script<P: AsRef<Path>>(
+    root: P,
+    datetime: &NaiveDateTime,
+    entries: &[RestoreEntry],
+) -> Result<Script, BBError> {
+    let mut script = Script::new();
+    script.describe(format!("restore {} as of {}", root.as_ref().display(), datetime.format(STDTIMEFMT)));
+    for entry in entries {
+        let target = &entry.target;
+        let mut history = read_stack_history(&target.stack)?;
+        if !history.set_current(&entry.to) {
+            return Err(BBError::NoVersionFound(format!("{} has no install {}", target.stack.display(), entry.to)));
+        }
+        let backup = target.backup_path(&entry.to)?;
+        if !backup.is_file() {
+            return Err(BBError::NonExtantPath(backup.display().to_string()));
+        }
+        report_mismatches(&backup, &target.live)?;
+        let from = entry.from.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        script.describe(format!("  {} {} -> {}", target.live.display(), from, entry.to));
+        for path in &[&target.stack, &backup, &target.live] {
+            script.guard(path)?;
+        }
+        script.write(&target.stack, &stack_history_text(&history)?, Some(&FileMetadata::of(&target.stack)?));
+        let live_metadata = replacement_metadata(&backup, &target.live, &Ownership::default())?;
+        script.copy(&backup, &target.live, &live_metadata, target.live.exists());
+    }
+    Ok(script)
+}
+
+/// Continue the interrupted restore recorded in `journal_dir`, recording it in
+/// `operation`, if given, once complete
+pub fn resume_restore(
+    journal_dir: &Path,
+    lock: &LockOptions,
+    operation: Option<&mut Operation>,
+) -> Result<Vec<RestoreEntry>, BBError> {
+    run(load_existing(journal_dir)?, lock, operation)
+}
+
+/// Put back every file restored by the interrupted restore recorded in
+/// `journal_dir`, returning the files which were put back
+pub fn abort_restore(journal_dir: &Path, lock: &LockOptions) -> Result<Vec<RestoreEntry>, BBError> {
+    let mut journal = load_existing(journal_dir)?;
+    let touched = journal.entries.iter().filter(|e| e.state != EntryState::Pending).cloned().collect();
+    roll_back(&mut journal, lock)?;
+    journal.remove()?;
+    Ok(touched)
+}
+
+// Refuse to start a restore while another is unfinished
+fn check_no_journal(journal_dir: &Path) -> Result<(), BBError> {
+    match Journal::load(journal_dir)? {
+        Some(journal) => Err(BBError::IoError(format!(
+            "an interrupted restore of {} is recorded in {}; resume or abort it first",
+            journal.root.display(), journal_dir.display()))),
+        None => Ok(()),
+    }
+}
+
+fn load_existing(journal_dir: &Path) -> Result<Journal, BBError> {
+    Journal::load(journal_dir)?
+        .ok_or_else(|| BBError::NonExtantPath(format!("no interrupted restore is recorded in {}", journal_dir.display())))
+}
+
+fn run(mut journal: Journal, lock: &LockOptions, operation: Option<&mut Operation>) -> Result<Vec<RestoreEntry>, BBError> {
+    for idx in 0..journal.entries.len() {
+        if journal.entries[idx].state == EntryState::Done {
+            continue;
+        }
+        if let Err(e) = apply(&mut journal, idx, lock) {
+            let live = journal.entries[idx].target.live.clone();
+            return Err(match roll_back(&mut journal, lock) {
+                Ok(()) => {
+                    journal.remove()?;
+                    BBError::IoError(format!("restore of {} failed, and every file has been put back: {}", live.display(), e))
+                },
+                Err(rollback) => BBError::IoError(format!(
+                    "restore of {} failed: {}; putting files back also failed: {}. The journal has been kept; abort the restore to try again",
+                    live.display(), e, rollback)),
+            });
+        }
+    }
+    if let Some(operation) = operation {
+        // the copies taken before each file was restored are what undo puts back
+        for (idx, entry) in journal.entries.iter().enumerate() {
+            let saved_live = journal.saved_live(idx);
+            operation.record(&entry.target, entry.from.clone(), entry.to.clone(),
+                             Some(&journal.saved_stack(idx)), Some(saved_live.as_path()).filter(|p| p.exists()), Vec::new())?;
+        }
+    }
+    let entries = journal.entries.clone();
+    journal.remove()?;
+    Ok(entries)
+}
+
+fn apply(journal: &mut Journal, idx: usize, lock: &LockOptions) -> Result<(), BBError> {
+    let entry = journal.entries[idx].clone();
+    let _lock = lock_stack(&entry.target.stack, lock)?;
+    let saved_stack = journal.saved_stack(idx);
+    let saved_live = journal.saved_live(idx);
+
+    if entry.state == EntryState::Pending {
+        let history = read_stack_history(&entry.target.stack)?;
+        if history.current().map(|e| &e.version) != entry.from.as_ref() {
+            return Err(BBError::IoError(format!("{} has changed since the restore began", entry.target.stack.display())));
+        }
+        copy_atomically(&entry.target.stack, &saved_stack)?;
+        if entry.target.live.exists() {
+            copy_atomically(&entry.target.live, &saved_live)?;
+        }
+        journal.set_state(idx, EntryState::Saved)?;
+    }
+
+    // restore from the saved copy, so that resuming is repeatable
+    let mut history = read_stack_history(&saved_stack)?;
+    if !history.set_current(&entry.to) {
+        return Err(BBError::NoVersionFound(format!("{} has no install {}", entry.target.stack.display(), entry.to)));
+    }
+    let backup = entry.target.backup_path(&entry.to)?;
+    if !backup.is_file() {
+        return Err(BBError::NonExtantPath(backup.display().to_string()));
+    }
+    report_mismatches(&backup, &entry.target.live)?;
+    write_atomically(&entry.target.stack, |output| write_stack_history(&history, output))?;
+    copy_atomically(&backup, &entry.target.live)?;
+    journal.set_state(idx, EntryState::Done)
+}
+
+// Check that every stack and live file can be replaced, keeping its mode and
+// ownership, before any is touched
+fn check_permissions(entries: &[RestoreEntry]) -> Result<(), BBError> {
+    for entry in entries {
+        check_can_replace(&entry.target.stack, &FileMetadata::of(&entry.target.stack)?)?;
+        let backup = entry.target.backup_path(&entry.to)?;
+        if backup.is_file() {
+            check_can_replace(&entry.target.live, &replacement_metadata(&backup, &entry.target.live, &Ownership::default())?)?;
+        }
+    }
+    Ok(())
+}
+
+// Put back every file which has been touched, newest first
+fn roll_back(journal: &mut Journal, lock: &LockOptions) -> Result<(), BBError> {
+    for idx in (0..journal.entries.len()).rev() {
+        if journal.entries[idx].state == EntryState::Pending {
+            continue;
+        }
+        let target = journal.entries[idx].target.clone();
+        let _lock = lock_stack(&target.stack, lock)?;
+        copy_atomically(&journal.saved_stack(idx), &target.stack)?;
+        let saved_live = journal.saved_live(idx);
+        if saved_live.exists() {
+            copy_atomically(&saved_live, &target.live)?;
+        } else if target.live.exists() {
+            fs::remove_file(&target.live)
+                .map_err(|e| BBError::IoError(format!("unable to remove {}: {}", target.live.display(), e)))?;
+        }
+        journal.set_state(idx, EntryState::Pending)?;
+        info!("put back {}", target.live.display());
+    }
+    Ok(())
+}
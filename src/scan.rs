@@ -0,0 +1,75 @@
+//! scan
+//!
+//! Locates swinstalled files beneath a root directory by looking for the stack
+//! files which record their history.
+use errors::BBError;
+use layout::{BakLayout, SwinstallLayout};
+use std::fs;
+use std::path::Path;
+
+pub use layout::SwinstalledFile;
+
+/// Recursively find every swinstall stack beneath `root`, returning them sorted
+/// by live path. Symlinked directories are not followed, so that a tree which
+/// links back into itself is only visited once.
+pub fn find_swinstalled_files<P: AsRef<Path>>(root: P) -> Result<Vec<SwinstalledFile>, BBError> {
+    find_swinstalled_files_with_layout(root, &SwinstallLayout::default())
+}
+
+/// Recursively find the stack file of every swinstalled file beneath `root`,
+/// as laid out by `layout`.
+pub fn find_swinstalled_files_with_layout<P: AsRef<Path>>(
+    root: P,
+    layout: &dyn BakLayout,
+) -> Result<Vec<SwinstalledFile>, BBError> {
+    let search_root = layout.search_root(root.as_ref())?;
+    let mut found = Vec::new();
+    if search_root.is_dir() {
+        walk(&search_root, layout, &mut found)?;
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Given either a swinstalled file or a root directory, return the swinstalled
+/// files it refers to. A file is returned as is, whether or not it has a stack.
+pub fn swinstalled_files_for<P: AsRef<Path>>(file_or_root: P) -> Result<Vec<SwinstalledFile>, BBError> {
+    swinstalled_files_for_with_layout(file_or_root, &SwinstallLayout::default())
+}
+
+/// As `swinstalled_files_for`, using the supplied layout
+pub fn swinstalled_files_for_with_layout<P: AsRef<Path>>(
+    file_or_root: P,
+    layout: &dyn BakLayout,
+) -> Result<Vec<SwinstalledFile>, BBError> {
+    let file_or_root = file_or_root.as_ref();
+    if file_or_root.is_dir() {
+        find_swinstalled_files_with_layout(file_or_root, layout)
+    } else {
+        Ok(vec![SwinstalledFile::new(file_or_root, layout)?])
+    }
+}
+
+fn walk(dir: &Path, layout: &dyn BakLayout, found: &mut Vec<SwinstalledFile>) -> Result<(), BBError> {
+    for entry in read_dir(dir)? {
+        let file_type = entry.file_type()
+                             .map_err(|e| BBError::IoError(format!("unable to stat {}: {}", entry.path().display(), e)))?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            walk(&path, layout, found)?;
+        } else if file_type.is_file() && layout.is_stack_path(&path) {
+            match layout.live_path(&path) {
+                Ok(live) => found.push(SwinstalledFile { live, stack: path }),
+                Err(e) => warn!("skipping {}: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<fs::DirEntry>, BBError> {
+    let entries = fs::read_dir(dir)
+                  .map_err(|e| BBError::IoError(format!("unable to read {}: {}", dir.display(), e)))?;
+    entries.collect::<Result<Vec<_>, _>>()
+           .map_err(|e| BBError::IoError(format!("unable to read {}: {}", dir.display(), e)))
+}
@@ -0,0 +1,232 @@
+//! script
+//!
+//! Shell scripts performing the changes a mutating command would make, so that
+//! they may be reviewed before they are run: install, restore, undo and
+//! pruning the operation log. Scripts are POSIX sh, and check
+//! before changing anything that every file they read or replace still has the
+//! SHA-256 it had when the script was generated, or is still absent.
+//!
+//! A script does not take bakbuster's stack locks, is not journaled and is not
+//! recorded in the operation log; it stops at the first failed command.
+use errors::BBError;
+use metadata::FileMetadata;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Compute a file's SHA-256 with whichever tool the host has
+static SHA256_FUNCTION: &str = r#"bb_sha256() {
+    if command -v sha256sum >/dev/null 2>&1; then
+        sha256sum < "$1" | cut -d ' ' -f 1
+    elif command -v shasum >/dev/null 2>&1; then
+        shasum -a 256 < "$1" | cut -d ' ' -f 1
+    else
+        openssl dgst -sha256 < "$1" | sed 's/^.* //'
+    fi
+}
+
+bb_fail() {
+    echo "bakbuster: $1; not applying this script" >&2
+    exit 1
+}
+
+bb_unchanged() {
+    [ -f "$1" ] || bb_fail "$1 no longer exists"
+    [ "$(bb_sha256 "$1")" = "$2" ] || bb_fail "$1 has changed since this script was generated"
+}
+
+bb_absent() {
+    [ ! -e "$1" ] || bb_fail "$1 has been created since this script was generated"
+}
+"#;
+
+/// A shell script under construction
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Script {
+    description: Vec<String>,
+    guards: Vec<Vec<u8>>,
+    steps: Vec<Vec<u8>>,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script::default()
+    }
+
+    /// Add a line to the comment heading the script
+    pub fn describe<S: Into<String>>(&mut self, line: S) {
+        self.description.push(line.into());
+    }
+
+    /// Require `path` to be as it is now: the same contents if it exists, or
+    /// still absent if it does not
+    pub fn guard(&mut self, path: &Path) -> Result<(), BBError> {
+        let guard = if path.exists() {
+            let mut guard = b"bb_unchanged ".to_vec();
+            guard.extend(quote(path));
+            guard.extend(format!(" {}", sha256_file(path)?).into_bytes());
+            guard
+        } else {
+            let mut guard = b"bb_absent ".to_vec();
+            guard.extend(quote(path));
+            guard
+        };
+        if !self.guards.contains(&guard) {
+            self.guards.push(guard);
+        }
+        Ok(())
+    }
+
+    /// Create `dir` and its parents
+    pub fn mkdir(&mut self, dir: &Path) {
+        self.step(&[b"mkdir -p -- ", &quote(dir)]);
+    }
+
+    /// Copy `source` to `dest` by way of a temporary file beside it, giving the
+    /// copy `metadata`. Ownership is only set when `chown` is true.
+    pub fn copy(&mut self, source: &Path, dest: &Path, metadata: &FileMetadata, chown: bool) {
+        let temp = quote(&temp_beside(dest));
+        self.step(&[b"cp -- ", &quote(source), b" ", &temp]);
+        self.set_metadata(&temp, metadata, chown);
+        self.step(&[b"touch -r ", &quote(source), b" ", &temp]);
+        self.step(&[b"mv -f -- ", &temp, b" ", &quote(dest)]);
+    }
+
+    /// Remove the file `path`, if it exists
+    pub fn remove(&mut self, path: &Path) {
+        self.step(&[b"rm -f -- ", &quote(path)]);
+    }
+
+    /// Remove the directory `dir` and everything in it
+    pub fn remove_dir(&mut self, dir: &Path) {
+        self.step(&[b"rm -rf -- ", &quote(dir)]);
+    }
+
+    /// Write `contents`, which must be text ending in a newline, to `dest` by
+    /// way of a temporary file beside it, keeping the mode and ownership in
+    /// `metadata` if given
+    pub fn write(&mut self, dest: &Path, contents: &str, metadata: Option<&FileMetadata>) {
+        let temp = quote(&temp_beside(dest));
+        let mut delimiter = "BAKBUSTER_EOF".to_string();
+        while contents.lines().any(|line| line == delimiter) {
+            delimiter.push('_');
+        }
+        let heredoc = format!(" <<'{}'\n{}{}", delimiter, contents, delimiter);
+        self.step(&[b"cat > ", &temp, heredoc.as_bytes()]);
+        if let Some(metadata) = metadata {
+            self.set_metadata(&temp, metadata, true);
+        }
+        self.step(&[b"mv -f -- ", &temp, b" ", &quote(dest)]);
+    }
+
+    /// The script, ready to be written out
+    pub fn render(&self) -> Vec<u8> {
+        let mut script = b"#!/bin/sh\n".to_vec();
+        script.extend(format!("# Generated by bakbuster {}\n", env!("CARGO_PKG_VERSION")).into_bytes());
+        for line in &self.description {
+            script.extend(format!("# {}\n", line).into_bytes());
+        }
+        script.extend(b"set -eu\n\n");
+        script.extend(SHA256_FUNCTION.as_bytes());
+        script.extend(b"\n# Abort if anything has changed since the script was generated\n");
+        for guard in &self.guards {
+            script.extend(guard);
+            script.push(b'\n');
+        }
+        script.extend(b"\n# Apply the changes\n");
+        for step in &self.steps {
+            script.extend(step);
+            script.push(b'\n');
+        }
+        script
+    }
+
+    fn set_metadata(&mut self, temp: &[u8], metadata: &FileMetadata, chown: bool) {
+        if chown {
+            let owner = format!("chown {}:{} ", metadata.uid, metadata.gid);
+            self.step(&[owner.as_bytes(), temp]);
+        }
+        let mode = format!("chmod {:04o} ", metadata.mode);
+        self.step(&[mode.as_bytes(), temp]);
+    }
+
+    fn step(&mut self, parts: &[&[u8]]) {
+        self.steps.push(parts.concat());
+    }
+}
+
+/// The SHA-256 of a file's contents, in hex
+pub fn sha256_file(path: &Path) -> Result<String, BBError> {
+    let read_error = |e: io::Error| BBError::IoError(format!("unable to read {}: {}", path.display(), e));
+    let mut file = File::open(path).map_err(read_error)?;
+    let mut hasher = Sha256::default();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let count = file.read(&mut buffer).map_err(read_error)?;
+        if count == 0 {
+            break;
+        }
+        hasher.input(&buffer[..count]);
+    }
+    Ok(hasher.result().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Quote a path for the shell. Single quotes preserve every byte but the single
+/// quote itself, which is written as '\''.
+pub fn quote(path: &Path) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &byte in os_bytes(path.as_os_str()).iter() {
+        if byte == b'\'' {
+            quoted.extend(b"'\\''");
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+#[cfg(unix)]
+fn os_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(s.as_bytes())
+}
+
+// elsewhere, names which are not valid unicode are converted lossily
+#[cfg(not(unix))]
+fn os_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    match s.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+// The hidden file a script writes before renaming it into place
+fn temp_beside(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".bakbuster-script");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_paths() {
+        assert_eq!(quote(Path::new("/etc/foo bar")), b"'/etc/foo bar'".to_vec());
+        assert_eq!(quote(Path::new("it's")), b"'it'\\''s'".to_vec());
+    }
+
+    #[test]
+    fn chooses_an_unused_delimiter() {
+        let mut script = Script::new();
+        script.write(Path::new("/tmp/stack"), "BAKBUSTER_EOF\n", None);
+        let rendered = String::from_utf8(script.render()).unwrap();
+        assert!(rendered.contains("<<'BAKBUSTER_EOF_'\nBAKBUSTER_EOF\nBAKBUSTER_EOF_\n"));
+    }
+}
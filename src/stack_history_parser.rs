@@ -2,19 +2,136 @@
 //!
 //! Exports a public method, stack_history_from_path, which retrieves the path to
 //! the swinstall stack file for the supplied swinstalled file. Or an error of course.
-use chrono::prelude::*;
 use chrono::naive::NaiveDateTime;
 use errors::BBError;
 use fileversionparser::FileVersion;
-use std::io::Read;
-use std::path::PathBuf;
+use layout::{BakLayout, SwinstallLayout};
+use paths::{normalize_path, resolve_path, ParentResolution, ResolvedPath};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 use xml::{
     reader::EventReader,
     reader::XmlEvent,
-    attribute::OwnedAttribute
 };
 
+/// Controls which stack entries are candidates when resolving the version of a
+/// file at a datetime.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ResolvePolicy {
+    /// Only entries up to and including the current one. Entries after current
+    /// have been rolled back. This is what swinstall itself considers installed.
+    #[default]
+    Current,
+    /// Every entry in the stack, including those which have been rolled back
+    Latest,
+}
+
+impl FromStr for ResolvePolicy {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "current" => Ok(ResolvePolicy::Current),
+            "latest" => Ok(ResolvePolicy::Latest),
+            _ => Err(BBError::ParseError(format!("Unable to parse policy: '{}' (expected current or latest)", s))),
+        }
+    }
+}
+
+/// A single `elt` tag from a swinstall stack file.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StackEntry {
+    pub version: FileVersion,
+    pub is_current: bool,
+}
+
+/// The full contents of a swinstall stack file. Entries are kept in file
+/// order, which is install order (oldest first).
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StackHistory {
+    /// The `path` attribute of the `stack_history` tag, if present
+    pub path: Option<String>,
+    pub entries: Vec<StackEntry>,
+}
+
+impl StackHistory {
+    /// Append `version` as the current entry, clearing the current marker from
+    /// every other entry
+    pub fn push_current(&mut self, version: FileVersion) {
+        for entry in &mut self.entries {
+            entry.is_current = false;
+        }
+        self.entries.push(StackEntry { version, is_current: true });
+    }
+
+    /// Mark the entry holding `version` as current, as a rollback does, and
+    /// clear the marker from every other entry. Returns false, changing nothing,
+    /// if no entry holds `version`.
+    pub fn set_current(&mut self, version: &FileVersion) -> bool {
+        if !self.entries.iter().any(|e| e.version == *version) {
+            return false;
+        }
+        for entry in &mut self.entries {
+            entry.is_current = entry.version == *version;
+        }
+        true
+    }
+
+    /// Index of the entry marked as current, if any
+    pub fn current_index(&self) -> Option<usize> {
+        self.entries.iter().position(|e| e.is_current)
+    }
+
+    /// The entry marked as current, if any
+    pub fn current(&self) -> Option<&StackEntry> {
+        self.current_index().map(|idx| &self.entries[idx])
+    }
+
+    /// The entries up to and including the current one. Entries after current
+    /// have been rolled back and are not considered installed. A stack without
+    /// a current entry has nothing installed.
+    pub fn installed(&self) -> &[StackEntry] {
+        match self.current_index() {
+            Some(idx) => &self.entries[..=idx],
+            None => &[],
+        }
+    }
+
+    /// The latest installed entry at or before the supplied datetime. This
+    /// mirrors `get_file_version_on`.
+    pub fn entry_on(&self, datetime: &NaiveDateTime) -> Option<&StackEntry> {
+        self.resolve(datetime, ResolvePolicy::Current)
+    }
+
+    /// The latest entry at or before the supplied datetime, among the entries
+    /// the policy considers.
+    pub fn resolve(&self, datetime: &NaiveDateTime, policy: ResolvePolicy) -> Option<&StackEntry> {
+        let candidates = match policy {
+            ResolvePolicy::Current => self.installed(),
+            ResolvePolicy::Latest => &self.entries[..],
+        };
+        candidates.iter().rev().find(|e| e.version.date_time <= *datetime)
+    }
+
+    /// The most recent entry with the supplied revision, among the entries the
+    /// policy considers.
+    pub fn with_revision(&self, revision: &str, policy: ResolvePolicy) -> Option<&StackEntry> {
+        let candidates = match policy {
+            ResolvePolicy::Current => self.installed(),
+            ResolvePolicy::Latest => &self.entries[..],
+        };
+        candidates.iter().rev().find(|e| e.version.revision.as_deref() == Some(revision))
+    }
+}
+
 /// Given a path to an swinstalled file, return the path to its swinstall_stack file
 /// within the bak directory.
 ///
@@ -34,23 +151,191 @@ use xml::{
 ///
 /// as a PathBuf wrapped in a result.
 pub fn stack_history_from_path<F: Into<PathBuf>>(file: F) -> Result<PathBuf, BBError> {
-    let mut pb = file.into();
-    let filename = pb.file_name().ok_or(BBError::ConversionError(format!("Unable to get filename from '{:?}'", pb)))?
-                   .to_str().ok_or(BBError::ConversionError("failed to convert filename  to str".to_string()))?
-                   .to_string();
-    // remove filename
-    pb.pop();
-
-    // build up path to swinstall stack file
-    // ie given
-    //   ./foo.yaml
-    // return
-    //   ./bak/foo.yaml/foo.yaml_swinstall_stack
-    pb.push("bak");
-    pb.push(filename.as_str());
-    let swinstall_stack = format!("{}_swinstall_stack", filename.as_str());
-    pb.push(swinstall_stack);
-    Ok(pb)
+    SwinstallLayout::default().stack_path(&file.into())
+}
+
+/// As `stack_history_from_path`, after expanding environment variables and `~`
+/// in `file` and resolving it to an absolute path free of symlinks. An unset
+/// variable is an `EnvVarError`.
+///
+/// # Example
+///
+/// Given:
+///
+/// ```text,ignore
+/// $FACILITY_ROOT/etc/packages.xml
+/// ```
+///
+/// Return:
+///
+/// ```text,ignore
+/// /dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack
+/// ```
+pub fn canonical_stack_history_from_path<F: AsRef<Path>>(file: F) -> Result<PathBuf, BBError> {
+    stack_history_from_path(normalize_path(file)?)
+}
+
+/// As `canonical_stack_history_from_path`, choosing whether the bak directory is
+/// sought beside the physical or the logical parent of `file`. The result lists
+/// the symlinks followed to reach it.
+pub fn resolve_stack_history_from_path<F: AsRef<Path>>(
+    file: F,
+    mode: ParentResolution,
+) -> Result<ResolvedPath, BBError> {
+    let resolved = resolve_path(file, mode)?;
+    Ok(ResolvedPath { path: stack_history_from_path(resolved.path)?, followed: resolved.followed })
+}
+
+/// Given the path to a swinstall stack file or a backup within the bak directory,
+/// return the path to the swinstalled file it belongs to. This is the inverse of
+/// `stack_history_from_path`.
+///
+/// # Example
+///
+/// Given either of:
+///
+/// ```text,ignore
+/// ./bak/foo_preference.yaml/foo_preference.yaml_swinstall_stack
+/// ./bak/foo_preference.yaml/foo_preference.yaml.20181105-103813
+/// ```
+///
+/// Return:
+///
+/// ```text,ignore
+/// ./foo_preference.yaml
+/// ```
+pub fn live_path_for<F: Into<PathBuf>>(bak_path: F) -> Result<PathBuf, BBError> {
+    SwinstallLayout::default().live_path(&bak_path.into())
+}
+
+/// Given a path to an swinstalled file and a FileVersion, return the path to the
+/// backup of that version within the bak directory.
+///
+/// # Example
+///
+/// Given:
+///
+///  ```text,ignore
+/// ./foo_preference.yaml, 20181105-103813
+/// ```
+///
+/// Return:
+///
+/// ```text,ignore
+/// ./bak/foo_preference.yaml/foo_preference.yaml.20181105-103813
+/// ```
+pub fn backup_path_for<F: Into<PathBuf>>(file: F, version: &FileVersion) -> Result<PathBuf, BBError> {
+    SwinstallLayout::default().backup_path(&file.into(), version)
+}
+
+/// Given an input which implements the Read trait, parse the entire swinstall
+/// stack into a StackHistory.
+pub fn parse_stack_history<R: Read>(input: R) -> Result<StackHistory, BBError> {
+    let parser = EventReader::new(input);
+    let mut history = StackHistory { path: None, entries: Vec::new() };
+
+    for xml_event in parser {
+        match xml_event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                match name.local_name.as_str() {
+                    "stack_history" => {
+                        history.path = attributes.into_iter()
+                                                 .find(|a| a.name.local_name == "path")
+                                                 .map(|a| a.value);
+                    },
+                    "elt" => {
+                        let mut is_current = false;
+                        let mut version = None;
+                        for attr in attributes {
+                            let namestr = attr.name.local_name.as_str();
+                            match namestr {
+                                "is_current" => is_current = match_current_str(attr.value.as_str())?,
+                                "version" => version = Some(FileVersion::from_str(attr.value.as_str())?),
+                                _ => {
+                                    return Err(BBError::ParseError(format!("attribute {} not valid", namestr)))
+                                }
+                            }
+                        }
+                        let version = version.ok_or(BBError::ParseError("elt missing version attribute".to_string()))?;
+                        history.entries.push(StackEntry { version, is_current });
+                    },
+                    _ => {}
+                }
+            },
+            Err(e) => {
+                return Err(BBError::ParseError(format!("problem: {}",e)));
+            },
+            _ => {}
+        }
+    }
+    Ok(history)
+}
+
+/// Open and parse the swinstall stack file at the supplied path. A stack which
+/// is truncated, as when read while a writer which does not replace it
+/// atomically is part way through, is read again after a short wait.
+pub fn read_stack_history<P: AsRef<Path>>(stack_file: P) -> Result<StackHistory, BBError> {
+    let stack_file = stack_file.as_ref();
+    if !stack_file.exists() {
+        return Err(BBError::NonExtantPath(stack_file.display().to_string()));
+    }
+    let mut attempt = 1;
+    loop {
+        let mut contents = Vec::new();
+        File::open(stack_file)
+            .and_then(|f| BufReader::new(f).read_to_end(&mut contents))
+            .map_err(|e| BBError::IoError(format!("unable to read {}: {}", stack_file.display(), e)))?;
+        match parse_stack_history(contents.as_slice()) {
+            Err(ref e) if attempt < READ_ATTEMPTS && is_truncated(&contents) => {
+                debug!("{} appears truncated ({}), reading it again", stack_file.display(), e);
+                thread::sleep(Duration::from_millis(READ_RETRY_MILLIS * attempt));
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+// how many times a truncated stack is read before giving up, and the wait
+// between reads, which grows with each attempt
+const READ_ATTEMPTS: u64 = 5;
+const READ_RETRY_MILLIS: u64 = 20;
+
+// A complete stack ends with the closing stack_history tag
+fn is_truncated(contents: &[u8]) -> bool {
+    let end = b"</stack_history>";
+    let trimmed = contents.iter().rposition(|b| !b.is_ascii_whitespace()).map(|i| &contents[..=i]).unwrap_or(&[]);
+    !trimmed.ends_with(end)
+}
+
+/// Write a StackHistory in the swinstall stack format, which
+/// `parse_stack_history` and swinstall itself both read:
+///
+/// ```text,ignore
+/// <stack_history path="/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack">
+///     <elt is_current="True" version="20181105-103813" />
+/// </stack_history>
+/// ```
+pub fn write_stack_history<W: Write>(history: &StackHistory, mut output: W) -> Result<(), BBError> {
+    let mut xml = match history.path {
+        Some(ref path) => format!("<stack_history path=\"{}\">\n", escape_attribute(path)),
+        None => "<stack_history>\n".to_string(),
+    };
+    for entry in &history.entries {
+        xml.push_str(&format!("    <elt is_current=\"{}\" version=\"{}\" />\n",
+            if entry.is_current { "True" } else { "False" }, escape_attribute(&entry.version.to_string())));
+    }
+    xml.push_str("</stack_history>\n");
+    output.write_all(xml.as_bytes())
+          .map_err(|e| BBError::IoError(format!("unable to write stack history: {}", e)))
+}
+
+// Escape a value for use within a double quoted xml attribute
+fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;")
+         .replace('<', "&lt;")
+         .replace('>', "&gt;")
+         .replace('"', "&quot;")
 }
 
 /// Given an input which implements the Read trait, and a datetime, find the
@@ -77,36 +362,33 @@ pub fn get_file_version_on<R: Read>(input: R, datetime: NaiveDateTime) -> Result
 
     for xml_event in parser {
         match xml_event {
-            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
-                if name.local_name.as_str() == "elt" {
-
-                    let mut matched_version = false;
-                    for attr in attributes {
-                        let namestr = attr.name.local_name.as_str();
-                        match namestr {
-                            "is_current" => {
-                                current = match_current_str(attr.value.as_str())?;
-                            },
-                            "version" => {
-                                matched_version = true;
-                                update_file_version_if_in_range(attr.value.as_str(),&mut file_version, &datetime)?;
-                            },
-                            _ => {
-                                return Err(BBError::ParseError(format!("attribute {} not valid", namestr)))
-                            }
-                        }
-                        // we need to wait until we have matched the version attribute as well as
-                        // evaluated whether we are current. Otherwise, we will return early
-                        if current && matched_version {
-                            // if the elt tag has attribute is_current=true and we have
-                            // a matched version in terms of time, we return early. This covers
-                            // the case where someone has rolled back an install, and there are
-                            // additional elt tags after the current one which would potentially match the
-                            // datetime constraint.
-                            return file_version
-                                   .ok_or(BBError::ParseError("No fileversion found".to_string()));
+            Ok(XmlEvent::StartElement { name, attributes, .. }) if name.local_name.as_str() == "elt" => {
+                let mut matched_version = false;
+                for attr in attributes {
+                    let namestr = attr.name.local_name.as_str();
+                    match namestr {
+                        "is_current" => {
+                            current = match_current_str(attr.value.as_str())?;
+                        },
+                        "version" => {
+                            matched_version = true;
+                            update_file_version_if_in_range(attr.value.as_str(),&mut file_version, &datetime)?;
+                        },
+                        _ => {
+                            return Err(BBError::ParseError(format!("attribute {} not valid", namestr)))
                         }
                     }
+                    // we need to wait until we have matched the version attribute as well as
+                    // evaluated whether we are current. Otherwise, we will return early
+                    if current && matched_version {
+                        // if the elt tag has attribute is_current=true and we have
+                        // a matched version in terms of time, we return early. This covers
+                        // the case where someone has rolled back an install, and there are
+                        // additional elt tags after the current one which would potentially match the
+                        // datetime constraint.
+                        return file_version
+                               .ok_or(BBError::ParseError("No fileversion found".to_string()));
+                    }
                 }
             },
 
@@ -161,6 +443,29 @@ fn update_file_version_if_in_range(
 #[cfg(test)]
 mod test {
     use super::*;
+    use chrono::prelude::*;
+
+    #[test]
+    fn write_round_trips() {
+        let xml =
+r#"<stack_history path="/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack">
+    <elt is_current="False" version="20161213-093146_r575055" />
+    <elt is_current="True" version="20181105-103813" />
+</stack_history>
+"#;
+        let mut history = parse_stack_history(xml.as_bytes()).unwrap();
+        let mut written = Vec::new();
+        write_stack_history(&history, &mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), xml);
+
+        history.push_current(FileVersion::from_str("20181106-104603").unwrap());
+        history.path = Some("/a & <b>".to_string());
+        let mut written = Vec::new();
+        write_stack_history(&history, &mut written).unwrap();
+        let reread = parse_stack_history(written.as_slice()).unwrap();
+        assert_eq!(reread, history);
+        assert_eq!(reread.current_index(), Some(2));
+    }
 
     #[test]
     fn parse() {
@@ -192,4 +497,26 @@ r#"<stack_history path="/dd/facility/etc/bak/packages.xml/packages.xml_swinstall
         let expected = FileVersion::from_str("20181102-144204");
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn parse_history() {
+        let xml =
+r#"<stack_history path="/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack">
+    <elt is_current="False" version="20161213-093146_r575055" />
+    <elt is_current="True" version="20181105-103813" />
+    <elt is_current="False" version="20181106-104603" />
+</stack_history>"#;
+
+        let history = parse_stack_history(xml.as_bytes()).unwrap();
+        assert_eq!(history.path, Some("/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack".to_string()));
+        assert_eq!(history.entries.len(), 3);
+        assert_eq!(history.installed().len(), 2);
+        let entry = history.entry_on(&NaiveDate::from_ymd(2018,11,7).and_hms(0,0,0)).unwrap();
+        assert_eq!(entry.version, FileVersion::from_str("20181105-103813").unwrap());
+        let entry = history.resolve(&NaiveDate::from_ymd(2018,11,7).and_hms(0,0,0), ResolvePolicy::Latest).unwrap();
+        assert_eq!(entry.version, FileVersion::from_str("20181106-104603").unwrap());
+        let entry = history.with_revision("575055", ResolvePolicy::Current).unwrap();
+        assert_eq!(entry.version, FileVersion::from_str("20161213-093146_r575055").unwrap());
+        assert_eq!(history.entry_on(&NaiveDate::from_ymd(2016,1,1).and_hms(0,0,0)), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,258 @@
+//! timezone
+//!
+//! Stack timestamps are recorded in the facility's local time, without an
+//! offset. This module converts between them and real instants, so that users
+//! in other timezones can query the history, and reports the local times which
+//! daylight saving makes ambiguous or impossible.
+use chrono::{DateTime, FixedOffset, Local, LocalResult, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use constants::STDTIMEFMT;
+use errors::BBError;
+use std::fmt;
+use std::str::FromStr;
+use utils::parse_datetime;
+
+/// A timezone in which naive timestamps are interpreted
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Zone {
+    /// The timezone of the host running bakbuster
+    #[default]
+    Local,
+    /// A zone from the tz database, eg `America/Los_Angeles`
+    Named(Tz),
+    /// A fixed offset from UTC, eg `+05:30` or `UTC`
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Zone {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "local" {
+            return Ok(Zone::Local);
+        }
+        if let Some(offset) = parse_offset(s) {
+            return Ok(Zone::Fixed(offset));
+        }
+        Tz::from_str(s)
+            .map(Zone::Named)
+            .map_err(|_| BBError::ParseError(
+                format!("Unable to parse timezone: '{}' (expected local, a tz database name or an offset like +05:30)", s)))
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Zone::Local => write!(f, "local"),
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+            Zone::Fixed(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+/// The instants a local time may name
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LocalTime {
+    /// The local time names exactly one instant
+    Single(DateTime<FixedOffset>),
+    /// The local time occurs twice, as when clocks go back in November
+    Ambiguous(DateTime<FixedOffset>, DateTime<FixedOffset>),
+    /// The local time never occurs, as when clocks go forward in March
+    Nonexistent,
+}
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LocalTime::Single(ref dt) => write!(f, "{}", dt.to_rfc3339()),
+            LocalTime::Ambiguous(ref earliest, ref latest) => {
+                write!(f, "ambiguous: either {} or {}", earliest.to_rfc3339(), latest.to_rfc3339())
+            },
+            LocalTime::Nonexistent => write!(f, "nonexistent: skipped by a daylight saving transition"),
+        }
+    }
+}
+
+impl Zone {
+    /// The instants which `naive`, a local time in this zone, may name
+    pub fn localize(&self, naive: &NaiveDateTime) -> LocalTime {
+        match *self {
+            Zone::Local => classify(Local.from_local_datetime(naive)),
+            Zone::Named(tz) => classify(tz.from_local_datetime(naive)),
+            Zone::Fixed(offset) => classify(offset.from_local_datetime(naive)),
+        }
+    }
+
+    /// The local time in this zone at `instant`
+    pub fn naive_local(&self, instant: &DateTime<Utc>) -> NaiveDateTime {
+        match *self {
+            Zone::Local => instant.with_timezone(&Local).naive_local(),
+            Zone::Named(tz) => instant.with_timezone(&tz).naive_local(),
+            Zone::Fixed(offset) => instant.with_timezone(&offset).naive_local(),
+        }
+    }
+
+    /// The current local time in this zone
+    pub fn now(&self) -> NaiveDateTime {
+        self.naive_local(&Utc::now())
+    }
+
+    /// The instant `naive`, a local time in this zone, names. Ambiguous times
+    /// are resolved by `policy`; nonexistent times are an error.
+    pub fn to_utc(&self, naive: &NaiveDateTime, policy: AmbiguityPolicy) -> Result<DateTime<Utc>, BBError> {
+        match self.localize(naive) {
+            LocalTime::Single(dt) => Ok(dt.with_timezone(&Utc)),
+            LocalTime::Ambiguous(earliest, latest) => match policy {
+                AmbiguityPolicy::Earliest => Ok(earliest.with_timezone(&Utc)),
+                AmbiguityPolicy::Latest => Ok(latest.with_timezone(&Utc)),
+                AmbiguityPolicy::Reject => Err(BBError::ParseError(format!(
+                    "{} is ambiguous in {}: it may be {} or {}. Give an offset, or choose with --ambiguous earliest|latest",
+                    naive.format(STDTIMEFMT), self, earliest.to_rfc3339(), latest.to_rfc3339()))),
+            },
+            LocalTime::Nonexistent => Err(BBError::ParseError(format!(
+                "{} does not exist in {}, as it falls in a daylight saving transition", naive.format(STDTIMEFMT), self))),
+        }
+    }
+}
+
+/// How a local time which occurs twice is resolved
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AmbiguityPolicy {
+    /// Refuse to guess
+    #[default]
+    Reject,
+    /// Take the first occurrence, before clocks go back
+    Earliest,
+    /// Take the second occurrence, after clocks go back
+    Latest,
+}
+
+impl FromStr for AmbiguityPolicy {
+    type Err = BBError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(AmbiguityPolicy::Reject),
+            "earliest" => Ok(AmbiguityPolicy::Earliest),
+            "latest" => Ok(AmbiguityPolicy::Latest),
+            _ => Err(BBError::ParseError(format!("Unable to parse ambiguity policy: '{}' (expected reject, earliest or latest)", s))),
+        }
+    }
+}
+
+/// A datetime supplied by the user, resolved to an instant and to the facility's
+/// local time, which is what stack timestamps are compared against.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct QueryTime {
+    /// The datetime as given
+    pub input: String,
+    /// The instant it names
+    pub instant: DateTime<Utc>,
+    /// The facility's local time at that instant
+    pub facility_local: NaiveDateTime,
+    /// How the facility's local time maps back to instants. Ambiguous if the
+    /// query falls in the facility's repeated hour, when stack timestamps near
+    /// it cannot be ordered reliably.
+    pub facility_time: LocalTime,
+}
+
+/// Resolve a datetime supplied by the user. Datetimes with an offset, eg
+/// `2018-11-05T10:38:13-08:00` or `2018-11-05 10:38:13 +0000`, name an instant;
+/// otherwise any format accepted by `parse_datetime` is interpreted in
+/// `input_zone`, resolving ambiguity with `policy`.
+pub fn parse_query_time(
+    input: &str,
+    input_zone: Zone,
+    facility: Zone,
+    policy: AmbiguityPolicy,
+) -> Result<QueryTime, BBError> {
+    let instant = match parse_with_offset(input) {
+        Some(dt) => dt.with_timezone(&Utc),
+        None => input_zone.to_utc(&parse_datetime(input)?, policy)?,
+    };
+    Ok(query_time_at(input, instant, facility))
+}
+
+/// The QueryTime for an instant, such as now
+pub fn query_time_at(input: &str, instant: DateTime<Utc>, facility: Zone) -> QueryTime {
+    let facility_local = facility.naive_local(&instant);
+    QueryTime {
+        input: input.to_string(),
+        instant,
+        facility_local,
+        facility_time: facility.localize(&facility_local),
+    }
+}
+
+fn parse_with_offset(input: &str) -> Option<DateTime<FixedOffset>> {
+    let input = input.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        return Some(dt);
+    }
+    for fmt in &["%Y-%m-%d %H:%M:%S%z", "%Y-%m-%d %H:%M:%S %z", "%Y%m%d-%H%M%S%z"] {
+        if let Ok(dt) = DateTime::parse_from_str(input, fmt) {
+            return Some(dt);
+        }
+    }
+    // a trailing zone name or offset, eg "2018-11-05 10:38:13 UTC"
+    let idx = input.rfind(' ')?;
+    let offset = parse_offset(&input[idx + 1..])?;
+    let naive = parse_datetime(&input[..idx]).ok()?;
+    offset.from_local_datetime(&naive).single()
+}
+
+// Parse "UTC", "Z", "+05", "+0530" or "+05:30"
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    if s == "UTC" || s == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = s[1..].replace(':', "");
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse::<i32>().ok()?, digits[2..].parse::<i32>().ok()?),
+        _ => return None,
+    };
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+fn classify<T: TimeZone>(result: LocalResult<DateTime<T>>) -> LocalTime {
+    let fix = |dt: DateTime<T>| dt.with_timezone(&dt.offset().fix());
+    match result {
+        LocalResult::Single(dt) => LocalTime::Single(fix(dt)),
+        LocalResult::Ambiguous(earliest, latest) => LocalTime::Ambiguous(fix(earliest), fix(latest)),
+        LocalResult::None => LocalTime::Nonexistent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_zones() {
+        assert_eq!(Zone::from_str("local"), Ok(Zone::Local));
+        assert_eq!(Zone::from_str("UTC"), Ok(Zone::Fixed(FixedOffset::east(0))));
+        assert_eq!(Zone::from_str("-08:00"), Ok(Zone::Fixed(FixedOffset::west(8 * 3600))));
+        assert_eq!(Zone::from_str("+0530"), Ok(Zone::Fixed(FixedOffset::east(5 * 3600 + 30 * 60))));
+        assert_eq!(Zone::from_str("America/Los_Angeles"), Ok(Zone::Named(Tz::America__Los_Angeles)));
+        assert!(Zone::from_str("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn parses_offsets_in_queries() {
+        let facility = Zone::Named(Tz::America__Los_Angeles);
+        let query = parse_query_time("2018-11-05 18:38:13 UTC", Zone::Local, facility, AmbiguityPolicy::Reject).unwrap();
+        assert_eq!(query.facility_local, parse_datetime("2018-11-05 10:38:13").unwrap());
+        let query = parse_query_time("2018-11-05T13:38:13-05:00", Zone::Local, facility, AmbiguityPolicy::Reject).unwrap();
+        assert_eq!(query.facility_local, parse_datetime("2018-11-05 10:38:13").unwrap());
+    }
+}
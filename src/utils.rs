@@ -1,3 +1,5 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use constants::{BAKTIMEFMT, STDTIMEFMT};
 use errors::BBError;
 use std::env;
 use std::path::PathBuf;
@@ -9,7 +11,9 @@ pub fn path_to_executable() -> Result<PathBuf, BBError> {
     Ok(this_file)
 }
 
-///Convert path to string. Takes a Path or PathBuf and taeks ownership before converting to string
+///Convert path to string. Takes a Path or PathBuf and taeks ownership before converting to string.
+/// Fails with a ConversionError if the path is not valid UTF-8, so prefer working
+/// with the path itself and converting with `display()` only for output.
 pub fn pathbuf_to_string<I: Into<PathBuf>>(path: I) -> Result<String, BBError> {
     let pathbuf = path.into();
     let pb = pathbuf.into_os_string().into_string().map_err(|os_str| BBError::ConversionError(format!("Unable to convert {:?} to string", os_str)) )?;
@@ -23,3 +27,40 @@ pub fn path_to_executable_string() -> Result<String, BBError> {
     Ok(pb)
 }
 
+
+/// Parse a datetime supplied by the user. Accepts the standard format
+/// (```2018-11-05 10:38:13```), the bak directory format (```20181105-103813```),
+/// or a bare date (```2018-11-05```), which is taken to mean the end of that day.
+pub fn parse_datetime(input: &str) -> Result<NaiveDateTime, BBError> {
+    let input = input.trim();
+    if let Ok(dt) = NaiveDateTime::parse_from_str(input, STDTIMEFMT) {
+        return Ok(dt);
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(input, BAKTIMEFMT) {
+        return Ok(dt);
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(d.and_hms(23, 59, 59));
+    }
+    Err(BBError::ParseError(format!("unable to parse datetime: '{}'", input)))
+}
+
+/// Retrieve the directory bakbuster uses to cache derived data, honoring
+/// ```XDG_CACHE_HOME``` and falling back to ```$HOME/.cache```.
+pub fn cache_dir() -> Result<PathBuf, BBError> {
+    if let Ok(xdg) = env::var("XDG_CACHE_HOME") {
+        return Ok(PathBuf::from(xdg).join("bakbuster"));
+    }
+    let home = env::var("HOME").map_err(|_| BBError::EnvVarError("HOME".to_string()))?;
+    Ok(PathBuf::from(home).join(".cache").join("bakbuster"))
+}
+
+/// Retrieve the directory bakbuster keeps state in, such as restore journals,
+/// honoring ```XDG_STATE_HOME``` and falling back to ```$HOME/.local/state```.
+pub fn state_dir() -> Result<PathBuf, BBError> {
+    if let Ok(xdg) = env::var("XDG_STATE_HOME") {
+        return Ok(PathBuf::from(xdg).join("bakbuster"));
+    }
+    let home = env::var("HOME").map_err(|_| BBError::EnvVarError("HOME".to_string()))?;
+    Ok(PathBuf::from(home).join(".local").join("state").join("bakbuster"))
+}
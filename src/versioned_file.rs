@@ -0,0 +1,100 @@
+//! versioned_file
+//!
+//! Provides read access to the historical content of a swinstalled file without
+//! shelling out to the bakbuster cli.
+use chrono::naive::NaiveDateTime;
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile, SwinstallLayout};
+use stack_history_parser::{read_stack_history, ResolvePolicy};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// An open backup of a swinstalled file, along with the version it holds.
+#[derive(Debug)]
+pub struct VersionedFile {
+    version: FileVersion,
+    backup_path: PathBuf,
+    file: File,
+}
+
+impl VersionedFile {
+    /// Open the backup of the swinstalled `file` at `version`.
+    pub fn open<P: AsRef<Path>>(file: P, version: FileVersion) -> Result<VersionedFile, BBError> {
+        VersionedFile::open_swinstalled(&SwinstalledFile::new(file.as_ref(), &SwinstallLayout::default())?, version)
+    }
+
+    /// Open the backup of the swinstalled file at `version`.
+    pub fn open_swinstalled(target: &SwinstalledFile, version: FileVersion) -> Result<VersionedFile, BBError> {
+        let backup_path = target.backup_path(&version)?;
+        let file = File::open(&backup_path)
+                   .map_err(|e| BBError::IoError(format!("unable to open {}: {}", backup_path.display(), e)))?;
+        Ok(VersionedFile { version, backup_path, file })
+    }
+
+    /// The version of the file being read
+    pub fn file_version(&self) -> &FileVersion {
+        &self.version
+    }
+
+    /// The path to the backup being read
+    pub fn backup_path(&self) -> &Path {
+        &self.backup_path
+    }
+}
+
+impl Read for VersionedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for VersionedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+/// Open the swinstalled file at `path` as it was installed at `datetime`,
+/// ignoring installs which have been rolled back.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let mut versioned = open_as_of("./foo_preference.yaml", datetime)?;
+/// let mut contents = String::new();
+/// versioned.read_to_string(&mut contents)?;
+/// ```
+pub fn open_as_of<P: AsRef<Path>>(path: P, datetime: NaiveDateTime) -> Result<VersionedFile, BBError> {
+    open_as_of_with_policy(path, datetime, ResolvePolicy::Current)
+}
+
+/// Open the swinstalled file at `path` as it was at `datetime`, considering the
+/// installs allowed by `policy`.
+pub fn open_as_of_with_policy<P: AsRef<Path>>(
+    path: P,
+    datetime: NaiveDateTime,
+    policy: ResolvePolicy,
+) -> Result<VersionedFile, BBError> {
+    open_as_of_with_layout(path, datetime, policy, &SwinstallLayout::default())
+}
+
+/// Open the swinstalled file at `path` as it was at `datetime`, considering the
+/// installs allowed by `policy` and locating its history using `layout`.
+pub fn open_as_of_with_layout<P: AsRef<Path>>(
+    path: P,
+    datetime: NaiveDateTime,
+    policy: ResolvePolicy,
+    layout: &dyn BakLayout,
+) -> Result<VersionedFile, BBError> {
+    let path = path.as_ref();
+    let target = SwinstalledFile::new(path, layout)?;
+    if !target.stack.exists() {
+        return Err(BBError::NoVersionFound(format!("{} has no swinstall stack at {}", path.display(), target.stack.display())));
+    }
+    let history = read_stack_history(&target.stack)?;
+    let entry = history.resolve(&datetime, policy)
+                       .ok_or(BBError::NoVersionFound(format!("{} had no installed version on or before {}", path.display(), datetime)))?;
+    VersionedFile::open_swinstalled(&target, entry.version.clone())
+}
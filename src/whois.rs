@@ -0,0 +1,86 @@
+//! whois
+//!
+//! Identifies the swinstalled file a backup or stack file belongs to, and where
+//! a backup sits within that file's history.
+use errors::BBError;
+use fileversionparser::FileVersion;
+use layout::{BakLayout, SwinstalledFile};
+use paths::{os_concat, os_strip_prefix};
+use stack_history_parser::read_stack_history;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Everything known about a backup or stack file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Whois {
+    /// The swinstalled file and its stack
+    pub target: SwinstalledFile,
+    /// The version held by the backup, or None when given the stack file itself
+    pub version: Option<FileVersion>,
+    /// Zero based index of the backup's entry in the stack, if it is recorded
+    pub position: Option<usize>,
+    /// Number of entries in the stack
+    pub entries: usize,
+    /// True if the backup's entry is marked current
+    pub is_current: bool,
+    /// True if the backup's entry lies after the current entry
+    pub rolled_back: bool,
+    /// The `path` attribute recorded in the stack, if any
+    pub recorded_path: Option<String>,
+    /// Whether the recorded path names this stack or live file. None if there
+    /// is no recorded path.
+    pub recorded_path_matches: Option<bool>,
+}
+
+/// Identify the swinstalled file which `bak_path`, a stack file or backup,
+/// belongs to, and cross check it against the path recorded in the stack.
+pub fn whois<P: AsRef<Path>>(bak_path: P, layout: &dyn BakLayout) -> Result<Whois, BBError> {
+    let bak_path = bak_path.as_ref();
+    let live = layout.live_path(bak_path)?;
+    let target = SwinstalledFile::new(live, layout)?;
+    let history = read_stack_history(&target.stack)?;
+
+    let version = if bak_path == target.stack {
+        None
+    } else {
+        let live_name = target.live.file_name().map(|n| os_concat(n, ".")).unwrap_or_default();
+        let version = bak_path.file_name()
+                              .and_then(|n| os_strip_prefix(n, &live_name))
+                              .unwrap_or_default();
+        Some(FileVersion::from_str(&version.to_string_lossy())?)
+    };
+
+    let current_index = history.current_index();
+    let position = version.as_ref().and_then(|v| history.entries.iter().position(|e| e.version == *v));
+    let recorded_path_matches = history.path.as_ref().map(|recorded| {
+        same_file(Path::new(recorded), &target.stack) || same_file(Path::new(recorded), &target.live)
+    });
+
+    Ok(Whois {
+        version,
+        position,
+        entries: history.entries.len(),
+        is_current: position.is_some() && position == current_index,
+        rolled_back: match (position, current_index) {
+            (Some(p), Some(c)) => p > c,
+            _ => false,
+        },
+        recorded_path: history.path,
+        recorded_path_matches,
+        target,
+    })
+}
+
+// Compare paths, resolving symlinks and relative components where the paths
+// exist. The recorded path often comes from another host, so an exact match
+// of the literal paths is also accepted.
+fn same_file(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
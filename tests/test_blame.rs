@@ -0,0 +1,29 @@
+extern crate bakbuster;
+extern crate chrono;
+use self::chrono::NaiveDateTime;
+use std::path::PathBuf;
+use bakbuster::BAKTIMEFMT;
+use bakbuster::blame::blame;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn packages_xml() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("packages.xml")
+}
+
+test! {
+    blame_attributes_lines_to_installs {
+        let dt = NaiveDateTime::parse_from_str("20181201-000000", BAKTIMEFMT).unwrap();
+        let blamed = blame(packages_xml(), &dt).unwrap();
+        let versions = blamed.iter().map(|b| b.version.to_string()).collect::<Vec<_>>();
+        assert_eq!(versions, vec![
+            "20161213-093146_r575055",
+            "20181105-103813",
+            "20181102-144204",
+            "20161213-093146_r575055",
+        ]);
+        assert_eq!(blamed[1].line.trim(), r#"<package name="foo" version="2.3" />"#);
+    }
+}
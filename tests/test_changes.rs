@@ -0,0 +1,89 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use bakbuster::{BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::changes::{changes, ChangeKind};
+use bakbuster::install::install;
+use bakbuster::lock::LockOptions;
+use bakbuster::restore::restore;
+use bakbuster::timezone::Zone;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_changes_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("root")).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+fn install_at(dir: &Path, name: &str, datetime: &str) {
+    let source = dir.join("source");
+    fs::write(&source, datetime).unwrap();
+    install(&source, dir.join("root").join(name), &at(datetime), None).unwrap();
+}
+
+// set a stack's modification time to a UTC datetime
+fn touch_stack(dir: &Path, name: &str, datetime: &str) {
+    let stack = dir.join("root/bak").join(name).join(format!("{}_swinstall_stack", name));
+    let time: SystemTime = Utc.from_utc_datetime(&at(datetime)).into();
+    fs::File::options().write(true).open(stack).unwrap().set_modified(time).unwrap();
+}
+
+test! {
+    lists_installs_and_rollbacks_in_order {
+        let dir = scratch("feed");
+        let utc = Zone::from_str("UTC").unwrap();
+        install_at(&dir, "a.yaml", "20181101-090000");
+        install_at(&dir, "a.yaml", "20181105-103813");
+        install_at(&dir, "b.yaml", "20181103-120000");
+        restore(dir.join("root"), &at("20181104-000000"), ResolvePolicy::Current, &SwinstallLayout::default(),
+                &LockOptions::default(), &dir.join("journal"), None).unwrap();
+        touch_stack(&dir, "a.yaml", "20181106-080000");
+        touch_stack(&dir, "b.yaml", "20181103-120000");
+
+        let feed = changes(&[dir.join("root")], &at("20181102-000000"), None, utc, &SwinstallLayout::default()).unwrap();
+        let summary = feed.iter()
+            .map(|c| (c.datetime.format(BAKTIMEFMT).to_string(), c.kind.to_string(),
+                      c.target.live.file_name().unwrap().to_string_lossy().into_owned(), c.version.to_string()))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("20181103-120000", "install", "b.yaml", "20181103-120000"),
+            ("20181105-103813", "install", "a.yaml", "20181105-103813"),
+            ("20181106-080000", "rollback", "a.yaml", "20181101-090000"),
+        ];
+        assert_eq!(summary, expected.iter()
+            .map(|&(a, b, c, d)| (a.to_string(), b.to_string(), c.to_string(), d.to_string()))
+            .collect::<Vec<_>>());
+        assert_eq!(feed[2].kind, ChangeKind::Rollback { from: "20181105-103813".parse().unwrap() });
+
+        // the window is inclusive at both ends
+        let feed = changes(&[dir.join("root")], &at("20181103-120000"), Some(&at("20181105-103813")), utc,
+                           &SwinstallLayout::default()).unwrap();
+        assert_eq!(feed.len(), 2);
+    }
+}
+
+test! {
+    skips_stacks_modified_before_the_window {
+        let dir = scratch("skip");
+        let utc = Zone::from_str("UTC").unwrap();
+        install_at(&dir, "a.yaml", "20181105-103813");
+        // a stack last written before the window is not read
+        touch_stack(&dir, "a.yaml", "20181101-000000");
+        let feed = changes(&[dir.join("root")], &at("20181102-000000"), None, utc, &SwinstallLayout::default()).unwrap();
+        assert!(feed.is_empty());
+    }
+}
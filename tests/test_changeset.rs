@@ -0,0 +1,142 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::{Duration, NaiveDateTime};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use bakbuster::{BAKTIMEFMT, SwinstallLayout};
+use bakbuster::changeset::{changed_files, changeset_diff, find_changeset, find_changesets, plan_changeset_restore, Grouping};
+use bakbuster::install::install;
+use bakbuster::lock::LockOptions;
+use bakbuster::restore::{apply_restore, planned_restore_script};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_changeset_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("root")).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+fn install_at(dir: &Path, name: &str, datetime: &str, revision: Option<&str>, contents: &str) {
+    let source = dir.join("source");
+    fs::write(&source, contents).unwrap();
+    install(&source, dir.join("root").join(name), &at(datetime), revision).unwrap();
+}
+
+fn summary(dir: &Path, grouping: Grouping) -> Vec<(String, Vec<String>)> {
+    find_changesets(&[dir.join("root")], grouping, Duration::seconds(5), &SwinstallLayout::default())
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.id, c.members.iter().map(|m| m.target.live.file_name().unwrap().to_string_lossy().into_owned()).collect()))
+        .collect()
+}
+
+test! {
+    groups_by_revision_then_time {
+        let dir = scratch("group");
+        install_at(&dir, "a.yaml", "20181101-090000", Some("12"), "a1\n");
+        install_at(&dir, "b.yaml", "20181101-093000", Some("12"), "b1\n");
+        install_at(&dir, "a.yaml", "20181102-100000", None, "a2\n");
+        install_at(&dir, "c.yaml", "20181102-100004", None, "c1\n");
+        install_at(&dir, "b.yaml", "20181102-100010", None, "b2\n");
+
+        assert_eq!(summary(&dir, Grouping::Revision), vec![
+            ("r12".to_string(), vec!["a.yaml".to_string(), "b.yaml".to_string()]),
+            ("20181102-100000".to_string(), vec!["a.yaml".to_string(), "c.yaml".to_string()]),
+            ("20181102-100010".to_string(), vec!["b.yaml".to_string()]),
+        ]);
+    }
+}
+
+test! {
+    groups_by_time_ignoring_revisions {
+        let dir = scratch("time");
+        install_at(&dir, "a.yaml", "20181101-090000", Some("12"), "a1\n");
+        install_at(&dir, "b.yaml", "20181101-090003", Some("13"), "b1\n");
+        install_at(&dir, "c.yaml", "20181101-090007", Some("14"), "c1\n");
+        install_at(&dir, "a.yaml", "20181101-090020", Some("14"), "a2\n");
+
+        assert_eq!(summary(&dir, Grouping::Time), vec![
+            ("20181101-090000".to_string(), vec!["a.yaml".to_string(), "b.yaml".to_string(), "c.yaml".to_string()]),
+            ("20181101-090020".to_string(), vec!["a.yaml".to_string()]),
+        ]);
+    }
+}
+
+test! {
+    diffs_a_changeset_against_what_it_replaced {
+        let dir = scratch("diff");
+        install_at(&dir, "a.yaml", "20181101-090000", None, "one\ntwo\n");
+        install_at(&dir, "a.yaml", "20181102-090000", Some("7"), "one\nthree\n");
+        install_at(&dir, "b.yaml", "20181102-090001", Some("7"), "new\n");
+
+        let changeset = find_changeset(&[dir.join("root")], "r7", Grouping::Revision, Duration::seconds(5),
+                                       &SwinstallLayout::default()).unwrap();
+        let diff = changeset_diff(&changeset).unwrap();
+        assert!(diff.contains("a.yaml.20181101-090000\n+++ "));
+        assert!(diff.contains(" one\n-two\n+three\n"));
+        assert!(diff.contains("--- /dev/null\n"));
+        assert!(diff.contains("+new\n"));
+
+        let files = changed_files(&changeset).unwrap();
+        let bak = dir.join("root/bak");
+        assert_eq!(files.iter().map(|f| (f.old.clone(), f.new.clone())).collect::<Vec<_>>(), vec![
+            (Some(bak.join("a.yaml/a.yaml.20181101-090000")), bak.join("a.yaml/a.yaml.20181102-090000_r7")),
+            (None, bak.join("b.yaml/b.yaml.20181102-090001_r7")),
+        ]);
+    }
+}
+
+test! {
+    restores_to_before_a_changeset {
+        let dir = scratch("restore");
+        install_at(&dir, "a.yaml", "20181101-090000", None, "a1\n");
+        install_at(&dir, "a.yaml", "20181102-090000", Some("7"), "a2\n");
+        install_at(&dir, "b.yaml", "20181102-090001", Some("7"), "b1\n");
+        let root = dir.join("root");
+
+        let changeset = find_changeset(&[&root], "r7", Grouping::Revision, Duration::seconds(5),
+                                       &SwinstallLayout::default()).unwrap();
+        let entries = plan_changeset_restore(&changeset, true).unwrap();
+        // b.yaml was first installed by the changeset, so has nothing to go back to
+        assert_eq!(entries.len(), 1);
+        apply_restore(&root, &changeset.start, entries, &LockOptions::default(), &dir.join("journal"), None).unwrap();
+        assert_eq!(fs::read_to_string(root.join("a.yaml")).unwrap(), "a1\n");
+        assert_eq!(fs::read_to_string(root.join("b.yaml")).unwrap(), "b1\n");
+
+        let entries = plan_changeset_restore(&changeset, false).unwrap();
+        assert_eq!(entries.len(), 1);
+        apply_restore(&root, &changeset.end, entries, &LockOptions::default(), &dir.join("journal"), None).unwrap();
+        assert_eq!(fs::read_to_string(root.join("a.yaml")).unwrap(), "a2\n");
+    }
+}
+
+test! {
+    scripts_a_changeset_restore {
+        let dir = scratch("script");
+        install_at(&dir, "a.yaml", "20181101-090000", None, "a1\n");
+        install_at(&dir, "a.yaml", "20181102-090000", Some("7"), "a2\n");
+        let root = dir.join("root");
+
+        let changeset = find_changeset(&[&root], "r7", Grouping::Revision, Duration::seconds(5),
+                                       &SwinstallLayout::default()).unwrap();
+        let entries = plan_changeset_restore(&changeset, true).unwrap();
+        let script = planned_restore_script(&root, &changeset.start, &entries).unwrap();
+        // generating the script changes nothing
+        assert_eq!(fs::read_to_string(root.join("a.yaml")).unwrap(), "a2\n");
+        let path = dir.join("script.sh");
+        fs::write(&path, script.render()).unwrap();
+        assert!(Command::new("sh").arg(&path).output().unwrap().status.success());
+        assert_eq!(fs::read_to_string(root.join("a.yaml")).unwrap(), "a1\n");
+    }
+}
@@ -0,0 +1,44 @@
+extern crate bakbuster;
+use std::path::{Path, PathBuf};
+use bakbuster::config::{Config, ConfigSource};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+test! {
+    central_layout_from_toml {
+        let mut config = Config::default();
+        let toml = r#"
+[layout]
+kind = "central"
+live_root = "/shows/foo"
+bak_root = "/bakroot/foo"
+stack_suffix = "_stack"
+"#;
+        config.merge_toml(toml, ConfigSource::File(PathBuf::from("bakbuster.toml"))).unwrap();
+        let layout = config.layout().unwrap();
+        assert_eq!(
+            layout.stack_path(Path::new("/shows/foo/etc/foo.yaml")).unwrap(),
+            PathBuf::from("/bakroot/foo/etc/foo.yaml/foo.yaml_stack")
+        );
+        assert_eq!(config.get("layout.kind").unwrap().1, &ConfigSource::File(PathBuf::from("bakbuster.toml")));
+    }
+}
+
+test! {
+    central_layout_requires_roots {
+        let mut config = Config::default();
+        config.set("layout.kind", "central", ConfigSource::CommandLine).unwrap();
+        assert!(config.layout().is_err());
+        config.set("layout.kind", "nonsense", ConfigSource::CommandLine).unwrap();
+        assert!(config.layout().is_err());
+    }
+}
+
+test! {
+    unknown_keys_are_rejected {
+        let mut config = Config::default();
+        assert!(config.merge_toml("[layout]\nkidn = \"central\"\n", ConfigSource::CommandLine).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+extern crate bakbuster;
+extern crate regex;
+use regex::Regex;
+use std::path::PathBuf;
+use bakbuster::grep::{grep, pickaxe, GrepOptions};
+use bakbuster::scan::find_swinstalled_files;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn examples() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples")
+}
+
+test! {
+    find_swinstalled_files_under_root {
+        let found = find_swinstalled_files(examples()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].live, examples().join("packages.xml"));
+    }
+}
+
+test! {
+    grep_every_backup {
+        let target = &find_swinstalled_files(examples()).unwrap()[0];
+        let regex = Regex::new(r#"name="foo""#).unwrap();
+        let matches = grep(target, &regex, &GrepOptions::default()).unwrap();
+        assert_eq!(matches.len(), 4);
+        assert!(matches.iter().all(|m| m.line_number == 2));
+    }
+}
+
+test! {
+    pickaxe_reports_added_and_removed {
+        let target = &find_swinstalled_files(examples()).unwrap()[0];
+        let regex = Regex::new(r#"version="2\.3""#).unwrap();
+        let hits = pickaxe(target, &regex, &GrepOptions::default()).unwrap();
+        let hits = hits.iter().map(|h| (h.version.to_string(), h.added())).collect::<Vec<_>>();
+        assert_eq!(hits, vec![
+            ("20181105-103813".to_string(), true),
+            ("20181106-104603".to_string(), false),
+        ]);
+    }
+}
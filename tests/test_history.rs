@@ -0,0 +1,31 @@
+extern crate bakbuster;
+extern crate chrono;
+use self::chrono::NaiveDateTime;
+use std::path::PathBuf;
+use bakbuster::BAKTIMEFMT;
+use bakbuster::history::file_log;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn packages_xml() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("packages.xml")
+}
+
+test! {
+    file_log_newest_first {
+        let now = NaiveDateTime::parse_from_str("20181107-104603", BAKTIMEFMT).unwrap();
+        let log = file_log(packages_xml(), &now).unwrap();
+        let rendered = log.iter()
+                          .map(|e| e.render("{version} {status} {newest_for}"))
+                          .collect::<Vec<_>>();
+        assert_eq!(rendered, vec![
+            "20181106-104603 rolled-back 1d 0h 0m",
+            "20181105-103813 current 1d 0h 7m",
+            "20181102-144204 - 2d 19h 56m",
+            "20161213-093146_r575055 - 689d 5h 10m",
+        ]);
+        assert!(log.iter().all(|e| e.size.is_some()));
+    }
+}
@@ -0,0 +1,118 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use bakbuster::{BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::index::{build_index, find_stacks, index_path, index_stats, update_index};
+use bakbuster::install::install;
+use bakbuster::listing::list_at;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_index_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("root/sub")).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+fn install_at(dir: &Path, name: &str, datetime: &str) {
+    let source = dir.join("source");
+    fs::write(&source, datetime).unwrap();
+    install(&source, dir.join("root").join(name), &at(datetime), None).unwrap();
+}
+
+// backdate everything beneath `path` to `secs` after the epoch, as the index
+// does not trust files modified just before it was written
+fn age(path: &Path, secs: u64) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).unwrap() {
+            age(&entry.unwrap().path(), secs);
+        }
+    }
+    fs::File::open(path).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+}
+
+test! {
+    only_reads_changed_stacks {
+        let dir = scratch("update");
+        let root = dir.join("root");
+        install_at(&dir, "a.yaml", "20181101-090000");
+        install_at(&dir, "sub/b.yaml", "20181101-090000");
+        age(&root, 1_500_000_000);
+        let layout = SwinstallLayout::default();
+
+        let built = build_index(&root, &layout).unwrap();
+        assert_eq!((built.stacks, built.read, built.reused), (2, 2, 0));
+        assert!(index_path(&root, &layout).unwrap().is_file());
+        let updated = update_index(&root, &layout).unwrap();
+        assert_eq!((updated.stacks, updated.read, updated.reused), (2, 0, 2));
+
+        install_at(&dir, "sub/b.yaml", "20181102-090000");
+        install_at(&dir, "sub/c.yaml", "20181102-090000");
+        let found = find_stacks(&root, &layout).unwrap();
+        let indexed = found.iter()
+            .map(|f| (f.target.live.strip_prefix(&root).unwrap().to_path_buf(), f.indexed.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(indexed, vec![
+            (PathBuf::from("a.yaml"), true),
+            (PathBuf::from("sub/b.yaml"), false),
+            (PathBuf::from("sub/c.yaml"), false),
+        ]);
+
+        let stats = index_stats(&root, &layout).unwrap();
+        assert_eq!((stats.stacks, stats.entries, stats.stale_stacks), (2, 2, 1));
+        assert!(stats.stale_dirs > 0);
+
+        age(&root.join("sub"), 1_500_000_100);
+        let updated = update_index(&root, &layout).unwrap();
+        assert_eq!((updated.stacks, updated.read, updated.reused), (3, 2, 1));
+    }
+}
+
+test! {
+    writing_the_index_leaves_the_root_trusted {
+        let dir = scratch("root");
+        let root = dir.join("root");
+        install_at(&dir, "a.yaml", "20181101-090000");
+        let layout = SwinstallLayout::default();
+
+        build_index(&root, &layout).unwrap();
+        age(&root, 1_500_000_000);
+        update_index(&root, &layout).unwrap();
+        let stats = index_stats(&root, &layout).unwrap();
+        assert_eq!((stats.stale_stacks, stats.stale_dirs), (0, 0));
+        let updated = update_index(&root, &layout).unwrap();
+        assert_eq!((updated.stacks, updated.read, updated.reused), (1, 0, 1));
+    }
+}
+
+test! {
+    queries_agree_with_a_live_scan {
+        let dir = scratch("query");
+        let root = dir.join("root");
+        install_at(&dir, "a.yaml", "20181101-090000");
+        install_at(&dir, "a.yaml", "20181103-090000");
+        install_at(&dir, "sub/b.yaml", "20181102-090000");
+        age(&root, 1_500_000_000);
+        let layout = SwinstallLayout::default();
+        let live = list_at(&root, &at("20181102-120000"), ResolvePolicy::Current, true, &layout).unwrap();
+
+        build_index(&root, &layout).unwrap();
+        assert_eq!(list_at(&root, &at("20181102-120000"), ResolvePolicy::Current, true, &layout).unwrap(), live);
+        // a subdirectory uses the index at the root above it
+        let sub = list_at(root.join("sub"), &at("20181102-120000"), ResolvePolicy::Current, false, &layout).unwrap();
+        assert_eq!(sub, live[1..].to_vec());
+        assert!(find_stacks(&root.join("sub"), &layout).unwrap()[0].indexed.is_some());
+    }
+}
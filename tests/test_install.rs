@@ -0,0 +1,123 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use bakbuster::{read_stack_history, BAKTIMEFMT};
+use bakbuster::install::install;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_install_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+test! {
+    install_writes_backup_stack_and_live {
+        let dir = scratch("writes");
+        let source = dir.join("source.yaml");
+        let live = dir.join("foo.yaml");
+
+        fs::write(&source, "foo: 1\n").unwrap();
+        let first = install(&source, &live, &at("20181102-144204"), Some("575055")).unwrap();
+        fs::write(&source, "foo: 2\n").unwrap();
+        let second = install(&source, &live, &at("20181105-103813"), None).unwrap();
+
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 2\n");
+        assert_eq!(first.backup, dir.join("bak/foo.yaml/foo.yaml.20181102-144204_r575055"));
+        assert_eq!(fs::read_to_string(&first.backup).unwrap(), "foo: 1\n");
+        assert_eq!(fs::read_to_string(&second.backup).unwrap(), "foo: 2\n");
+
+        let history = read_stack_history(&second.target.stack).unwrap();
+        assert_eq!(history.path, Some(second.target.stack.to_string_lossy().into_owned()));
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.current().unwrap().version, second.version);
+        assert!(!history.entries[0].is_current);
+
+        // no temporary files are left behind
+        let hidden = fs::read_dir(dir.join("bak/foo.yaml")).unwrap()
+                       .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with('.'))
+                       .count();
+        assert_eq!(hidden, 0);
+    }
+}
+
+test! {
+    install_appends_to_existing_stack {
+        let dir = scratch("existing");
+        let bak = dir.join("bak/packages.xml");
+        fs::create_dir_all(&bak).unwrap();
+        let examples = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/bak/packages.xml");
+        fs::copy(examples.join("packages.xml_swinstall_stack"), bak.join("packages.xml_swinstall_stack")).unwrap();
+        let source = dir.join("source.xml");
+        fs::write(&source, "<packages/>\n").unwrap();
+
+        let installed = install(&source, dir.join("packages.xml"), &at("20181107-090000"), None).unwrap();
+        let history = read_stack_history(&installed.target.stack).unwrap();
+        // the recorded path is left as swinstall wrote it
+        assert_eq!(history.path, Some("/dd/facility/etc/bak/packages.xml/packages.xml_swinstall_stack".to_string()));
+        assert_eq!(history.entries.len(), 5);
+        assert_eq!(history.current_index(), Some(4));
+    }
+}
+
+test! {
+    install_refuses_duplicate_version {
+        let dir = scratch("duplicate");
+        let source = dir.join("source.yaml");
+        fs::write(&source, "foo: 1\n").unwrap();
+        install(&source, dir.join("foo.yaml"), &at("20181102-144204"), None).unwrap();
+        assert!(install(&source, dir.join("foo.yaml"), &at("20181102-144204"), None).is_err());
+    }
+}
+
+test! {
+    failed_live_replace_rolls_back_the_stack {
+        let dir = scratch("rollback");
+        let source = dir.join("source.yaml");
+        let live = dir.join("foo.yaml");
+        fs::write(&source, "foo: 1\n").unwrap();
+        let first = install(&source, &live, &at("20181102-144204"), None).unwrap();
+        let stack_before = fs::read_to_string(&first.target.stack).unwrap();
+
+        // the live file cannot be renamed over once it is a non-empty directory,
+        // so the install fails after the stack is written. Permissions cannot be
+        // relied on to make it fail, as tests may run as root.
+        fs::remove_file(&live).unwrap();
+        fs::create_dir_all(live.join("occupied")).unwrap();
+        fs::write(&source, "foo: 2\n").unwrap();
+        assert!(install(&source, &live, &at("20181105-103813"), None).is_err());
+
+        assert_eq!(fs::read_to_string(&first.target.stack).unwrap(), stack_before);
+        assert!(!dir.join("bak/foo.yaml/foo.yaml.20181105-103813").exists());
+
+        // nothing is left to refuse a retry
+        fs::remove_dir_all(&live).unwrap();
+        assert!(install(&source, &live, &at("20181105-103813"), None).is_ok());
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 2\n");
+    }
+}
+
+test! {
+    failed_first_install_removes_the_stack {
+        let dir = scratch("rollback_new");
+        let source = dir.join("source.yaml");
+        let live = dir.join("foo.yaml");
+        fs::write(&source, "foo: 1\n").unwrap();
+        fs::create_dir_all(live.join("occupied")).unwrap();
+        assert!(install(&source, &live, &at("20181102-144204"), None).is_err());
+        assert!(!dir.join("bak/foo.yaml/foo.yaml_swinstall_stack").exists());
+        assert!(!dir.join("bak/foo.yaml/foo.yaml.20181102-144204").exists());
+    }
+}
@@ -0,0 +1,63 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use bakbuster::{BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::install::install;
+use bakbuster::listing::{list_at, ListedStatus};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_listing_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("root/sub")).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+fn install_at(dir: &Path, name: &str, datetime: &str) {
+    let source = dir.join("source");
+    fs::write(&source, name).unwrap();
+    install(&source, dir.join("root").join(name), &at(datetime), None).unwrap();
+}
+
+fn statuses(dir: &Path, datetime: &str, recursive: bool) -> Vec<(String, ListedStatus, Option<String>)> {
+    list_at(dir.join("root"), &at(datetime), ResolvePolicy::Current, recursive, &SwinstallLayout::default())
+        .unwrap()
+        .into_iter()
+        .map(|listed| (listed.target.live.file_name().unwrap().to_string_lossy().into_owned(),
+                       listed.status,
+                       listed.version.map(|v| v.to_string())))
+        .collect()
+}
+
+test! {
+    lists_files_as_of_a_datetime {
+        let dir = scratch("at");
+        install_at(&dir, "a.yaml", "20181102-144204");
+        install_at(&dir, "b.yaml", "20181102-144204");
+        install_at(&dir, "c.yaml", "20181105-103813");
+        install_at(&dir, "sub/d.yaml", "20181102-144204");
+        // removed from the live tree, but its bak directory remains
+        fs::remove_file(dir.join("root/b.yaml")).unwrap();
+
+        assert_eq!(statuses(&dir, "20181103-000000", false), vec![
+            ("a.yaml".to_string(), ListedStatus::Present, Some("20181102-144204".to_string())),
+            ("b.yaml".to_string(), ListedStatus::SinceDeleted, Some("20181102-144204".to_string())),
+            ("c.yaml".to_string(), ListedStatus::AbsentYet, None),
+        ]);
+        let recursive = statuses(&dir, "20181106-000000", true);
+        assert_eq!(recursive.len(), 4);
+        assert_eq!(recursive[2], ("c.yaml".to_string(), ListedStatus::Present, Some("20181105-103813".to_string())));
+        assert_eq!(recursive[3].0, "d.yaml");
+    }
+}
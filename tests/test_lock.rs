@@ -0,0 +1,128 @@
+extern crate bakbuster;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use bakbuster::read_stack_history;
+use bakbuster::lock::{holder_id, lock_stack, LockMethod, LockOptions};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_lock_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn options(method: LockMethod) -> LockOptions {
+    LockOptions { method, timeout: Duration::from_millis(100) }
+}
+
+test! {
+    flock_excludes_second_holder {
+        let stack = scratch("flock").join("foo.yaml_swinstall_stack");
+        let held = lock_stack(&stack, &options(LockMethod::Flock)).unwrap();
+        let err = lock_stack(&stack, &options(LockMethod::Flock)).unwrap_err();
+        assert!(err.to_string().contains(&holder_id()), "{}", err);
+        drop(held);
+        assert!(lock_stack(&stack, &options(LockMethod::Flock)).is_ok());
+    }
+}
+
+test! {
+    flock_holder_excludes_lockfile_clients {
+        let stack = scratch("mixed").join("foo.yaml_swinstall_stack");
+        let held = lock_stack(&stack, &options(LockMethod::Flock)).unwrap();
+        let err = lock_stack(&stack, &options(LockMethod::LockFile)).unwrap_err();
+        assert!(err.to_string().contains(&holder_id()), "{}", err);
+        let lockfile = held.path().to_path_buf();
+        drop(held);
+        assert!(!lockfile.exists());
+        assert!(lock_stack(&stack, &options(LockMethod::LockFile)).is_ok());
+    }
+}
+
+test! {
+    lockfile_excludes_second_holder {
+        let stack = scratch("lockfile").join("foo.yaml_swinstall_stack");
+        let held = lock_stack(&stack, &options(LockMethod::LockFile)).unwrap();
+        assert!(held.path().exists());
+        assert!(lock_stack(&stack, &options(LockMethod::LockFile)).is_err());
+        // flock holders respect a lockfile too
+        assert!(lock_stack(&stack, &options(LockMethod::Flock)).is_err());
+        let path = held.path().to_path_buf();
+        drop(held);
+        assert!(!path.exists());
+        assert!(lock_stack(&stack, &options(LockMethod::LockFile)).is_ok());
+    }
+}
+
+test! {
+    stale_lockfile_is_broken {
+        let dir = scratch("stale");
+        let stack = dir.join("foo.yaml_swinstall_stack");
+        let host = holder_id().split_once('@').unwrap().1.to_string();
+        fs::write(dir.join("foo.yaml_swinstall_stack.lck"), format!("{}@{}", i32::MAX - 1, host)).unwrap();
+        assert!(lock_stack(&stack, &options(LockMethod::LockFile)).is_ok());
+
+        // a lock held on another host is never assumed stale
+        fs::write(dir.join("foo.yaml_swinstall_stack.lck"), "1@some-other-host").unwrap();
+        let err = lock_stack(&stack, &options(LockMethod::LockFile)).unwrap_err();
+        assert!(err.to_string().contains("1@some-other-host"), "{}", err);
+    }
+}
+
+test! {
+    concurrent_waiters_break_a_stale_lock_once {
+        let dir = scratch("stale_race");
+        let stack = dir.join("foo.yaml_swinstall_stack");
+        let host = holder_id().split_once('@').unwrap().1.to_string();
+        fs::write(dir.join("foo.yaml_swinstall_stack.lck"), format!("{}@{}", i32::MAX - 1, host)).unwrap();
+        let holding = Arc::new(AtomicUsize::new(0));
+        let overlaps = Arc::new(AtomicUsize::new(0));
+        let start = Arc::new(Barrier::new(16));
+        let waiters = (0..16).map(|_| {
+            let (stack, holding, overlaps, start) = (stack.clone(), holding.clone(), overlaps.clone(), start.clone());
+            thread::spawn(move || {
+                start.wait();
+                let options = LockOptions { method: LockMethod::LockFile, timeout: Duration::from_secs(10) };
+                let _lock = lock_stack(&stack, &options).unwrap();
+                if holding.fetch_add(1, Ordering::SeqCst) != 0 {
+                    overlaps.fetch_add(1, Ordering::SeqCst);
+                }
+                thread::sleep(Duration::from_millis(20));
+                holding.fetch_sub(1, Ordering::SeqCst);
+            })
+        }).collect::<Vec<_>>();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(overlaps.load(Ordering::SeqCst), 0);
+        assert!(!dir.join("foo.yaml_swinstall_stack.lck").exists());
+    }
+}
+
+test! {
+    reader_retries_truncated_stack {
+        let stack = scratch("truncated").join("foo.yaml_swinstall_stack");
+        let full = "<stack_history>\n    <elt is_current=\"True\" version=\"20181105-103813\" />\n</stack_history>\n";
+        fs::write(&stack, &full[..30]).unwrap();
+        let writer = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(30));
+                fs::write(&stack, full).unwrap();
+            })
+        };
+        let history = read_stack_history(&stack).unwrap();
+        writer.join().unwrap();
+        assert_eq!(history.entries.len(), 1);
+    }
+}
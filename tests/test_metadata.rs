@@ -0,0 +1,172 @@
+#![cfg(unix)]
+extern crate bakbuster;
+extern crate chrono;
+extern crate libc;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, UNIX_EPOCH};
+use bakbuster::{BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::install::{install, install_recorded, InstallOptions};
+use bakbuster::lock::LockOptions;
+use bakbuster::metadata::{FileMetadata, Ownership};
+use bakbuster::oplog::{OpKind, OpLog};
+use bakbuster::restore::restore;
+use bakbuster::stack_history_parser::read_stack_history;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_metadata_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().mode() & 0o7777
+}
+
+fn write_source(path: &Path, contents: &str, mtime_secs: u64) {
+    fs::write(path, contents).unwrap();
+    fs::File::options().write(true).open(path).unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(mtime_secs)).unwrap();
+}
+
+// give everything beneath `path` to `uid` and `gid`, group writable
+fn share(path: &Path, uid: u32, gid: u32) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).unwrap() {
+            share(&entry.unwrap().path(), uid, gid);
+        }
+    }
+    chown(path, Some(uid), Some(gid)).unwrap();
+    fs::set_permissions(path, Permissions::from_mode(if path.is_dir() { 0o775 } else { 0o664 })).unwrap();
+}
+
+test! {
+    install_preserves_mode_and_takes_source_mtime {
+        let dir = scratch("install");
+        let source = dir.join("source");
+        let live = dir.join("foo.yaml");
+        let stack = dir.join("bak/foo.yaml/foo.yaml_swinstall_stack");
+
+        write_source(&source, "foo: 1\n", 1_000_000_000);
+        install(&source, &live, &at("20181102-144204"), None).unwrap();
+        fs::set_permissions(&live, Permissions::from_mode(0o2664)).unwrap();
+        fs::set_permissions(&stack, Permissions::from_mode(0o660)).unwrap();
+
+        write_source(&source, "foo: 2\n", 1_100_000_000);
+        fs::set_permissions(&source, Permissions::from_mode(0o600)).unwrap();
+        install(&source, &live, &at("20181105-103813"), None).unwrap();
+
+        assert_eq!(mode(&live), 0o2664);
+        assert_eq!(mode(&stack), 0o660);
+        let metadata = FileMetadata::of(&live).unwrap();
+        assert_eq!(metadata.mtime, Some(UNIX_EPOCH + Duration::from_secs(1_100_000_000)));
+        // the backup is a copy of the source
+        assert_eq!(mode(&dir.join("bak/foo.yaml/foo.yaml.20181105-103813")), 0o600);
+    }
+}
+
+test! {
+    install_sets_explicit_mode {
+        let dir = scratch("explicit");
+        let source = dir.join("source");
+        let live = dir.join("foo.yaml");
+        write_source(&source, "foo: 1\n", 1_000_000_000);
+
+        let oplog = OpLog::new(dir.join("log"));
+        let mut operation = oplog.begin(OpKind::Install, &at("20181102-144204")).unwrap();
+        let uid = fs::metadata(&source).unwrap().uid();
+        let options = InstallOptions { ownership: Ownership { mode: Some(0o640), uid: Some(uid), gid: None }, ..InstallOptions::default() };
+        install_recorded(&source, &live, &at("20181102-144204"), None, &SwinstallLayout::default(), &options, &mut operation).unwrap();
+        assert_eq!(mode(&live), 0o640);
+    }
+}
+
+test! {
+    restore_preserves_mode_and_takes_backup_mtime {
+        let dir = scratch("restore");
+        let source = dir.join("source");
+        let live = dir.join("root/foo.yaml");
+        fs::create_dir_all(dir.join("root")).unwrap();
+        write_source(&source, "foo: 1\n", 1_000_000_000);
+        install(&source, &live, &at("20181102-144204"), None).unwrap();
+        write_source(&source, "foo: 2\n", 1_100_000_000);
+        install(&source, &live, &at("20181105-103813"), None).unwrap();
+        fs::set_permissions(&live, Permissions::from_mode(0o640)).unwrap();
+
+        restore(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current, &SwinstallLayout::default(),
+                &LockOptions::default(), &dir.join("journal"), None).unwrap();
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 1\n");
+        assert_eq!(mode(&live), 0o640);
+        assert_eq!(FileMetadata::of(&live).unwrap().mtime, Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)));
+    }
+}
+
+test! {
+    install_rewrites_a_colleagues_group_writable_file_in_place {
+        // switching to another user needs root
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let (owner, colleague, group) = (4241, 4242, 4240);
+        let dir = scratch("group");
+        let source = dir.join("source");
+        let live = dir.join("root/foo.yaml");
+        let stack = dir.join("root/bak/foo.yaml/foo.yaml_swinstall_stack");
+        fs::create_dir_all(dir.join("root")).unwrap();
+        write_source(&source, "foo: 1\n", 1_000_000_000);
+        install(&source, &live, &at("20181102-144204"), None).unwrap();
+        share(&dir.join("root"), owner, group);
+        write_source(&source, "foo: 2\n", 1_100_000_000);
+        fs::set_permissions(&source, Permissions::from_mode(0o644)).unwrap();
+        let home = dir.join("home");
+        fs::create_dir_all(&home).unwrap();
+        chown(&home, Some(colleague), Some(colleague)).unwrap();
+        let inode = fs::metadata(&live).unwrap().ino();
+
+        // the build directory may not be readable by the colleague
+        let bakbuster = dir.join("bakbuster");
+        fs::copy(env!("CARGO_BIN_EXE_bakbuster"), &bakbuster).unwrap();
+        let mut command = Command::new(&bakbuster);
+        command.arg("install").arg(&source).arg(&live)
+               .env("HOME", &home)
+               .env("XDG_CONFIG_HOME", &home)
+               .env("XDG_STATE_HOME", &home);
+        unsafe {
+            command.pre_exec(move || {
+                let groups = [group as libc::gid_t];
+                if libc::setgroups(1, groups.as_ptr()) != 0
+                    || libc::setgid(colleague) != 0
+                    || libc::setuid(colleague) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 2\n");
+        for path in &[&live, &stack] {
+            let metadata = fs::metadata(path).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid(), metadata.mode() & 0o7777), (owner, group, 0o664));
+        }
+        assert_eq!(fs::metadata(&live).unwrap().ino(), inode);
+        assert_eq!(read_stack_history(&stack).unwrap().entries.len(), 2);
+    }
+}
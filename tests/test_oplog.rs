@@ -0,0 +1,135 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use bakbuster::{read_stack_history, BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::install::{install, install_recorded, InstallOptions};
+use bakbuster::lock::LockOptions;
+use bakbuster::oplog::{undo, OpKind, OpLog};
+use bakbuster::restore::restore;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_oplog_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+fn install_logged(oplog: &OpLog, source: &Path, dest: &Path, datetime: &str) -> String {
+    let mut operation = oplog.begin(OpKind::Install, &at(datetime)).unwrap();
+    install_recorded(source, dest, &at(datetime), None, &SwinstallLayout::default(), &InstallOptions::default(), &mut operation).unwrap();
+    operation.commit().unwrap();
+    operation.id
+}
+
+test! {
+    undo_reverses_installs {
+        let dir = scratch("install");
+        let oplog = OpLog::new(dir.join("log"));
+        let source = dir.join("source");
+        let live = dir.join("foo.yaml");
+        let stack = dir.join("bak/foo.yaml/foo.yaml_swinstall_stack");
+
+        fs::write(&source, "foo: 1\n").unwrap();
+        let first = install_logged(&oplog, &source, &live, "20181102-144204");
+        fs::write(&source, "foo: 2\n").unwrap();
+        install_logged(&oplog, &source, &live, "20181105-103813");
+        assert_eq!(oplog.operations().unwrap().len(), 2);
+
+        // the most recent operation is undone by default
+        let undone = undo(&oplog, None, &LockOptions::default()).unwrap();
+        assert_eq!(undone.files[0].to.to_string(), "20181105-103813");
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 1\n");
+        let history = read_stack_history(&stack).unwrap();
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.current().unwrap().version.to_string(), "20181102-144204");
+        assert!(!dir.join("bak/foo.yaml/foo.yaml.20181105-103813").exists());
+
+        // undoing the first install removes everything it created
+        let undone = undo(&oplog, None, &LockOptions::default()).unwrap();
+        assert_eq!(undone.id, first);
+        assert!(!live.exists());
+        assert!(!stack.exists());
+        assert!(!dir.join("bak/foo.yaml/foo.yaml.20181102-144204").exists());
+
+        assert!(oplog.operations().unwrap().iter().all(|op| op.undone));
+        assert!(undo(&oplog, None, &LockOptions::default()).is_err());
+        assert!(undo(&oplog, Some(first.as_str()), &LockOptions::default()).is_err());
+    }
+}
+
+test! {
+    undo_refuses_changed_files {
+        let dir = scratch("changed");
+        let oplog = OpLog::new(dir.join("log"));
+        let source = dir.join("source");
+        let live = dir.join("foo.yaml");
+
+        fs::write(&source, "foo: 1\n").unwrap();
+        let first = install_logged(&oplog, &source, &live, "20181102-144204");
+        // an install which is not logged
+        install(&source, &live, &at("20181105-103813"), None).unwrap();
+
+        assert!(undo(&oplog, Some(first.as_str()), &LockOptions::default()).is_err());
+        assert!(live.exists());
+        assert!(!oplog.find(Some(first.as_str())).unwrap().undone);
+    }
+}
+
+test! {
+    undo_reverses_restores {
+        let dir = scratch("restore");
+        let oplog = OpLog::new(dir.join("log"));
+        let source = dir.join("source");
+        let live = dir.join("root/foo.yaml");
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::write(&source, "foo: 1\n").unwrap();
+        install(&source, &live, &at("20181102-144204"), None).unwrap();
+        fs::write(&source, "foo: 2\n").unwrap();
+        install(&source, &live, &at("20181105-103813"), None).unwrap();
+
+        let mut operation = oplog.begin(OpKind::Restore, &at("20181106-000000")).unwrap();
+        restore(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current, &SwinstallLayout::default(),
+                &LockOptions::default(), &dir.join("journal"), Some(&mut operation)).unwrap();
+        operation.commit().unwrap();
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 1\n");
+
+        undo(&oplog, None, &LockOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 2\n");
+        let history = read_stack_history(dir.join("root/bak/foo.yaml/foo.yaml_swinstall_stack")).unwrap();
+        assert_eq!(history.current().unwrap().version.to_string(), "20181105-103813");
+        // backups are untouched, as the restore created none
+        assert!(dir.join("root/bak/foo.yaml/foo.yaml.20181102-144204").exists());
+    }
+}
+
+test! {
+    prunes_by_count_and_age {
+        let dir = scratch("prune");
+        let oplog = OpLog::new(dir.join("log"));
+        let source = dir.join("source");
+        fs::write(&source, "foo: 1\n").unwrap();
+        let ids = ["20181101-090000", "20181102-090000", "20181103-090000", "20181104-090000"].iter()
+            .map(|datetime| install_logged(&oplog, &source, &dir.join("foo.yaml"), datetime))
+            .collect::<Vec<_>>();
+
+        assert!(oplog.prune(Some(4), Some(at("20181101-000000"))).unwrap().is_empty());
+        let pruned = oplog.prune(Some(3), None).unwrap();
+        assert_eq!(pruned.iter().map(|op| op.id.clone()).collect::<Vec<_>>(), ids[..1].to_vec());
+        let pruned = oplog.prune(None, Some(at("20181103-000000"))).unwrap();
+        assert_eq!(pruned.iter().map(|op| op.id.clone()).collect::<Vec<_>>(), ids[1..2].to_vec());
+        assert_eq!(oplog.operations().unwrap().iter().map(|op| op.id.clone()).collect::<Vec<_>>(), ids[2..].to_vec());
+        assert!(!dir.join("log").join(&ids[0]).exists());
+    }
+}
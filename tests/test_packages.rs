@@ -0,0 +1,52 @@
+extern crate bakbuster;
+extern crate chrono;
+use self::chrono::NaiveDateTime;
+use std::path::PathBuf;
+use bakbuster::{BAKTIMEFMT, SwinstalledFile};
+use bakbuster::packages::{package_history, package_version_on, PackageCache};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn packages_xml() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("packages.xml")
+}
+
+test! {
+    package_version_on_date {
+        let mut cache = PackageCache::new();
+        let dt = NaiveDateTime::parse_from_str("20181103-000000", BAKTIMEFMT).unwrap();
+        let result = package_version_on(packages_xml(), "foo", &dt, &mut cache).unwrap();
+        assert_eq!(result.file_version.to_string(), "20181102-144204");
+        assert_eq!(result.version, Some("2.1".to_string()));
+    }
+}
+
+test! {
+    package_history_ignores_rolled_back_installs {
+        let mut cache = PackageCache::new();
+        let changes = package_history(packages_xml(), "foo", &mut cache).unwrap();
+        let changes = changes.iter()
+                             .map(|c| (c.file_version.to_string(), c.version.clone().unwrap()))
+                             .collect::<Vec<_>>();
+        assert_eq!(changes, vec![
+            ("20161213-093146_r575055".to_string(), "2.1".to_string()),
+            ("20181105-103813".to_string(), "2.3".to_string()),
+        ]);
+    }
+}
+
+test! {
+    every_path_to_a_stack_shares_a_cache_file {
+        let cache_file = |stack: PathBuf| {
+            let target = SwinstalledFile { live: PathBuf::from("packages.xml"), stack };
+            PackageCache::default_cache_file(&target).unwrap()
+        };
+        let stack = PathBuf::from("packages.xml").join("packages.xml_swinstall_stack");
+        let absolute = cache_file(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/bak").join(&stack));
+        // tests run from the manifest directory
+        assert_eq!(cache_file(PathBuf::from("examples/bak").join(&stack)), absolute);
+        assert_eq!(cache_file(PathBuf::from("examples/../examples/bak").join(&stack)), absolute);
+    }
+}
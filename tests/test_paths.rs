@@ -0,0 +1,99 @@
+#![cfg(unix)]
+extern crate bakbuster;
+use std::env;
+use std::fs;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use bakbuster::{resolve_path, resolve_stack_history_from_path, live_path_for, open_as_of, ParentResolution};
+use bakbuster::utils::parse_datetime;
+use bakbuster::paths::os_concat;
+use bakbuster::whois::whois;
+use bakbuster::SwinstallLayout;
+
+#[macro_use] mod common;
+
+// scratch/real/show/etc/foo.yaml
+// scratch/show -> real/show
+// scratch/links/etc -> ../show/etc
+fn scratch() -> PathBuf {
+    env::temp_dir().join(format!("bakbuster_test_paths_{}", std::process::id()))
+}
+
+fn setup() {
+    let root = scratch();
+    let etc = root.join("real").join("show").join("etc");
+    fs::create_dir_all(etc.join("bak").join("foo.yaml")).unwrap();
+    fs::write(etc.join("foo.yaml"), "foo: 1\n").unwrap();
+    fs::create_dir_all(root.join("links")).unwrap();
+    let _ = symlink("real/show", root.join("show"));
+    let _ = symlink("../show/etc", root.join("links").join("etc"));
+
+    // a legacy file named "caf\xe9.yaml" in Latin-1
+    let name = latin1_name();
+    let bak = etc.join("bak").join(name);
+    fs::create_dir_all(&bak).unwrap();
+    fs::write(etc.join(name), "cafe: 2\n").unwrap();
+    fs::write(bak.join(os_concat(name, "_swinstall_stack")), concat!(
+        "<stack_history path=\"/dd/facility/etc\">\n",
+        "    <elt is_current=\"True\" version=\"20181105-103813\" />\n",
+        "</stack_history>\n")).unwrap();
+    fs::write(bak.join(os_concat(name, ".20181105-103813")), "cafe: 1\n").unwrap();
+}
+
+fn latin1_name() -> &'static OsStr {
+    OsStr::from_bytes(b"caf\xe9.yaml")
+}
+
+test! {
+    physical_parent_follows_links {
+        let root = scratch().canonicalize().unwrap();
+        let resolved = resolve_path(root.join("links/etc/foo.yaml"), ParentResolution::Physical).unwrap();
+        assert_eq!(resolved.path, root.join("real/show/etc/foo.yaml"));
+        let links = resolved.followed.iter().map(|l| l.link.clone()).collect::<Vec<_>>();
+        assert_eq!(links, vec![root.join("links/etc"), root.join("show")]);
+        assert_eq!(resolved.followed[0].target, root.join("show/etc"));
+    }
+}
+
+test! {
+    logical_parent_is_kept {
+        let root = scratch().canonicalize().unwrap();
+        let resolved = resolve_path(root.join("links/etc/../etc/foo.yaml"), ParentResolution::Logical).unwrap();
+        assert_eq!(resolved.path, root.join("links/etc/foo.yaml"));
+        assert!(resolved.followed.is_empty());
+    }
+}
+
+test! {
+    stack_history_beside_physical_parent {
+        let root = scratch().canonicalize().unwrap();
+        let stack = resolve_stack_history_from_path(root.join("links/etc/foo.yaml"), ParentResolution::Physical).unwrap();
+        assert_eq!(stack.path, root.join("real/show/etc/bak/foo.yaml/foo.yaml_swinstall_stack"));
+        assert_eq!(stack.followed.len(), 2);
+        // components which do not exist are appended as written
+        let missing = resolve_path(root.join("show/missing/../etc/foo.yaml"), ParentResolution::Physical).unwrap();
+        assert_eq!(missing.path, root.join("real/show/etc/foo.yaml"));
+    }
+}
+
+test! {
+    non_utf8_names_resolve {
+        let root = scratch().canonicalize().unwrap();
+        let etc = root.join("real/show/etc");
+        let live = etc.join(latin1_name());
+        let stack = resolve_stack_history_from_path(root.join("show/etc").join(latin1_name()), ParentResolution::Physical).unwrap();
+        assert_eq!(stack.path, etc.join("bak").join(latin1_name()).join(os_concat(latin1_name(), "_swinstall_stack")));
+        assert_eq!(live_path_for(stack.path.clone()), Ok(live.clone()));
+
+        let mut contents = String::new();
+        open_as_of(&live, parse_datetime("2018-11-06").unwrap()).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "cafe: 1\n");
+
+        let found = whois(stack.path.with_file_name(os_concat(latin1_name(), ".20181105-103813")), &SwinstallLayout::default()).unwrap();
+        assert_eq!(found.target.live, live);
+        assert_eq!(found.version.unwrap().to_string(), "20181105-103813");
+    }
+}
@@ -0,0 +1,133 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use bakbuster::{read_stack_history, BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::install::install;
+use bakbuster::lock::LockOptions;
+use bakbuster::restore::{abort_restore, restore, resume_restore, JOURNAL_HEADER};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_restore_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("root")).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+// install two versions each of foo.yaml and bar.yaml beneath dir/root
+fn install_two_versions(dir: &Path) {
+    let source = dir.join("source");
+    for name in &["foo.yaml", "bar.yaml"] {
+        fs::write(&source, format!("{} 1\n", name)).unwrap();
+        install(&source, dir.join("root").join(name), &at("20181102-144204"), None).unwrap();
+        fs::write(&source, format!("{} 2\n", name)).unwrap();
+        install(&source, dir.join("root").join(name), &at("20181105-103813"), None).unwrap();
+    }
+}
+
+fn current(dir: &Path, name: &str) -> String {
+    let stack = dir.join("root/bak").join(name).join(format!("{}_swinstall_stack", name));
+    read_stack_history(&stack).unwrap().current().unwrap().version.to_string()
+}
+
+test! {
+    restore_restores_every_file {
+        let dir = scratch("every");
+        install_two_versions(&dir);
+        let journal = dir.join("journal");
+
+        let restored = restore(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current,
+                               &SwinstallLayout::default(), &LockOptions::default(), &journal, None).unwrap();
+        assert_eq!(restored.len(), 2);
+        for name in &["foo.yaml", "bar.yaml"] {
+            assert_eq!(fs::read_to_string(dir.join("root").join(name)).unwrap(), format!("{} 1\n", name));
+            assert_eq!(current(&dir, name), "20181102-144204");
+        }
+        assert!(!journal.exists());
+
+        // a second restore has nothing to do
+        let restored = restore(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current,
+                               &SwinstallLayout::default(), &LockOptions::default(), &journal, None).unwrap();
+        assert!(restored.is_empty());
+    }
+}
+
+test! {
+    restore_rolls_back_on_failure {
+        let dir = scratch("failure");
+        install_two_versions(&dir);
+        let journal = dir.join("journal");
+        fs::remove_file(dir.join("root/bak/foo.yaml/foo.yaml.20181102-144204")).unwrap();
+
+        let result = restore(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current,
+                             &SwinstallLayout::default(), &LockOptions::default(), &journal, None);
+        assert!(result.is_err());
+        for name in &["foo.yaml", "bar.yaml"] {
+            assert_eq!(fs::read_to_string(dir.join("root").join(name)).unwrap(), format!("{} 2\n", name));
+            assert_eq!(current(&dir, name), "20181105-103813");
+        }
+        assert!(!journal.exists());
+    }
+}
+
+test! {
+    restore_resumes_an_interrupted_run {
+        let dir = scratch("resume");
+        install_two_versions(&dir);
+        let journal = dir.join("journal");
+        fs::create_dir_all(&journal).unwrap();
+        let mut contents = format!("{}\nroot\t{}\nat\t2018-11-03 00:00:00\n", JOURNAL_HEADER, dir.join("root").display());
+        for name in &["foo.yaml", "bar.yaml"] {
+            contents.push_str(&format!("entry\tpending\t{}\t{}\t20181105-103813\t20181102-144204\n",
+                dir.join("root").join(name).display(),
+                dir.join("root/bak").join(name).join(format!("{}_swinstall_stack", name)).display()));
+        }
+        fs::write(journal.join("journal"), contents).unwrap();
+
+        // an interrupted restore must be finished first
+        assert!(restore(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current,
+                        &SwinstallLayout::default(), &LockOptions::default(), &journal, None).is_err());
+
+        let restored = resume_restore(&journal, &LockOptions::default(), None).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(fs::read_to_string(dir.join("root/foo.yaml")).unwrap(), "foo.yaml 1\n");
+        assert_eq!(current(&dir, "bar.yaml"), "20181102-144204");
+        assert!(!journal.exists());
+    }
+}
+
+test! {
+    restore_abort_puts_files_back {
+        let dir = scratch("abort");
+        install_two_versions(&dir);
+        let journal = dir.join("journal");
+        let live = dir.join("root/foo.yaml");
+        let stack = dir.join("root/bak/foo.yaml/foo.yaml_swinstall_stack");
+        fs::create_dir_all(&journal).unwrap();
+        fs::copy(&stack, journal.join("0.stack")).unwrap();
+        fs::copy(&live, journal.join("0.live")).unwrap();
+        fs::write(journal.join("journal"), format!(
+            "{}\nroot\t{}\nat\t2018-11-03 00:00:00\nentry\tsaved\t{}\t{}\t20181105-103813\t20181102-144204\n",
+            JOURNAL_HEADER, dir.join("root").display(), live.display(), stack.display())).unwrap();
+        // interrupted part way through replacing the live file
+        fs::write(&live, "partial").unwrap();
+
+        let put_back = abort_restore(&journal, &LockOptions::default()).unwrap();
+        assert_eq!(put_back.len(), 1);
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo.yaml 2\n");
+        assert_eq!(current(&dir, "foo.yaml"), "20181105-103813");
+        assert!(!journal.exists());
+        assert!(resume_restore(&journal, &LockOptions::default(), None).is_err());
+    }
+}
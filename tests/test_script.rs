@@ -0,0 +1,152 @@
+extern crate bakbuster;
+extern crate chrono;
+use chrono::NaiveDateTime;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use bakbuster::{read_stack_history, BAKTIMEFMT, ResolvePolicy, SwinstallLayout};
+use bakbuster::install::{install, install_recorded, install_script, InstallOptions};
+use bakbuster::metadata::Ownership;
+use bakbuster::oplog::{undo_script, OpKind, OpLog};
+use bakbuster::restore::restore_script;
+use bakbuster::script::{sha256_file, Script};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+// a fresh directory per test, as tests run concurrently
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bakbuster_test_script_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("root")).unwrap();
+    dir
+}
+
+fn at(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, BAKTIMEFMT).unwrap()
+}
+
+// write the script out and run it with sh, returning whether it succeeded
+fn run(dir: &Path, script: &Script) -> bool {
+    let path = dir.join("script.sh");
+    fs::write(&path, script.render()).unwrap();
+    Command::new("sh").arg(&path).output().unwrap().status.success()
+}
+
+fn install_two_versions(dir: &Path) -> PathBuf {
+    let source = dir.join("source");
+    let live = dir.join("root/foo.yaml");
+    fs::write(&source, "foo: 1\n").unwrap();
+    install(&source, &live, &at("20181102-144204"), None).unwrap();
+    fs::write(&source, "foo: 2\n").unwrap();
+    install(&source, &live, &at("20181105-103813"), None).unwrap();
+    live
+}
+
+test! {
+    hashes_files {
+        let dir = scratch("hash");
+        fs::write(dir.join("abc"), "abc").unwrap();
+        assert_eq!(sha256_file(&dir.join("abc")).unwrap(),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
+
+test! {
+    restore_script_restores_files {
+        let dir = scratch("restore");
+        let live = install_two_versions(&dir);
+        let script = restore_script(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current, &SwinstallLayout::default()).unwrap();
+
+        // generating the script changes nothing
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 2\n");
+        assert!(run(&dir, &script));
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 1\n");
+        let history = read_stack_history(dir.join("root/bak/foo.yaml/foo.yaml_swinstall_stack")).unwrap();
+        assert_eq!(history.current().unwrap().version.to_string(), "20181102-144204");
+        assert_eq!(history.entries.len(), 2);
+    }
+}
+
+test! {
+    restore_script_refuses_changed_stack {
+        let dir = scratch("changed");
+        let live = install_two_versions(&dir);
+        let script = restore_script(dir.join("root"), &at("20181103-000000"), ResolvePolicy::Current, &SwinstallLayout::default()).unwrap();
+
+        fs::write(dir.join("source"), "foo: 3\n").unwrap();
+        install(dir.join("source"), &live, &at("20181106-090000"), None).unwrap();
+        let stack = dir.join("root/bak/foo.yaml/foo.yaml_swinstall_stack");
+        let before = fs::read_to_string(&stack).unwrap();
+
+        assert!(!run(&dir, &script));
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 3\n");
+        assert_eq!(fs::read_to_string(&stack).unwrap(), before);
+    }
+}
+
+test! {
+    install_script_installs_file {
+        let dir = scratch("install");
+        let source = dir.join("source");
+        let live = dir.join("root/it's here.yaml");
+        fs::write(&source, "foo: 1\n").unwrap();
+        let script = install_script(&source, &live, &at("20181102-144204"), Some("575055"),
+                                    &SwinstallLayout::default(), &Ownership::default()).unwrap();
+        assert!(!live.exists());
+
+        assert!(run(&dir, &script));
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 1\n");
+        let bak = dir.join("root/bak/it's here.yaml");
+        assert_eq!(fs::read_to_string(bak.join("it's here.yaml.20181102-144204_r575055")).unwrap(), "foo: 1\n");
+        let history = read_stack_history(bak.join("it's here.yaml_swinstall_stack")).unwrap();
+        assert_eq!(history.current().unwrap().version.to_string(), "20181102-144204_r575055");
+
+        // the script cannot be applied twice
+        assert!(!run(&dir, &script));
+    }
+}
+
+test! {
+    undo_script_undoes_an_install {
+        let dir = scratch("undo");
+        let oplog = OpLog::new(dir.join("log"));
+        let source = dir.join("source");
+        let live = dir.join("root/foo.yaml");
+        fs::write(&source, "foo: 1\n").unwrap();
+        install(&source, &live, &at("20181102-144204"), None).unwrap();
+        fs::write(&source, "foo: 2\n").unwrap();
+        let mut operation = oplog.begin(OpKind::Install, &at("20181105-103813")).unwrap();
+        install_recorded(&source, &live, &at("20181105-103813"), None, &SwinstallLayout::default(),
+                         &InstallOptions::default(), &mut operation).unwrap();
+        operation.commit().unwrap();
+
+        let script = undo_script(&oplog, None).unwrap();
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 2\n");
+        assert!(run(&dir, &script));
+        assert_eq!(fs::read_to_string(&live).unwrap(), "foo: 1\n");
+        let history = read_stack_history(dir.join("root/bak/foo.yaml/foo.yaml_swinstall_stack")).unwrap();
+        assert_eq!(history.entries.len(), 1);
+        assert!(!dir.join("root/bak/foo.yaml/foo.yaml.20181105-103813").exists());
+        // the script records the operation as undone
+        assert!(oplog.operations().unwrap()[0].undone);
+    }
+}
+
+test! {
+    prune_script_removes_operations {
+        let dir = scratch("prune");
+        let oplog = OpLog::new(dir.join("log"));
+        for datetime in &["20181102-144204", "20181105-103813"] {
+            oplog.begin(OpKind::Install, &at(datetime)).unwrap().commit().unwrap();
+        }
+        let script = oplog.prune_script(Some(1), None).unwrap();
+        assert_eq!(oplog.operations().unwrap().len(), 2);
+        assert!(run(&dir, &script));
+        let remaining = oplog.operations().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].datetime, at("20181105-103813"));
+    }
+}
@@ -3,7 +3,8 @@ extern crate chrono;
 use self::chrono::{Local, NaiveDateTime};
 use std::str::FromStr;
 use std::path::{Path, PathBuf};
-use bakbuster::{get_file_version_on, FileVersion, BAKTIMEFMT, stack_history_from_path};
+use bakbuster::{get_file_version_on, FileVersion, BAKTIMEFMT, stack_history_from_path, live_path_for, ResolvePolicy, BBError};
+use bakbuster::canonical_stack_history_from_path;
 
 #[macro_use] mod common;
 
@@ -88,4 +89,37 @@ test! {
         PathBuf::from("./bak/foo_preference.yaml/foo_preference.yaml_swinstall_stack".to_string());
         assert_eq!(result, Ok(expect));
     }
-}
\ No newline at end of file
+}
+test! {
+    resolve_policy_from_str {
+        assert_eq!(ResolvePolicy::from_str("current"), Ok(ResolvePolicy::Current));
+        assert_eq!(ResolvePolicy::from_str("latest"), Ok(ResolvePolicy::Latest));
+        assert!(ResolvePolicy::from_str("newest").is_err());
+    }
+}
+
+test! {
+    live_path_for_stack_and_backup {
+        let expect = PathBuf::from("./foo_preference.yaml");
+        let stack = live_path_for("./bak/foo_preference.yaml/foo_preference.yaml_swinstall_stack");
+        assert_eq!(stack, Ok(expect.clone()));
+        let backup = live_path_for("./bak/foo_preference.yaml/foo_preference.yaml.20181105-103813");
+        assert_eq!(backup, Ok(expect));
+        assert!(live_path_for("./bak/foo_preference.yaml/bar.yaml.20181105-103813").is_err());
+    }
+}
+
+test! {
+    canonical_stack_history_from_path_expands_variables {
+        std::env::set_var("BAKBUSTER_TEST_EXAMPLES", concat!(env!("CARGO_MANIFEST_DIR"), "/examples"));
+        let expect = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples")
+                     .canonicalize().unwrap()
+                     .join("bak/packages.xml/packages.xml_swinstall_stack");
+        let result = canonical_stack_history_from_path("${BAKBUSTER_TEST_EXAMPLES}/./bak/../packages.xml");
+        assert_eq!(result, Ok(expect));
+        assert_eq!(
+            canonical_stack_history_from_path("$BAKBUSTER_TEST_NOT_SET/packages.xml"),
+            Err(BBError::EnvVarError("BAKBUSTER_TEST_NOT_SET".to_string()))
+        );
+    }
+}
@@ -0,0 +1,59 @@
+extern crate bakbuster;
+use std::str::FromStr;
+use bakbuster::FileVersion;
+use bakbuster::output::{render, OutputFormat, Record, FILE_VERSION_COLUMNS};
+use bakbuster::timezone::{parse_query_time, AmbiguityPolicy, LocalTime, Zone};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn new_york() -> Zone {
+    Zone::from_str("America/New_York").unwrap()
+}
+
+test! {
+    repeated_hour_is_ambiguous {
+        let input = "2018-11-04 01:30:00";
+        assert!(parse_query_time(input, new_york(), new_york(), AmbiguityPolicy::Reject).is_err());
+        let earliest = parse_query_time(input, new_york(), new_york(), AmbiguityPolicy::Earliest).unwrap();
+        let latest = parse_query_time(input, new_york(), new_york(), AmbiguityPolicy::Latest).unwrap();
+        assert_eq!((latest.instant - earliest.instant).num_hours(), 1);
+        assert_eq!(earliest.facility_local, latest.facility_local);
+        match earliest.facility_time {
+            LocalTime::Ambiguous(..) => {},
+            other => panic!("expected an ambiguous time, got {:?}", other),
+        }
+        // an explicit offset needs no policy
+        assert!(parse_query_time("2018-11-04 01:30:00 -05:00", new_york(), new_york(), AmbiguityPolicy::Reject).is_ok());
+    }
+}
+
+test! {
+    skipped_hour_is_rejected {
+        assert!(parse_query_time("2018-03-11 02:30:00", new_york(), new_york(), AmbiguityPolicy::Earliest).is_err());
+    }
+}
+
+test! {
+    query_zone_differs_from_facility {
+        let tokyo = Zone::from_str("Asia/Tokyo").unwrap();
+        let query = parse_query_time("2018-11-06 00:38:13", tokyo, new_york(), AmbiguityPolicy::Reject).unwrap();
+        assert_eq!(query.facility_local.to_string(), "2018-11-05 10:38:13");
+    }
+}
+
+test! {
+    records_include_utc_timestamp {
+        let records = vec![
+            Record::new().file_version_in(&FileVersion::from_str("20181105-103813").unwrap(), new_york()),
+            Record::new().file_version_in(&FileVersion::from_str("20181104-013000").unwrap(), new_york()),
+        ];
+        assert_eq!(
+            render(OutputFormat::Tsv, "log", FILE_VERSION_COLUMNS, &records),
+            "version\ttimestamp\trevision\ttimestamp_utc\n\
+             20181105-103813\t2018-11-05 10:38:13\t\t2018-11-05T15:38:13+00:00\n\
+             20181104-013000\t2018-11-04 01:30:00\t\t\n"
+        );
+    }
+}
@@ -0,0 +1,53 @@
+extern crate bakbuster;
+extern crate chrono;
+use self::chrono::NaiveDateTime;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use bakbuster::{open_as_of, BBError, BAKTIMEFMT};
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn packages_xml() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("packages.xml")
+}
+
+test! {
+    open_as_of_reads_backup {
+        let dt = NaiveDateTime::parse_from_str("20181103-000000", BAKTIMEFMT).unwrap();
+        let mut versioned = open_as_of(packages_xml(), dt).unwrap();
+        assert_eq!(versioned.file_version().to_string(), "20181102-144204");
+        assert!(versioned.backup_path().ends_with("bak/packages.xml/packages.xml.20181102-144204"));
+
+        let mut contents = String::new();
+        versioned.read_to_string(&mut contents).unwrap();
+        assert!(contents.contains(r#"<package name="foo" version="2.1" />"#));
+
+        versioned.seek(SeekFrom::Start(1)).unwrap();
+        let mut buf = [0u8; 8];
+        versioned.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"packages");
+    }
+}
+
+test! {
+    open_as_of_before_first_install {
+        let dt = NaiveDateTime::parse_from_str("20160101-000000", BAKTIMEFMT).unwrap();
+        match open_as_of(packages_xml(), dt) {
+            Err(BBError::NoVersionFound(_)) => {},
+            other => panic!("expected NoVersionFound, got {:?}", other),
+        }
+    }
+}
+
+test! {
+    open_as_of_without_a_stack {
+        let dt = NaiveDateTime::parse_from_str("20181103-000000", BAKTIMEFMT).unwrap();
+        let unswinstalled = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("unswinstalled.xml");
+        match open_as_of(unswinstalled, dt) {
+            Err(BBError::NoVersionFound(_)) => {},
+            other => panic!("expected NoVersionFound, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+extern crate bakbuster;
+use std::path::PathBuf;
+use bakbuster::SwinstallLayout;
+use bakbuster::whois::whois;
+
+#[macro_use] mod common;
+
+fn setup() {}
+
+fn bak_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("bak").join("packages.xml")
+}
+
+test! {
+    whois_backup {
+        let found = whois(bak_dir().join("packages.xml.20181105-103813"), &SwinstallLayout::default()).unwrap();
+        assert_eq!(found.target.live, PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples").join("packages.xml"));
+        assert_eq!(found.version.unwrap().to_string(), "20181105-103813");
+        assert_eq!(found.position, Some(2));
+        assert_eq!(found.entries, 4);
+        assert!(found.is_current);
+        assert!(!found.rolled_back);
+        // the example stack records the facility path, not this checkout
+        assert_eq!(found.recorded_path_matches, Some(false));
+    }
+}
+
+test! {
+    whois_stack {
+        let found = whois(bak_dir().join("packages.xml_swinstall_stack"), &SwinstallLayout::default()).unwrap();
+        assert_eq!(found.version, None);
+        assert_eq!(found.position, None);
+    }
+}